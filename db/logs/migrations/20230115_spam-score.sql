@@ -0,0 +1 @@
+ALTER TABLE messages ADD COLUMN spam_score REAL NOT NULL DEFAULT 0;
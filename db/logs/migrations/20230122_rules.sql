@@ -0,0 +1,24 @@
+CREATE TABLE player_tags(
+    player_id INTEGER NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (player_id, tag)
+);
+
+CREATE TABLE rules(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL,
+    dry_run BOOLEAN NOT NULL,
+    conditions TEXT NOT NULL,
+    actions TEXT NOT NULL
+);
+
+CREATE TABLE rule_firings(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    rule_id INTEGER NOT NULL,
+    message_id INTEGER NOT NULL,
+    player_id INTEGER NOT NULL,
+    time DATETIME NOT NULL,
+    action TEXT NOT NULL,
+    dry_run BOOLEAN NOT NULL
+);
@@ -0,0 +1,13 @@
+CREATE TABLE reports(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    reporter_id INTEGER NOT NULL,
+    reported_id INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    time DATETIME NOT NULL
+);
+
+CREATE TABLE report_messages(
+    report_id INTEGER NOT NULL,
+    message_id INTEGER NOT NULL,
+    PRIMARY KEY (report_id, message_id)
+);
@@ -0,0 +1,29 @@
+CREATE TABLE tickets(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    title TEXT NOT NULL,
+    status TEXT NOT NULL,
+    assignee TEXT,
+    report_id INTEGER,
+    created DATETIME NOT NULL,
+    updated DATETIME NOT NULL
+);
+
+CREATE TABLE ticket_players(
+    ticket_id INTEGER NOT NULL,
+    player_id INTEGER NOT NULL,
+    PRIMARY KEY (ticket_id, player_id)
+);
+
+CREATE TABLE ticket_messages(
+    ticket_id INTEGER NOT NULL,
+    message_id INTEGER NOT NULL,
+    PRIMARY KEY (ticket_id, message_id)
+);
+
+CREATE TABLE ticket_comments(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    ticket_id INTEGER NOT NULL,
+    author TEXT NOT NULL,
+    content TEXT NOT NULL,
+    time DATETIME NOT NULL
+);
@@ -0,0 +1,31 @@
+CREATE TABLE player_aliases(
+    player_id INTEGER NOT NULL,
+    alias TEXT NOT NULL,
+    first_seen DATETIME NOT NULL,
+    last_seen DATETIME NOT NULL,
+    PRIMARY KEY (player_id, alias)
+);
+
+INSERT INTO player_aliases (player_id, alias, first_seen, last_seen)
+SELECT players.id, players.alias, coalesce(min(activity.time), CURRENT_TIMESTAMP), coalesce(max(activity.time), CURRENT_TIMESTAMP)
+FROM players
+LEFT JOIN activity ON activity.player_id = players.id
+GROUP BY players.id;
+
+CREATE TABLE player_notes(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    player_id INTEGER NOT NULL,
+    author TEXT NOT NULL,
+    content TEXT NOT NULL,
+    time DATETIME NOT NULL
+);
+
+CREATE TABLE erasures(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    player_id INTEGER NOT NULL,
+    mode TEXT NOT NULL,
+    operator TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    affected_rows INTEGER NOT NULL,
+    time DATETIME NOT NULL
+);
@@ -0,0 +1,26 @@
+ALTER TABLE players ADD COLUMN legal_hold BOOLEAN NOT NULL DEFAULT 0;
+
+CREATE TABLE messages_archive(
+    id INTEGER PRIMARY KEY,
+    player_id INTEGER,
+    time DATETIME,
+    content TEXT NOT NULL,
+    ty TEXT NOT NULL,
+    spam_score REAL NOT NULL DEFAULT 0
+);
+
+CREATE TABLE activity_archive(
+    id INTEGER PRIMARY KEY,
+    player_id INTEGER,
+    time DATETIME,
+    online BOOLEAN
+);
+
+CREATE TABLE prune_runs(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    time DATETIME NOT NULL,
+    archived BOOLEAN NOT NULL,
+    messages INTEGER NOT NULL,
+    activity INTEGER NOT NULL,
+    details TEXT NOT NULL
+);
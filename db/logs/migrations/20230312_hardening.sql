@@ -0,0 +1,97 @@
+-- Rows that would violate the new constraints are moved out of the way
+-- instead of being dropped, the preflight in `run_migrations` reports them.
+CREATE TABLE quarantined_messages(
+    id INTEGER PRIMARY KEY,
+    player_id INTEGER,
+    time DATETIME,
+    content TEXT,
+    ty TEXT,
+    spam_score REAL,
+    reason TEXT NOT NULL
+);
+
+INSERT INTO quarantined_messages (id, player_id, time, content, ty, spam_score, reason)
+SELECT id, player_id, time, content, ty, spam_score,
+    CASE
+        WHEN player_id IS NULL OR player_id NOT IN (SELECT id FROM players) THEN 'unknown player'
+        WHEN time IS NULL THEN 'missing time'
+        ELSE 'unknown type'
+    END
+FROM messages
+WHERE player_id IS NULL
+    OR player_id NOT IN (SELECT id FROM players)
+    OR time IS NULL
+    OR ty NOT IN ('World', 'Tell', 'Faction');
+
+CREATE TABLE quarantined_activity(
+    id INTEGER PRIMARY KEY,
+    player_id INTEGER,
+    time DATETIME,
+    online BOOLEAN,
+    reason TEXT NOT NULL
+);
+
+INSERT INTO quarantined_activity (id, player_id, time, online, reason)
+SELECT id, player_id, time, online,
+    CASE
+        WHEN player_id IS NULL OR player_id NOT IN (SELECT id FROM players) THEN 'unknown player'
+        WHEN time IS NULL THEN 'missing time'
+        ELSE 'missing online'
+    END
+FROM activity
+WHERE player_id IS NULL
+    OR player_id NOT IN (SELECT id FROM players)
+    OR time IS NULL
+    OR online IS NULL;
+
+CREATE TABLE messages_new(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    player_id INTEGER NOT NULL REFERENCES players(id),
+    time DATETIME NOT NULL,
+    content TEXT NOT NULL,
+    ty TEXT NOT NULL CHECK (ty IN ('World', 'Tell', 'Faction')),
+    spam_score REAL NOT NULL DEFAULT 0
+);
+
+INSERT INTO messages_new (id, player_id, time, content, ty, spam_score)
+SELECT id, player_id, time, content, ty, spam_score
+FROM messages
+WHERE id NOT IN (SELECT id FROM quarantined_messages);
+
+-- Keep the sequence, ids of deleted, quarantined or archived rows are never
+-- handed out again.
+DELETE FROM sqlite_sequence WHERE name = 'messages_new';
+INSERT INTO sqlite_sequence (name, seq)
+SELECT 'messages_new', seq FROM sqlite_sequence WHERE name = 'messages';
+
+DROP TABLE messages;
+ALTER TABLE messages_new RENAME TO messages;
+
+CREATE TABLE activity_new(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    player_id INTEGER NOT NULL REFERENCES players(id),
+    time DATETIME NOT NULL,
+    online BOOLEAN NOT NULL
+);
+
+INSERT INTO activity_new (id, player_id, time, online)
+SELECT id, player_id, time, online
+FROM activity
+WHERE id NOT IN (SELECT id FROM quarantined_activity);
+
+DELETE FROM sqlite_sequence WHERE name = 'activity_new';
+INSERT INTO sqlite_sequence (name, seq)
+SELECT 'activity_new', seq FROM sqlite_sequence WHERE name = 'activity';
+
+DROP TABLE activity;
+ALTER TABLE activity_new RENAME TO activity;
+
+CREATE INDEX messages_player_id ON messages(player_id);
+CREATE INDEX messages_time ON messages(time);
+CREATE INDEX activity_player_id_time ON activity(player_id, time);
+CREATE INDEX players_alias ON players(alias COLLATE NOCASE);
+CREATE INDEX rule_firings_player_id ON rule_firings(player_id);
+CREATE INDEX reports_reported_id ON reports(reported_id);
+CREATE INDEX ticket_players_player_id ON ticket_players(player_id);
+CREATE INDEX report_messages_message_id ON report_messages(message_id);
+CREATE INDEX ticket_messages_message_id ON ticket_messages(message_id);
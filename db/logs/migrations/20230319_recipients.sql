@@ -0,0 +1,6 @@
+-- Receiver of tells, null for other message types and messages logged before
+-- recipients were recorded.
+ALTER TABLE messages ADD COLUMN recipient_id INTEGER REFERENCES players(id);
+ALTER TABLE messages_archive ADD COLUMN recipient_id INTEGER;
+
+CREATE INDEX messages_recipient_id ON messages(recipient_id);
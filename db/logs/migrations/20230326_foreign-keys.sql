@@ -0,0 +1,229 @@
+-- Moderation tables only referred to players, messages, reports, tickets and
+-- webhooks by id. They are rebuilt with foreign keys, parents before the tables
+-- referring to them, so no table is dropped while another references it.
+-- Rows referring to something that no longer exists are dropped, or unlinked
+-- for tickets of a deleted report, the preflight in `run_migrations` reports
+-- them. Every rebuilt table keeps its id sequence so ids aren't handed out
+-- again.
+
+CREATE TABLE reports_new(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    reporter_id INTEGER NOT NULL REFERENCES players(id) ON DELETE CASCADE,
+    reported_id INTEGER NOT NULL REFERENCES players(id) ON DELETE CASCADE,
+    reason TEXT NOT NULL,
+    time DATETIME NOT NULL
+);
+
+INSERT INTO reports_new (id, reporter_id, reported_id, reason, time)
+SELECT id, reporter_id, reported_id, reason, time
+FROM reports
+WHERE reporter_id IN (SELECT id FROM players)
+    AND reported_id IN (SELECT id FROM players);
+
+DELETE FROM sqlite_sequence WHERE name = 'reports_new';
+INSERT INTO sqlite_sequence (name, seq)
+SELECT 'reports_new', seq FROM sqlite_sequence WHERE name = 'reports';
+
+DROP TABLE reports;
+ALTER TABLE reports_new RENAME TO reports;
+
+CREATE TABLE tickets_new(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    title TEXT NOT NULL,
+    status TEXT NOT NULL,
+    assignee TEXT,
+    report_id INTEGER REFERENCES reports(id) ON DELETE SET NULL,
+    created DATETIME NOT NULL,
+    updated DATETIME NOT NULL
+);
+
+INSERT INTO tickets_new (id, title, status, assignee, report_id, created, updated)
+SELECT id, title, status, assignee,
+    CASE WHEN report_id IN (SELECT id FROM reports) THEN report_id END,
+    created, updated
+FROM tickets;
+
+DELETE FROM sqlite_sequence WHERE name = 'tickets_new';
+INSERT INTO sqlite_sequence (name, seq)
+SELECT 'tickets_new', seq FROM sqlite_sequence WHERE name = 'tickets';
+
+DROP TABLE tickets;
+ALTER TABLE tickets_new RENAME TO tickets;
+
+-- Firings keep the ids of archived messages, so `message_id` has no foreign
+-- key.
+CREATE TABLE rule_firings_new(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    rule_id INTEGER NOT NULL REFERENCES rules(id) ON DELETE CASCADE,
+    message_id INTEGER NOT NULL,
+    player_id INTEGER NOT NULL REFERENCES players(id) ON DELETE CASCADE,
+    time DATETIME NOT NULL,
+    action TEXT NOT NULL,
+    dry_run BOOLEAN NOT NULL
+);
+
+INSERT INTO rule_firings_new (id, rule_id, message_id, player_id, time, action, dry_run)
+SELECT id, rule_id, message_id, player_id, time, action, dry_run
+FROM rule_firings
+WHERE rule_id IN (SELECT id FROM rules)
+    AND player_id IN (SELECT id FROM players);
+
+DELETE FROM sqlite_sequence WHERE name = 'rule_firings_new';
+INSERT INTO sqlite_sequence (name, seq)
+SELECT 'rule_firings_new', seq FROM sqlite_sequence WHERE name = 'rule_firings';
+
+DROP TABLE rule_firings;
+ALTER TABLE rule_firings_new RENAME TO rule_firings;
+
+CREATE TABLE report_messages_new(
+    report_id INTEGER NOT NULL REFERENCES reports(id) ON DELETE CASCADE,
+    message_id INTEGER NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    PRIMARY KEY (report_id, message_id)
+);
+
+INSERT INTO report_messages_new (report_id, message_id)
+SELECT report_id, message_id
+FROM report_messages
+WHERE report_id IN (SELECT id FROM reports)
+    AND message_id IN (SELECT id FROM messages);
+
+DROP TABLE report_messages;
+ALTER TABLE report_messages_new RENAME TO report_messages;
+
+CREATE TABLE ticket_players_new(
+    ticket_id INTEGER NOT NULL REFERENCES tickets(id) ON DELETE CASCADE,
+    player_id INTEGER NOT NULL REFERENCES players(id) ON DELETE CASCADE,
+    PRIMARY KEY (ticket_id, player_id)
+);
+
+INSERT INTO ticket_players_new (ticket_id, player_id)
+SELECT ticket_id, player_id
+FROM ticket_players
+WHERE ticket_id IN (SELECT id FROM tickets)
+    AND player_id IN (SELECT id FROM players);
+
+DROP TABLE ticket_players;
+ALTER TABLE ticket_players_new RENAME TO ticket_players;
+
+CREATE TABLE ticket_messages_new(
+    ticket_id INTEGER NOT NULL REFERENCES tickets(id) ON DELETE CASCADE,
+    message_id INTEGER NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    PRIMARY KEY (ticket_id, message_id)
+);
+
+INSERT INTO ticket_messages_new (ticket_id, message_id)
+SELECT ticket_id, message_id
+FROM ticket_messages
+WHERE ticket_id IN (SELECT id FROM tickets)
+    AND message_id IN (SELECT id FROM messages);
+
+DROP TABLE ticket_messages;
+ALTER TABLE ticket_messages_new RENAME TO ticket_messages;
+
+CREATE TABLE ticket_comments_new(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    ticket_id INTEGER NOT NULL REFERENCES tickets(id) ON DELETE CASCADE,
+    author TEXT NOT NULL,
+    content TEXT NOT NULL,
+    time DATETIME NOT NULL
+);
+
+INSERT INTO ticket_comments_new (id, ticket_id, author, content, time)
+SELECT id, ticket_id, author, content, time
+FROM ticket_comments
+WHERE ticket_id IN (SELECT id FROM tickets);
+
+DELETE FROM sqlite_sequence WHERE name = 'ticket_comments_new';
+INSERT INTO sqlite_sequence (name, seq)
+SELECT 'ticket_comments_new', seq FROM sqlite_sequence WHERE name = 'ticket_comments';
+
+DROP TABLE ticket_comments;
+ALTER TABLE ticket_comments_new RENAME TO ticket_comments;
+
+CREATE TABLE player_tags_new(
+    player_id INTEGER NOT NULL REFERENCES players(id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (player_id, tag)
+);
+
+INSERT INTO player_tags_new (player_id, tag)
+SELECT player_id, tag
+FROM player_tags
+WHERE player_id IN (SELECT id FROM players);
+
+DROP TABLE player_tags;
+ALTER TABLE player_tags_new RENAME TO player_tags;
+
+CREATE TABLE player_aliases_new(
+    player_id INTEGER NOT NULL REFERENCES players(id) ON DELETE CASCADE,
+    alias TEXT NOT NULL,
+    first_seen DATETIME NOT NULL,
+    last_seen DATETIME NOT NULL,
+    PRIMARY KEY (player_id, alias)
+);
+
+INSERT INTO player_aliases_new (player_id, alias, first_seen, last_seen)
+SELECT player_id, alias, first_seen, last_seen
+FROM player_aliases
+WHERE player_id IN (SELECT id FROM players);
+
+DROP TABLE player_aliases;
+ALTER TABLE player_aliases_new RENAME TO player_aliases;
+
+CREATE TABLE player_notes_new(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    player_id INTEGER NOT NULL REFERENCES players(id) ON DELETE CASCADE,
+    author TEXT NOT NULL,
+    content TEXT NOT NULL,
+    time DATETIME NOT NULL
+);
+
+INSERT INTO player_notes_new (id, player_id, author, content, time)
+SELECT id, player_id, author, content, time
+FROM player_notes
+WHERE player_id IN (SELECT id FROM players);
+
+DELETE FROM sqlite_sequence WHERE name = 'player_notes_new';
+INSERT INTO sqlite_sequence (name, seq)
+SELECT 'player_notes_new', seq FROM sqlite_sequence WHERE name = 'player_notes';
+
+DROP TABLE player_notes;
+ALTER TABLE player_notes_new RENAME TO player_notes;
+
+CREATE TABLE webhook_deliveries_new(
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    webhook_id INTEGER NOT NULL REFERENCES webhooks(id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    status INTEGER,
+    error TEXT,
+    delivered BOOLEAN NOT NULL,
+    time DATETIME NOT NULL
+);
+
+INSERT INTO webhook_deliveries_new (id, webhook_id, event, payload, attempts, status, error, delivered, time)
+SELECT id, webhook_id, event, payload, attempts, status, error, delivered, time
+FROM webhook_deliveries
+WHERE webhook_id IN (SELECT id FROM webhooks);
+
+DELETE FROM sqlite_sequence WHERE name = 'webhook_deliveries_new';
+INSERT INTO sqlite_sequence (name, seq)
+SELECT 'webhook_deliveries_new', seq FROM sqlite_sequence WHERE name = 'webhook_deliveries';
+
+DROP TABLE webhook_deliveries;
+ALTER TABLE webhook_deliveries_new RENAME TO webhook_deliveries;
+
+-- Indexes of the rebuilt tables were dropped with them, the new ones cover
+-- the foreign keys cascading deletes look up.
+CREATE INDEX reports_reporter_id ON reports(reporter_id);
+CREATE INDEX reports_reported_id ON reports(reported_id);
+CREATE INDEX tickets_report_id ON tickets(report_id);
+CREATE INDEX rule_firings_rule_id ON rule_firings(rule_id);
+CREATE INDEX rule_firings_player_id ON rule_firings(player_id);
+CREATE INDEX report_messages_message_id ON report_messages(message_id);
+CREATE INDEX ticket_players_player_id ON ticket_players(player_id);
+CREATE INDEX ticket_messages_message_id ON ticket_messages(message_id);
+CREATE INDEX ticket_comments_ticket_id ON ticket_comments(ticket_id);
+CREATE INDEX player_notes_player_id ON player_notes(player_id);
+CREATE INDEX webhook_deliveries_webhook_id ON webhook_deliveries(webhook_id);
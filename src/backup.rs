@@ -0,0 +1,237 @@
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use rocket::{serde::json::Json, Route, State};
+use rusqlite::{DatabaseName, OpenFlags};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ApiResult;
+
+const PREFIX: &str = "db-";
+const EXTENSION: &str = "sqlite";
+
+/// Backup settings, read from the `backups` table in `Rocket.toml`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct BackupConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    pub dir: PathBuf,
+    /// Snapshots kept, older ones are removed after every backup.
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 6 * 3600,
+            dir: PathBuf::from("db/logs/backups"),
+            keep: 14,
+        }
+    }
+}
+
+/// Where backups are taken from and written to.
+pub struct Backups {
+    pub database: PathBuf,
+    pub config: BackupConfig,
+    /// Held for as long as the server runs.
+    pub lock: DatabaseLock,
+}
+
+/// A lock on the `.lock` file next to the database. Servers and the `migrate`
+/// and `import` commands share it while they run and [`restore`] takes it
+/// exclusively, so the database is never replaced underneath them. The OS releases it when the process exits.
+pub struct DatabaseLock {
+    _file: File,
+}
+
+impl DatabaseLock {
+    fn open(database: &Path, shared: bool) -> Result<Self, BackupError> {
+        let mut path = database.as_os_str().to_owned();
+        path.push(".lock");
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(path)?;
+        let locked = if shared {
+            file.try_lock_shared()
+        } else {
+            file.try_lock()
+        };
+        match locked {
+            Ok(()) => Ok(Self { _file: file }),
+            Err(TryLockError::WouldBlock) => Err(BackupError::InUse),
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    /// Taken by a server or command for as long as it uses `database`.
+    pub fn shared(database: &Path) -> Result<Self, BackupError> {
+        Self::open(database, true)
+    }
+}
+
+#[derive(Debug)]
+pub enum BackupError {
+    Sqlite(rusqlite::Error),
+    Io(std::io::Error),
+    /// `pragma integrity_check` didn't return `ok`.
+    Corrupt(String),
+    /// A server or command holds the [`DatabaseLock`].
+    InUse,
+}
+
+impl std::fmt::Display for BackupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackupError::Sqlite(e) => write!(f, "{e}"),
+            BackupError::Io(e) => write!(f, "{e}"),
+            BackupError::Corrupt(result) => write!(f, "integrity check failed: {result}"),
+            BackupError::InUse => write!(f, "the database is in use by a server or command"),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for BackupError {
+    fn from(e: rusqlite::Error) -> Self {
+        BackupError::Sqlite(e)
+    }
+}
+
+impl From<std::io::Error> for BackupError {
+    fn from(e: std::io::Error) -> Self {
+        BackupError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub name: String,
+    pub size: u64,
+    pub time: DateTime<Utc>,
+}
+
+/// Runs `pragma integrity_check` on a database file without modifying it.
+pub fn verify(path: &Path) -> Result<(), BackupError> {
+    let conn = rusqlite::Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let result = conn.query_row("pragma integrity_check;", [], |row| row.get::<_, String>(0))?;
+    if result == "ok" {
+        Ok(())
+    } else {
+        Err(BackupError::Corrupt(result))
+    }
+}
+
+/// Takes a consistent snapshot of `database` using SQLite's online backup,
+/// so it can run while the server keeps writing.
+pub fn backup(database: &Path, config: &BackupConfig) -> Result<Snapshot, BackupError> {
+    fs::create_dir_all(&config.dir)?;
+    let time = Utc::now();
+    // Microseconds keep snapshots taken within the same second apart.
+    let name = format!("{PREFIX}{}.{EXTENSION}", time.format("%Y%m%dT%H%M%S%.6fZ"));
+    let partial = config.dir.join(format!("{name}.partial"));
+
+    let source = rusqlite::Connection::open_with_flags(database, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    source.backup(DatabaseName::Main, &partial, None)?;
+    if let Err(e) = verify(&partial) {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+    let path = config.dir.join(&name);
+    fs::rename(&partial, &path)?;
+
+    rotate(config)?;
+
+    Ok(Snapshot {
+        name,
+        size: fs::metadata(&path)?.len(),
+        time,
+    })
+}
+
+/// Snapshots in `config.dir`, newest first.
+pub fn snapshots(config: &BackupConfig) -> Result<Vec<Snapshot>, BackupError> {
+    let mut snapshots = Vec::new();
+    if !config.dir.exists() {
+        return Ok(snapshots);
+    }
+    for entry in fs::read_dir(&config.dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !name.starts_with(PREFIX) || !name.ends_with(&format!(".{EXTENSION}")) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        snapshots.push(Snapshot {
+            name,
+            size: metadata.len(),
+            time: metadata.modified()?.into(),
+        });
+    }
+    // Names embed the time, so they sort chronologically.
+    snapshots.sort_by(|a, b| b.name.cmp(&a.name));
+    Ok(snapshots)
+}
+
+fn rotate(config: &BackupConfig) -> Result<(), BackupError> {
+    for snapshot in snapshots(config)?.iter().skip(config.keep.max(1)) {
+        fs::remove_file(config.dir.join(&snapshot.name))?;
+    }
+    Ok(())
+}
+
+/// Replaces `database` with the contents of `snapshot` after verifying it. The
+/// current database is kept next to it with a `.pre-restore` suffix. Fails
+/// with [`BackupError::InUse`] while a server is running.
+pub fn restore(database: &Path, snapshot: &Path) -> Result<(), BackupError> {
+    let _lock = DatabaseLock::open(database, false)?;
+    verify(snapshot)?;
+    if database.exists() {
+        let mut previous = database.as_os_str().to_owned();
+        previous.push(".pre-restore");
+        // Through the backup API so pages still in the WAL are included.
+        let current =
+            rusqlite::Connection::open_with_flags(database, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        current.backup(DatabaseName::Main, previous, None)?;
+    }
+    let mut conn = rusqlite::Connection::open(database)?;
+    conn.restore(DatabaseName::Main, snapshot, None::<fn(rusqlite::backup::Progress)>)?;
+    verify(database)
+}
+
+/// Takes a backup every `interval_secs` for as long as the server runs.
+pub async fn run(database: PathBuf, config: BackupConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(60)));
+    loop {
+        interval.tick().await;
+        let (database, config) = (database.clone(), config.clone());
+        match tokio::task::spawn_blocking(move || backup(&database, &config)).await {
+            Ok(Ok(snapshot)) => tracing::info!("Backed up database to {}", snapshot.name),
+            Ok(Err(e)) => tracing::error!("Database backup failed: {}", e),
+            Err(e) => tracing::error!("Database backup panicked: {}", e),
+        }
+    }
+}
+
+#[post("/backups")]
+async fn list_backups(backups: &State<Backups>) -> ApiResult<Json<Vec<Snapshot>>> {
+    Ok(Json(snapshots(&backups.config)?))
+}
+
+#[post("/backups/now")]
+async fn backup_now(backups: &State<Backups>) -> ApiResult<Json<Snapshot>> {
+    let (database, config) = (backups.database.clone(), backups.config.clone());
+    let snapshot = tokio::task::spawn_blocking(move || backup(&database, &config)).await??;
+    Ok(Json(snapshot))
+}
+
+pub fn api_routes() -> Vec<Route> {
+    routes![list_backups, backup_now]
+}
@@ -0,0 +1,301 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    process::ExitCode,
+};
+
+use rocket::{figment::Figment, form::Form, futures::StreamExt, serde::json};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    Pool, Sqlite,
+};
+
+use crate::{
+    backup::{self, BackupError, DatabaseLock},
+    database_path,
+    export::{self, ExportFormat, ExportRow},
+    health, ingest, preflight, store, MessageFilter, MessageType, PREFLIGHT_CHECKS,
+};
+
+pub const USAGE: &str = "\
+Usage: veloren-mod-panel [command]
+
+Commands:
+  serve                       Run the web interface and the bot (default)
+  web                         Run the web interface without the bot
+  collect                     Run the bot without the web interface
+  migrate                     Apply pending database migrations
+  check-db                    Check the database for problems
+  export <format> [filter]    Print messages as csv, json or ndjson
+  import <file>               Add the messages of an ndjson export
+  restore <snapshot>          Restore the database from a backup
+  bench-ingest [events]       Measure ingestion throughput
+  help                        Show this message";
+
+/// Messages written per transaction by `import`.
+const IMPORT_BATCH: usize = 500;
+
+fn db_error(e: sqlx::Error) -> String {
+    format!("Database error: {e}")
+}
+
+/// Opens the `logs` database, creating it only if `create` is set.
+async fn connect(figment: &Figment, create: bool) -> Result<Pool<Sqlite>, String> {
+    let path = database_path(figment).map_err(|e| format!("Invalid database config: {e}"))?;
+    SqlitePoolOptions::new()
+        .connect_with(
+            SqliteConnectOptions::new()
+                .filename(&path)
+                .create_if_missing(create),
+        )
+        .await
+        .map_err(|e| format!("Failed to open {}: {e}", path.display()))
+}
+
+/// Opens the `logs` database, refusing to work with an outdated schema.
+async fn connect_migrated(figment: &Figment) -> Result<Pool<Sqlite>, String> {
+    let pool = connect(figment, false).await?;
+    let pending = health::pending_migrations(&pool).await.map_err(db_error)?;
+    if !pending.is_empty() {
+        return Err(format!(
+            "{} migrations are pending, run `migrate` first",
+            pending.len()
+        ));
+    }
+    Ok(pool)
+}
+
+/// Shares the lock servers hold on the database, so `restore` can't replace
+/// it while a command writes to it.
+fn lock(figment: &Figment) -> Result<DatabaseLock, String> {
+    let path = database_path(figment).map_err(|e| format!("Invalid database config: {e}"))?;
+    DatabaseLock::shared(&path).map_err(|e| match e {
+        BackupError::InUse => format!("{} is being restored", path.display()),
+        e => format!("Failed to lock {}: {e}", path.display()),
+    })
+}
+
+/// Applies pending migrations to the logs database, `migrate`.
+async fn migrate() -> Result<(), String> {
+    let figment = rocket::Config::figment();
+    let _lock = lock(&figment)?;
+    let pool = connect(&figment, true).await?;
+    preflight(&pool).await;
+    sqlx::migrate!("db/logs/migrations")
+        .run(&pool)
+        .await
+        .map_err(|e| format!("Migration failed: {e}"))?;
+    println!("The database is up to date");
+    Ok(())
+}
+
+/// Checks the logs database for corruption, rows breaking its constraints and
+/// pending migrations, `check-db`.
+async fn check_db() -> Result<(), String> {
+    let figment = rocket::Config::figment();
+    let pool = connect(&figment, false).await?;
+    let mut problems = 0;
+
+    let integrity = sqlx::query_scalar::<_, String>("pragma integrity_check;")
+        .fetch_all(&pool)
+        .await
+        .map_err(db_error)?;
+    if integrity == ["ok"] {
+        println!("integrity: ok");
+    } else {
+        for line in integrity {
+            println!("integrity: {line}");
+            problems += 1;
+        }
+    }
+
+    let violations = sqlx::query("pragma foreign_key_check;")
+        .fetch_all(&pool)
+        .await
+        .map_err(db_error)?
+        .len();
+    println!("foreign key violations: {violations}");
+    problems += violations;
+
+    for check in PREFLIGHT_CHECKS {
+        let description = check.description;
+        match sqlx::query_scalar::<_, u32>(check.query)
+            .fetch_one(&pool)
+            .await
+        {
+            Ok(count) => {
+                println!("{description}: {count}");
+                problems += count as usize;
+            }
+            Err(e) => {
+                println!("{description}: {e}");
+                problems += 1;
+            }
+        }
+    }
+
+    match health::pending_migrations(&pool).await {
+        Ok(pending) => {
+            println!("pending migrations: {pending:?}");
+            problems += pending.len();
+        }
+        Err(e) => {
+            println!("pending migrations: {e}");
+            problems += 1;
+        }
+    }
+
+    match store::open(pool).ping().await {
+        Ok(()) => println!("storage: ok"),
+        Err(e) => {
+            println!("storage: {e}");
+            problems += 1;
+        }
+    }
+
+    if problems == 0 {
+        Ok(())
+    } else {
+        Err(format!("Found {problems} problems"))
+    }
+}
+
+/// Prints every message matching `filter`, a query string like
+/// `player_id=3&ty=World` taking the same fields as `/api/export`,
+/// `export <csv|json|ndjson> [filter]`.
+async fn export(format: Option<String>, filter: Option<String>) -> Result<(), String> {
+    let Some(format) = format else {
+        return Err("Usage: export <csv|json|ndjson> [filter]".to_string());
+    };
+    let format = format.parse::<ExportFormat>().map_err(|e| e.to_string())?;
+    let filter = match &filter {
+        Some(filter) => {
+            Form::<MessageFilter>::parse(filter).map_err(|e| format!("Invalid filter: {e}"))?
+        }
+        None => MessageFilter::default(),
+    };
+    let figment = rocket::Config::figment();
+    let store = store::open(connect_migrated(&figment).await?);
+
+    let io_error = |e: io::Error| format!("Failed to write the export: {e}");
+    let mut out = BufWriter::new(io::stdout());
+    out.write_all(format.start().as_bytes()).map_err(io_error)?;
+    let mut rows = Box::pin(export::rows(store, filter));
+    let mut first = true;
+    while let Some(row) = rows.next().await {
+        let line = match row {
+            Ok(row) => format
+                .row(&row, first)
+                .map_err(|e| format!("Failed to encode a message: {e}")),
+            Err(e) => Err(db_error(e)),
+        };
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                out.write_all(format.error().as_bytes()).map_err(io_error)?;
+                out.flush().map_err(io_error)?;
+                return Err(e);
+            }
+        };
+        out.write_all(line.as_bytes()).map_err(io_error)?;
+        first = false;
+    }
+    out.write_all(format.end().as_bytes()).map_err(io_error)?;
+    out.flush().map_err(io_error)
+}
+
+/// Adds the messages of an `ndjson` export, `import <file>`. Every row becomes
+/// a new message without a spam score or recipient, so this is meant for
+/// filling a fresh database, importing into one that has them already
+/// duplicates them.
+async fn import(path: Option<String>) -> Result<(), String> {
+    let Some(path) = path else {
+        return Err("Usage: import <file>".to_string());
+    };
+    let file = File::open(&path).map_err(|e| format!("Failed to open {path}: {e}"))?;
+    let figment = rocket::Config::figment();
+    let _lock = lock(&figment)?;
+    let store = store::open(connect_migrated(&figment).await?);
+
+    let mut writer = store.begin().await.map_err(db_error)?;
+    let mut imported = 0;
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("Failed to read {path}: {e}"))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let row = json::from_str::<ExportRow>(&line)
+            .map_err(|e| format!("Invalid message on line {}: {e}", n + 1))?;
+        let ty = row
+            .ty
+            .parse::<MessageType>()
+            .map_err(|e| format!("Invalid message on line {}: {e}", n + 1))?;
+
+        let player_id = writer
+            .upsert_player(&row.uuid, &row.alias)
+            .await
+            .map_err(db_error)?;
+        writer
+            .insert_message(player_id, row.time, &row.message, &ty, 0.0, None)
+            .await
+            .map_err(db_error)?;
+        imported += 1;
+        if imported % IMPORT_BATCH == 0 {
+            writer.commit().await.map_err(db_error)?;
+            writer = store.begin().await.map_err(db_error)?;
+        }
+    }
+    writer.commit().await.map_err(db_error)?;
+    println!("Imported {imported} messages");
+    Ok(())
+}
+
+/// Restores the database from a backup, `restore <snapshot>`.
+fn restore(snapshot: Option<String>) -> Result<(), String> {
+    let Some(snapshot) = snapshot else {
+        return Err("Usage: restore <snapshot>".to_string());
+    };
+    let database = database_path(&rocket::Config::figment())
+        .map_err(|e| format!("Invalid database config: {e}"))?;
+    backup::restore(&database, Path::new(&snapshot)).map_err(|e| format!("Restore failed: {e}"))?;
+    println!("Restored {} from {snapshot}", database.display());
+    Ok(())
+}
+
+/// Measures ingestion throughput, `bench-ingest [events]`.
+async fn bench(events: Option<String>) -> Result<(), String> {
+    let events = match events.as_deref().map(str::parse::<usize>) {
+        None => 10_000,
+        Some(Ok(events)) => events,
+        Some(Err(e)) => return Err(format!("Invalid number of events: {e}")),
+    };
+    ingest::bench(events)
+        .await
+        .map_err(|e| format!("Benchmark failed: {e}"))
+}
+
+/// Runs a maintenance command. These read the same configuration as the
+/// server but start neither the bot nor the web interface.
+pub async fn run(command: &str, mut args: impl Iterator<Item = String>) -> ExitCode {
+    let result = match command {
+        "migrate" => migrate().await,
+        "check-db" => check_db().await,
+        "export" => export(args.next(), args.next()).await,
+        "import" => import(args.next()).await,
+        "restore" => restore(args.next()),
+        "bench-ingest" => bench(args.next()).await,
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        _ => Err(format!("Unknown command '{command}'\n\n{USAGE}")),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::fmt::Display;
+
+use rocket::{
+    http::Status,
+    response::{self, Responder},
+    serde::json::{serde_json, Json},
+    Catcher, Request,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::backup::BackupError;
+
+/// Error returned by routes, rendered as a JSON [`ErrorBody`] with a matching
+/// status code.
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound(String),
+    BadRequest(String),
+    /// The request clashes with the current state, e.g. claiming a ticket
+    /// someone else already claimed.
+    Conflict(String),
+    Database(sqlx::Error),
+    Json(serde_json::Error),
+    Backup(BackupError),
+    Internal(String),
+}
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// Machine readable kind of the error, e.g. `not_found`.
+    #[schema(value_type = String)]
+    pub error: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn not_found(what: impl Display) -> Self {
+        ApiError::NotFound(format!("{what} not found"))
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::NotFound(_) | ApiError::Database(sqlx::Error::RowNotFound) => {
+                Status::NotFound
+            }
+            ApiError::BadRequest(_) => Status::BadRequest,
+            ApiError::Conflict(_) => Status::Conflict,
+            ApiError::Database(_)
+            | ApiError::Json(_)
+            | ApiError::Backup(_)
+            | ApiError::Internal(_) => Status::InternalServerError,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) | ApiError::Database(sqlx::Error::RowNotFound) => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Database(_) => "database",
+            ApiError::Json(_) => "serialization",
+            ApiError::Backup(_) => "backup",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::NotFound(message)
+            | ApiError::BadRequest(message)
+            | ApiError::Conflict(message)
+            | ApiError::Internal(message) => f.write_str(message),
+            ApiError::Database(e) => write!(f, "database error: {e}"),
+            ApiError::Json(e) => write!(f, "serialization error: {e}"),
+            ApiError::Backup(e) => write!(f, "backup error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Extended SQLite result code of a foreign key violation.
+const SQLITE_CONSTRAINT_FOREIGNKEY: &str = "787";
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        // Linking a ticket or report to an id that doesn't exist is the
+        // caller's mistake.
+        let code = e.as_database_error().and_then(|e| e.code());
+        if code.as_deref() == Some(SQLITE_CONSTRAINT_FOREIGNKEY) {
+            return ApiError::BadRequest(
+                "a referenced player, message, report or ticket does not exist".to_string(),
+            );
+        }
+        ApiError::Database(e)
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        ApiError::Json(e)
+    }
+}
+
+impl From<BackupError> for ApiError {
+    fn from(e: BackupError) -> Self {
+        ApiError::Backup(e)
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        ApiError::Internal(format!("io error: {e}"))
+    }
+}
+
+impl From<tokio::task::JoinError> for ApiError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        ApiError::Internal(format!("background task failed: {e}"))
+    }
+}
+
+/// Decodes the rows of a list, logging and skipping the ones that can't be
+/// decoded so one bad row doesn't fail the whole list.
+pub fn decode_rows<R, T>(what: &str, rows: Vec<R>, id: impl Fn(&R) -> u32) -> Vec<T>
+where
+    T: TryFrom<R>,
+    T::Error: Display,
+{
+    rows.into_iter()
+        .filter_map(|row| {
+            let row_id = id(&row);
+            T::try_from(row)
+                .map_err(|e| tracing::error!("Failed to decode {what} {row_id}: {e}"))
+                .ok()
+        })
+        .collect()
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let status = self.status();
+        // Server errors are logged in full, clients only get the kind so
+        // queries and paths don't leak.
+        let message = if status.code >= 500 {
+            tracing::error!("{} {}: {}", req.method(), req.uri(), self);
+            format!("{} error", self.kind())
+        } else {
+            self.to_string()
+        };
+        let body = ErrorBody {
+            error: self.kind(),
+            message,
+        };
+        (status, Json(body)).respond_to(req)
+    }
+}
+
+/// A stored value that doesn't match any variant of the enum it's parsed as,
+/// e.g. a message type written by a newer version.
+#[derive(Debug)]
+pub struct UnknownVariant {
+    pub kind: &'static str,
+    pub value: String,
+}
+
+impl UnknownVariant {
+    pub fn new(kind: &'static str, value: &str) -> Self {
+        Self {
+            kind,
+            value: value.to_string(),
+        }
+    }
+}
+
+impl Display for UnknownVariant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown {} '{}'", self.kind, self.value)
+    }
+}
+
+impl std::error::Error for UnknownVariant {}
+
+impl From<UnknownVariant> for sqlx::Error {
+    fn from(e: UnknownVariant) -> Self {
+        sqlx::Error::Decode(Box::new(e))
+    }
+}
+
+/// Renders errors Rocket raises itself under `/api`, like unmatched routes or
+/// malformed bodies, in the same shape as [`ApiError`].
+#[catch(default)]
+fn api_catcher(status: Status, req: &Request) -> (Status, Json<ErrorBody>) {
+    let error = match status.code {
+        404 => "not_found",
+        400..=499 => "bad_request",
+        _ => "internal",
+    };
+    let message = format!("{} {}: {}", req.method(), req.uri(), status.reason_lossy());
+    (status, Json(ErrorBody { error, message }))
+}
+
+pub fn api_catchers() -> Vec<Catcher> {
+    catchers![api_catcher]
+}
@@ -0,0 +1,269 @@
+use std::{convert::Infallible, fmt::Display, str::FromStr};
+
+use rocket::{
+    request::{self, FromRequest},
+    response::stream::{Event, EventStream},
+    Request, Route, Shutdown, State,
+};
+use serde::Deserialize;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+use crate::{
+    error::{ApiError, ApiResult},
+    messages::Direction,
+    metrics::Metrics,
+    rules::Watchlist,
+    store::Store,
+    MessageFilter, MessageType, NetworkEvent, MAX_PER_PAGE,
+};
+
+/// How far a client got in the event stream, the latest message and activity
+/// entry it has seen. Sent as one number with the message id in the upper
+/// half, so ids grow with every message and activity event.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EventId {
+    pub message: u32,
+    pub activity: u32,
+}
+
+impl EventId {
+    /// Moves past `event`, returns false if it was seen already.
+    pub fn advance(&mut self, event: &NetworkEvent) -> bool {
+        match event {
+            NetworkEvent::Message(message) => {
+                if message.id <= self.message {
+                    return false;
+                }
+                self.message = message.id;
+            }
+            NetworkEvent::Activity(activity) => {
+                if activity.id <= self.activity {
+                    return false;
+                }
+                self.activity = activity.id;
+            }
+            _ => {}
+        }
+        true
+    }
+}
+
+impl Display for EventId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            ((self.message as u64) << 32) | self.activity as u64
+        )
+    }
+}
+
+impl FromStr for EventId {
+    type Err = ApiError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let id = value
+            .parse::<u64>()
+            .map_err(|_| ApiError::BadRequest(format!("invalid event id '{value}'")))?;
+        Ok(EventId {
+            message: (id >> 32) as u32,
+            activity: id as u32,
+        })
+    }
+}
+
+/// The `Last-Event-ID` header browsers send when reconnecting to an event
+/// stream on their own.
+pub struct LastEventId(Option<EventId>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = Infallible;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let id = req
+            .headers()
+            .get_one("Last-Event-ID")
+            .and_then(|id| id.parse().ok());
+        request::Outcome::Success(LastEventId(id))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, FromFormField)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Message,
+    Activity,
+    Alert,
+    #[field(value = "rule_firing")]
+    RuleFiring,
+    Report,
+    Ticket,
+    Connection,
+}
+
+impl EventKind {
+    fn of(event: &NetworkEvent) -> Self {
+        match event {
+            NetworkEvent::Message(_) => EventKind::Message,
+            NetworkEvent::Activity(_) => EventKind::Activity,
+            NetworkEvent::Alert(_) => EventKind::Alert,
+            NetworkEvent::RuleFiring(_) => EventKind::RuleFiring,
+            NetworkEvent::Report(_) => EventKind::Report,
+            NetworkEvent::Ticket(_) => EventKind::Ticket,
+            NetworkEvent::Connection(_) => EventKind::Connection,
+        }
+    }
+}
+
+/// Narrows down the events a subscription receives, an empty list lets
+/// everything through.
+#[derive(Debug, Default, Deserialize, FromForm)]
+#[serde(default)]
+pub struct EventFilter {
+    /// Only events concerning these players.
+    player_id: Vec<u32>,
+    /// Only messages of these types, other kinds of events are unaffected.
+    ty: Vec<MessageType>,
+    kind: Vec<EventKind>,
+    /// Only events concerning players on the watchlist.
+    watchlist: bool,
+}
+
+/// Players an event is about, the sender and receiver of a message or both
+/// sides of a report.
+fn players(event: &NetworkEvent) -> [Option<u32>; 2] {
+    match event {
+        NetworkEvent::Message(message) => [Some(message.player_id), message.recipient_id],
+        NetworkEvent::Activity(activity) => [Some(activity.player_id), None],
+        NetworkEvent::Alert(alert) => [Some(alert.player_id), None],
+        NetworkEvent::RuleFiring(firing) => [Some(firing.player_id), None],
+        NetworkEvent::Report(report) => [Some(report.reporter_id), Some(report.reported_id)],
+        NetworkEvent::Ticket(_) | NetworkEvent::Connection(_) => [None, None],
+    }
+}
+
+impl EventFilter {
+    /// Events without a player, like tickets, don't match player or
+    /// watchlist filters.
+    pub async fn matches(&self, event: &NetworkEvent, watchlist: &Watchlist) -> bool {
+        if !self.kind.is_empty() && !self.kind.contains(&EventKind::of(event)) {
+            return false;
+        }
+        let wrong_type = match event {
+            NetworkEvent::Message(message) => !self.ty.is_empty() && !self.ty.contains(&message.ty),
+            _ => false,
+        };
+        if wrong_type {
+            return false;
+        }
+        let players = players(event);
+        if !self.player_id.is_empty()
+            && !players
+                .iter()
+                .flatten()
+                .any(|id| self.player_id.contains(id))
+        {
+            return false;
+        }
+        if self.watchlist {
+            let watchlist = watchlist.read().await;
+            return players.iter().flatten().any(|id| watchlist.contains(id));
+        }
+        true
+    }
+}
+
+/// The next page of messages and activity stored after `position`, messages
+/// first. Empty once caught up.
+pub async fn missed(store: &Store, position: EventId) -> sqlx::Result<Vec<NetworkEvent>> {
+    let messages = store
+        .list_messages(
+            &MessageFilter::default(),
+            Some(position.message),
+            Direction::Newer,
+            MAX_PER_PAGE,
+        )
+        .await?;
+    if !messages.is_empty() {
+        return Ok(messages.into_iter().map(NetworkEvent::Message).collect());
+    }
+    let activity = store
+        .activity_after(position.activity, MAX_PER_PAGE)
+        .await?;
+    Ok(activity.into_iter().map(NetworkEvent::Activity).collect())
+}
+
+/// Streams events matching `filter` as they happen. A client resuming after
+/// `last_event_id`, or the `Last-Event-ID` header, first gets the messages and
+/// activity it missed from the database. The same happens when a slow client
+/// falls behind.
+#[get("/events?<last_event_id>&<filter..>")]
+async fn events(
+    queue: &State<Receiver<NetworkEvent>>,
+    store: &State<Store>,
+    watchlist: &State<Watchlist>,
+    metrics: &State<Metrics>,
+    header: LastEventId,
+    last_event_id: Option<&str>,
+    filter: EventFilter,
+    mut end: Shutdown,
+) -> ApiResult<EventStream![]> {
+    let mut position = match last_event_id.map(str::parse).transpose()?.or(header.0) {
+        Some(position) => position,
+        None => {
+            let (message, activity) = store.latest_ids().await?;
+            EventId { message, activity }
+        }
+    };
+    // Subscribed before replaying, anything committed in the meantime is
+    // either replayed or received, and skipped if it's both.
+    let mut rx = queue.resubscribe();
+    let store = store.inner().clone();
+    let watchlist = watchlist.inner().clone();
+    let metrics = metrics.inner().clone();
+
+    Ok(EventStream! {
+        let mut replay = true;
+        loop {
+            while replay {
+                let events = match missed(&store, position).await {
+                    Ok(events) => events,
+                    Err(e) => {
+                        tracing::error!("Failed to replay events: {e}");
+                        replay = false;
+                        break;
+                    }
+                };
+                replay = !events.is_empty();
+                for event in events {
+                    if position.advance(&event) && filter.matches(&event, &watchlist).await {
+                        yield Event::json(&event).id(position.to_string());
+                    }
+                }
+            }
+
+            let msg = rocket::tokio::select! {
+                msg = rx.recv() => match msg {
+                    Ok(msg) => msg,
+                    Err(RecvError::Closed) => break,
+                    Err(RecvError::Lagged(n)) => {
+                        metrics.event_stream_lagged.inc();
+                        metrics.event_stream_skipped.inc_by(n);
+                        replay = true;
+                        continue;
+                    }
+                },
+                _ = &mut end => break,
+            };
+
+            if position.advance(&msg) && filter.matches(&msg, &watchlist).await {
+                yield Event::json(&msg).id(position.to_string());
+            }
+        }
+    })
+}
+
+pub fn api_routes() -> Vec<Route> {
+    routes![events]
+}
@@ -0,0 +1,196 @@
+use chrono::{DateTime, Utc};
+use rocket::{
+    futures::{stream, Stream, StreamExt, TryStreamExt},
+    http::{ContentType, Header},
+    response::stream::TextStream,
+    serde::json,
+    Route, State,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+use crate::{error::UnknownVariant, store::Store, MessageFilter};
+
+/// Messages fetched from the store at a time.
+const EXPORT_PAGE: u32 = 1000;
+
+#[derive(Clone, Copy, Debug, FromFormField)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Ndjson,
+}
+
+impl std::str::FromStr for ExportFormat {
+    type Err = UnknownVariant;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            "ndjson" => Ok(ExportFormat::Ndjson),
+            _ => Err(UnknownVariant::new("export format", value)),
+        }
+    }
+}
+
+impl ExportFormat {
+    /// What comes before the first row.
+    pub fn start(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "id,player_id,alias,uuid,time,ty,message\n",
+            ExportFormat::Json => "[",
+            ExportFormat::Ndjson => "",
+        }
+    }
+
+    /// One message, `first` is set for the first so JSON can separate them.
+    pub fn row(self, row: &ExportRow, first: bool) -> Result<String, json::serde_json::Error> {
+        Ok(match self {
+            ExportFormat::Csv => row.to_csv(),
+            ExportFormat::Json => {
+                let separator = if first { "" } else { "," };
+                format!("{separator}{}", json::to_string(row)?)
+            }
+            ExportFormat::Ndjson => format!("{}\n", json::to_string(row)?),
+        })
+    }
+
+    /// What comes after the last row.
+    pub fn end(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "]",
+            ExportFormat::Csv | ExportFormat::Ndjson => "",
+        }
+    }
+
+    /// What comes instead of [`ExportFormat::end`] when the export fails
+    /// part way, so a truncated file can't pass for a complete one. JSON is
+    /// left without its closing bracket.
+    pub fn error(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "# export failed, this file is incomplete\n",
+            ExportFormat::Json => "",
+            ExportFormat::Ndjson => "{\"error\":\"export failed, this file is incomplete\"}\n",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, FromRow)]
+pub struct ExportRow {
+    pub id: u32,
+    pub player_id: u32,
+    pub alias: String,
+    pub uuid: String,
+    pub time: DateTime<Utc>,
+    pub message: String,
+    pub ty: String,
+}
+
+/// A download, served as an attachment.
+#[derive(Responder)]
+pub struct Export<T> {
+    pub inner: T,
+    pub content_type: ContentType,
+    pub disposition: Header<'static>,
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl ExportRow {
+    fn to_csv(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{}\n",
+            self.id,
+            self.player_id,
+            csv_field(&self.alias),
+            self.uuid,
+            self.time.to_rfc3339(),
+            self.ty,
+            csv_field(&self.message),
+        )
+    }
+}
+
+/// Every message matching `filter`, oldest first, fetched a page at a time so
+/// they are never all held in memory.
+pub fn rows(
+    store: Store,
+    filter: MessageFilter,
+) -> impl Stream<Item = sqlx::Result<ExportRow>> + Send {
+    let start = (store, filter, Some(0));
+    stream::try_unfold(start, |(store, filter, from)| async move {
+        let Some(from) = from else {
+            return Ok(None);
+        };
+        let rows = store.export_messages(&filter, from, EXPORT_PAGE).await?;
+        // A short page is the last one.
+        let next = rows
+            .last()
+            .map(|row| row.id)
+            .filter(|_| rows.len() == EXPORT_PAGE as usize);
+        let rows = stream::iter(rows.into_iter().map(Ok));
+        Ok(Some((rows, (store, filter, next))))
+    })
+    .try_flatten()
+}
+
+/// Streams every message matching the filter, oldest first, without holding
+/// them all in memory.
+#[get("/export?<format>&<filter..>")]
+async fn export(
+    store: &State<Store>,
+    format: ExportFormat,
+    filter: MessageFilter,
+) -> Export<TextStream![String]> {
+    let store = store.inner().clone();
+
+    let (content_type, extension) = match format {
+        ExportFormat::Csv => (ContentType::CSV, "csv"),
+        ExportFormat::Json => (ContentType::JSON, "json"),
+        ExportFormat::Ndjson => (ContentType::new("application", "x-ndjson"), "ndjson"),
+    };
+
+    let stream = TextStream! {
+        yield format.start().to_string();
+
+        let mut rows = Box::pin(rows(store, filter));
+        let mut first = true;
+        while let Some(row) = rows.next().await {
+            let line = match row {
+                Ok(row) => format.row(&row, first).map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match line {
+                Ok(line) => yield line,
+                Err(e) => {
+                    tracing::error!("Export failed: {}", e);
+                    yield format.error().to_string();
+                    return;
+                }
+            }
+            first = false;
+        }
+
+        yield format.end().to_string();
+    };
+
+    Export {
+        inner: stream,
+        content_type,
+        disposition: Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"messages.{extension}\""),
+        ),
+    }
+}
+
+pub fn api_routes() -> Vec<Route> {
+    routes![export]
+}
@@ -0,0 +1,178 @@
+use chrono::Utc;
+use rocket::{http::Status, serde::json::Json, Route, State};
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite};
+
+use crate::{
+    store::Store,
+    veloren::{BotState, BotStatus},
+    Db,
+};
+
+/// Health check settings, read from the `health` table in `Rocket.toml`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// Seconds without a client tick after which the connected bot counts as
+    /// stalled.
+    pub stall_secs: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self { stall_secs: 60 }
+    }
+}
+
+#[derive(Serialize)]
+struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Check {
+    fn new<E: ToString>(result: Result<(), E>) -> Self {
+        match result {
+            Ok(()) => Check {
+                ok: true,
+                error: None,
+            },
+            Err(e) => Check {
+                ok: false,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MigrationCheck {
+    ok: bool,
+    /// Versions of migrations that haven't been applied.
+    pending: Vec<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BotCheck {
+    ok: bool,
+    connected: bool,
+    /// The bot is connected but hasn't ticked in `stall_secs`.
+    stalled: bool,
+    /// Seconds since the last client tick.
+    last_tick_age_secs: Option<i64>,
+    /// Seconds since the game server last sent the bot anything.
+    last_event_age_secs: Option<i64>,
+}
+
+impl BotCheck {
+    fn new(status: &BotStatus, config: &HealthConfig) -> Self {
+        let now = Utc::now();
+        let last_tick_age_secs = status.last_tick.map(|time| (now - time).num_seconds());
+        let last_event_age_secs = status.last_event.map(|time| (now - time).num_seconds());
+        let stalled = status.connected
+            && matches!(last_tick_age_secs, Some(age) if age > config.stall_secs as i64);
+        BotCheck {
+            ok: status.connected && !stalled,
+            connected: status.connected,
+            stalled,
+            last_tick_age_secs,
+            last_event_age_secs,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: &'static str,
+    /// The logs database.
+    database: Check,
+    migrations: MigrationCheck,
+    /// The chat log storage backend.
+    storage: Check,
+    bot: BotCheck,
+}
+
+async fn ping(pool: &Pool<Sqlite>) -> sqlx::Result<()> {
+    sqlx::query("select 1;").execute(pool).await?;
+    Ok(())
+}
+
+/// Versions of the logs database migrations that haven't been applied.
+pub async fn pending_migrations(pool: &Pool<Sqlite>) -> sqlx::Result<Vec<i64>> {
+    let applied =
+        sqlx::query_scalar::<_, i64>("select version from _sqlx_migrations where success = 1;")
+            .fetch_all(pool)
+            .await?;
+    Ok(sqlx::migrate!("db/logs/migrations")
+        .iter()
+        .map(|migration| migration.version)
+        .filter(|version| !applied.contains(version))
+        .collect())
+}
+
+async fn health(db: &Db, store: &Store, bot: &BotState, config: &HealthConfig) -> Health {
+    let migrations = match pending_migrations(db).await {
+        Ok(pending) => MigrationCheck {
+            ok: pending.is_empty(),
+            pending,
+            error: None,
+        },
+        Err(e) => MigrationCheck {
+            ok: false,
+            pending: Vec::new(),
+            error: Some(e.to_string()),
+        },
+    };
+    Health {
+        status: "ok",
+        database: Check::new(ping(db).await),
+        migrations,
+        storage: Check::new(store.ping().await),
+        bot: BotCheck::new(&*bot.read().await, config),
+    }
+}
+
+fn respond(mut health: Health, ok: bool) -> (Status, Json<Health>) {
+    if ok {
+        (Status::Ok, Json(health))
+    } else {
+        health.status = "unavailable";
+        (Status::ServiceUnavailable, Json(health))
+    }
+}
+
+/// Whether the panel is alive, fails only once the bot loop has stalled so a
+/// supervisor can restart it.
+#[get("/healthz")]
+async fn healthz(
+    db: &Db,
+    store: &State<Store>,
+    bot: &State<BotState>,
+    config: &State<HealthConfig>,
+) -> (Status, Json<Health>) {
+    let health = health(db, store, bot, config).await;
+    let ok = !health.bot.stalled;
+    respond(health, ok)
+}
+
+/// Whether the panel can serve requests and is recording chat, fails while
+/// the database is unreachable, migrations are pending or the bot is offline
+/// or stalled.
+#[get("/readyz")]
+async fn readyz(
+    db: &Db,
+    store: &State<Store>,
+    bot: &State<BotState>,
+    config: &State<HealthConfig>,
+) -> (Status, Json<Health>) {
+    let health = health(db, store, bot, config).await;
+    let ok = health.database.ok && health.migrations.ok && health.storage.ok && health.bot.ok;
+    respond(health, ok)
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![healthz, readyz]
+}
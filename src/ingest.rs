@@ -0,0 +1,610 @@
+use std::{sync::Arc, time::Instant};
+
+use chrono::{DateTime, Duration, Utc};
+use futures::executor::block_on;
+use serde::Deserialize;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use tokio::{
+    sync::{
+        broadcast::Sender,
+        mpsc::{self, Receiver},
+    },
+    time::timeout,
+};
+use tracing::Instrument;
+use veloren_common::uuid::Uuid;
+
+use crate::{
+    metrics::Metrics,
+    rules::{Effect, RateTracker, RuleInput, RuleSet, StagedRates},
+    spam::{SpamConfig, SpamDetector, SpamReport, StagedWindows},
+    store::{ChatWriter, SqliteStore, Store},
+    tickets::{TicketEvent, TicketEventKind},
+    veloren::BotCommand,
+    Activity, Alert, AlertKind, Message, MessageType, NetworkEvent, PlayerList, VelorenEvent,
+    VelorenEventKind,
+};
+
+/// Ingestion settings, read from the `ingestion` table in `Rocket.toml`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct IngestConfig {
+    /// Most events written in a single transaction.
+    pub batch_size: usize,
+}
+
+impl Default for IngestConfig {
+    fn default() -> Self {
+        Self { batch_size: 256 }
+    }
+}
+
+/// State the ingestion task keeps between events.
+pub struct Ingestion {
+    pub store: Store,
+    pub player_list: PlayerList,
+    pub spam: SpamDetector,
+    pub rules: RuleSet,
+    pub rates: RateTracker,
+    pub bot: mpsc::Sender<BotCommand>,
+    pub sx: Sender<NetworkEvent>,
+    pub metrics: Metrics,
+}
+
+enum WrittenKind {
+    Message(Message, SpamReport),
+    Activity(u32, bool),
+    Report {
+        reported_alias: String,
+        reported_uuid: Option<Uuid>,
+        reason: String,
+    },
+}
+
+/// An event whose chat log rows are written.
+struct Written {
+    player_id: u32,
+    alias: String,
+    time: DateTime<Utc>,
+    kind: WrittenKind,
+}
+
+/// The spam windows and message rates of the players a transaction touched,
+/// only kept once it commits.
+#[derive(Default)]
+struct Staged {
+    spam: StagedWindows,
+    rates: StagedRates,
+}
+
+/// What a batch results in once everything is committed.
+#[derive(Default)]
+struct Outcome {
+    events: Vec<NetworkEvent>,
+    commands: Vec<BotCommand>,
+    /// Players that came online or went offline.
+    presence: Vec<(u32, bool)>,
+}
+
+impl Outcome {
+    fn extend(&mut self, other: Outcome) {
+        self.events.extend(other.events);
+        self.commands.extend(other.commands);
+        self.presence.extend(other.presence);
+    }
+}
+
+impl Ingestion {
+    /// Writes a batch and broadcasts its events once committed. When the batch
+    /// fails its events are retried one by one, so only the failing ones are
+    /// dropped.
+    pub async fn ingest(&mut self, batch: Vec<VelorenEvent>) {
+        let len = batch.len();
+        let span = tracing::info_span!("ingest_batch", len);
+        async {
+            let outcome = match self.ingest_events(&batch).await {
+                Ok(outcome) => outcome,
+                Err(e) if len > 1 => {
+                    tracing::warn!(error = %e, "Failed to ingest a batch, retrying its events one by one");
+                    let mut outcome = Outcome::default();
+                    for event in &batch {
+                        match self.ingest_events(std::slice::from_ref(event)).await {
+                            Ok(event_outcome) => outcome.extend(event_outcome),
+                            Err(e) => tracing::error!(
+                                error = %e,
+                                player = %event.player_uuid,
+                                time = %event.time,
+                                "Dropped an event that failed to ingest"
+                            ),
+                        }
+                    }
+                    outcome
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, "Dropped an event that failed to ingest");
+                    return;
+                }
+            };
+
+            {
+                let mut player_list = self.player_list.write().await;
+                for (player_id, online) in outcome.presence {
+                    if online {
+                        player_list.insert(player_id);
+                    } else {
+                        player_list.remove(&player_id);
+                    }
+                }
+            }
+            for event in outcome.events {
+                let message_id = match &event {
+                    NetworkEvent::Message(message) => {
+                        self.metrics.message_ingested(&message.ty);
+                        Some(message.id)
+                    }
+                    _ => None,
+                };
+                let receivers = self.sx.send(event).unwrap_or(0);
+                if let Some(message_id) = message_id {
+                    tracing::debug!(message_id, receivers, "Broadcast message");
+                }
+            }
+            for command in outcome.commands {
+                let _ = self.bot.send(command).await;
+            }
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Writes `events` to the chat log, records aliases, evaluates rules and
+    /// files reports, all in one transaction. The spam windows and message
+    /// rates are only updated once it commits.
+    async fn ingest_events(&mut self, events: &[VelorenEvent]) -> sqlx::Result<Outcome> {
+        let mut staged = Staged::default();
+        let mut writer = self.store.begin().await?;
+        let mut outcome = Outcome::default();
+        for event in events {
+            let kind = match event.kind {
+                VelorenEventKind::Message { .. } => "message",
+                VelorenEventKind::Activity { .. } => "activity",
+                VelorenEventKind::Report { .. } => "report",
+            };
+            let span = tracing::debug_span!("ingest_event", kind, player = %event.player_uuid);
+            async {
+                let written = self
+                    .write_event(&mut staged, writer.as_mut(), event.clone())
+                    .await?;
+                self.apply(&mut staged, writer.as_mut(), written, &mut outcome)
+                    .await
+            }
+            .instrument(span)
+            .await?;
+        }
+        writer.commit().await?;
+        self.spam.commit(staged.spam);
+        self.rates.commit(staged.rates);
+        Ok(outcome)
+    }
+
+    async fn write_event(
+        &self,
+        staged: &mut Staged,
+        writer: &mut dyn ChatWriter,
+        event: VelorenEvent,
+    ) -> sqlx::Result<Written> {
+        let player_id = writer
+            .upsert_player(&event.player_uuid.to_string(), &event.player_alias)
+            .await?;
+        let kind = match event.kind {
+            VelorenEventKind::Message {
+                message,
+                ty,
+                recipient,
+            } => {
+                let recipient_id = match recipient {
+                    Some((alias, uuid)) => {
+                        Some(writer.upsert_player(&uuid.to_string(), &alias).await?)
+                    }
+                    None => None,
+                };
+                let report = self
+                    .spam
+                    .analyze(&mut staged.spam, player_id, event.time, &message);
+                let id = writer
+                    .insert_message(
+                        player_id,
+                        event.time,
+                        &message,
+                        &ty,
+                        report.score,
+                        recipient_id,
+                    )
+                    .await?;
+                tracing::debug!(message_id = id, player_id, ty = %ty, "Wrote message");
+                let message = Message {
+                    id,
+                    player_id,
+                    message,
+                    ty,
+                    time: event.time,
+                    spam_score: report.score,
+                    recipient_id,
+                };
+                WrittenKind::Message(message, report)
+            }
+            VelorenEventKind::Activity { online } => {
+                let id = writer
+                    .insert_activity(player_id, event.time, online)
+                    .await?;
+                tracing::debug!(activity_id = id, player_id, online, "Wrote activity");
+                WrittenKind::Activity(id, online)
+            }
+            VelorenEventKind::Report {
+                reported_alias,
+                reported_uuid,
+                reason,
+            } => WrittenKind::Report {
+                reported_alias,
+                reported_uuid,
+                reason,
+            },
+        };
+        Ok(Written {
+            player_id,
+            alias: event.player_alias,
+            time: event.time,
+            kind,
+        })
+    }
+
+    /// Records the alias, evaluates rules and files the report of a written
+    /// event.
+    async fn apply(
+        &self,
+        staged: &mut Staged,
+        writer: &mut dyn ChatWriter,
+        w: Written,
+        outcome: &mut Outcome,
+    ) -> sqlx::Result<()> {
+        writer.record_alias(w.player_id, &w.alias, w.time).await?;
+        match w.kind {
+            WrittenKind::Message(message, report) => {
+                outcome.events.push(NetworkEvent::Message(message.clone()));
+                if self.spam.is_alert(&report) {
+                    outcome.events.push(NetworkEvent::Alert(Alert {
+                        player_id: w.player_id,
+                        message_id: Some(message.id),
+                        time: w.time,
+                        kind: AlertKind::Spam {
+                            score: report.score,
+                            reasons: report.reasons,
+                        },
+                    }));
+                }
+                self.apply_rules(staged, writer, &message, &w.alias, outcome)
+                    .await?;
+            }
+            WrittenKind::Activity(id, online) => {
+                if !online {
+                    staged.spam.forget(w.player_id);
+                    staged.rates.forget(w.player_id);
+                }
+                outcome.presence.push((w.player_id, online));
+                outcome.events.push(NetworkEvent::Activity(Activity {
+                    id,
+                    player_id: w.player_id,
+                    online,
+                }));
+            }
+            WrittenKind::Report {
+                reported_alias,
+                reported_uuid,
+                reason,
+            } => {
+                let reported = writer.find_reported(&reported_alias, reported_uuid).await?;
+                let reply = match reported {
+                    Some(reported_id) => {
+                        let report = writer
+                            .file_report(w.player_id, reported_id, &reason, w.time)
+                            .await?;
+                        let reply = format!(
+                            "Thanks, your report #{} against {} has been filed.",
+                            report.id, reported_alias
+                        );
+                        let ticket = writer.ticket_for_report(&report).await?;
+                        outcome.events.push(NetworkEvent::Report(report));
+                        outcome.events.push(NetworkEvent::Ticket(TicketEvent {
+                            kind: TicketEventKind::Created,
+                            ticket,
+                        }));
+                        reply
+                    }
+                    None => format!("Couldn't find a player called {reported_alias}."),
+                };
+                outcome.commands.push(BotCommand::Tell {
+                    alias: w.alias,
+                    message: reply,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    async fn apply_rules(
+        &self,
+        staged: &mut Staged,
+        writer: &mut dyn ChatWriter,
+        message: &Message,
+        player_alias: &str,
+        outcome: &mut Outcome,
+    ) -> sqlx::Result<()> {
+        let rule_set = self.rules.read().await;
+        let window = rule_set.iter().map(|r| r.rate_window()).max().unwrap_or(0);
+        let recent = self
+            .rates
+            .record(&mut staged.rates, message.player_id, message.time, window);
+        if rule_set.is_empty() {
+            return Ok(());
+        }
+
+        let (tags, first_seen) = writer.player_context(message.player_id).await?;
+        let input = RuleInput {
+            time: message.time,
+            message: &message.message,
+            ty: &message.ty,
+            spam_score: message.spam_score,
+            tags: &tags,
+            account_age: first_seen.map_or(Duration::zero(), |t| message.time - t),
+            recent,
+        };
+
+        for rule in rule_set.iter().filter(|r| r.matches(&input)) {
+            let Some(rule_id) = rule.id else {
+                continue;
+            };
+            for action in &rule.actions {
+                let firing = writer
+                    .log_firing(
+                        rule_id,
+                        message.id,
+                        message.player_id,
+                        message.time,
+                        action,
+                        rule.dry_run,
+                    )
+                    .await?;
+                outcome.events.push(NetworkEvent::RuleFiring(firing));
+            }
+            for effect in rule.effects(player_alias) {
+                match effect {
+                    Effect::Alert => {
+                        outcome.events.push(NetworkEvent::Alert(Alert {
+                            player_id: message.player_id,
+                            message_id: Some(message.id),
+                            time: message.time,
+                            kind: AlertKind::Rule {
+                                rule_id,
+                                name: rule.name.clone(),
+                            },
+                        }));
+                    }
+                    Effect::Command(command) => outcome.commands.push(command),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Waits for an event and takes whatever else is already queued, up to
+/// `max` events.
+async fn next_batch(rx: &mut Receiver<VelorenEvent>, max: usize) -> Option<Vec<VelorenEvent>> {
+    let mut batch = vec![rx.recv().await?];
+    while batch.len() < max.max(1) {
+        match rx.try_recv() {
+            Ok(event) => batch.push(event),
+            Err(_) => break,
+        }
+    }
+    Some(batch)
+}
+
+/// Writes queued events when dropped, so nothing received is lost on
+/// shutdown.
+pub struct DbDrop {
+    pub rx: Receiver<VelorenEvent>,
+    pub state: Ingestion,
+    pub config: IngestConfig,
+}
+
+impl Drop for DbDrop {
+    fn drop(&mut self) {
+        block_on(async {
+            let mut batch = Vec::new();
+            while let Ok(Some(msg)) =
+                timeout(std::time::Duration::from_millis(100), self.rx.recv()).await
+            {
+                batch.push(msg);
+                if batch.len() >= self.config.batch_size {
+                    self.state.ingest(std::mem::take(&mut batch)).await;
+                }
+            }
+            if !batch.is_empty() {
+                self.state.ingest(batch).await;
+            }
+        });
+    }
+}
+
+pub async fn run(mut db: DbDrop) {
+    while let Some(batch) = next_batch(&mut db.rx, db.config.batch_size).await {
+        db.state.ingest(batch).await;
+    }
+    tracing::info!("Ingestion stopped, no more events");
+}
+
+/// Pushes `events` synthetic events through ingestion into a scratch database
+/// with a few batch sizes and prints the throughput of each.
+pub async fn bench(events: usize) -> Result<(), sqlx::Error> {
+    const PLAYERS: u128 = 100;
+
+    for batch_size in [1, 16, 256] {
+        let path =
+            std::env::temp_dir().join(format!("veloren-mod-panel-bench-{batch_size}.sqlite"));
+        let _ = std::fs::remove_file(&path);
+        let pool = SqlitePoolOptions::new()
+            .connect_with(
+                SqliteConnectOptions::new()
+                    .filename(&path)
+                    .create_if_missing(true),
+            )
+            .await?;
+        sqlx::migrate!("db/logs/migrations").run(&pool).await?;
+
+        let (sx_db, rx_db) = mpsc::channel(events.max(1));
+        let (sx_bot, _rx_bot) = mpsc::channel(64);
+        let (sx, _rx) = tokio::sync::broadcast::channel(256);
+        for i in 0..events {
+            let player = i as u128 % PLAYERS;
+            // Every 50th event is a player coming online.
+            let kind = if i % 50 == 0 {
+                VelorenEventKind::Activity { online: true }
+            } else {
+                VelorenEventKind::Message {
+                    message: format!("synthetic message {i} from player {player}"),
+                    ty: MessageType::World,
+                    recipient: None,
+                }
+            };
+            let _ = sx_db
+                .send(VelorenEvent {
+                    player_alias: format!("player{player}"),
+                    player_uuid: Uuid::from_u128(player),
+                    time: Utc::now(),
+                    kind,
+                })
+                .await;
+        }
+        let metrics = Metrics::new(PlayerList::default(), sx_db.downgrade());
+        drop(sx_db);
+
+        let db = DbDrop {
+            rx: rx_db,
+            state: Ingestion {
+                store: Arc::new(SqliteStore::new(pool.clone())),
+                player_list: PlayerList::default(),
+                spam: SpamDetector::new(SpamConfig::default()),
+                rules: RuleSet::default(),
+                rates: RateTracker::default(),
+                bot: sx_bot,
+                sx,
+                metrics,
+            },
+            config: IngestConfig { batch_size },
+        };
+        let start = Instant::now();
+        run(db).await;
+        let elapsed = start.elapsed();
+
+        println!(
+            "batch size {batch_size:>3}: {events} events in {:.2}s, {:.0} events/s",
+            elapsed.as_secs_f64(),
+            events as f64 / elapsed.as_secs_f64()
+        );
+        pool.close().await;
+        let _ = std::fs::remove_file(&path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::broadcast;
+
+    use super::*;
+    use crate::rules::{Action, Condition, Rule};
+
+    fn event(kind: VelorenEventKind) -> VelorenEvent {
+        VelorenEvent {
+            player_alias: "Alice".to_string(),
+            player_uuid: Uuid::from_u128(1),
+            time: Utc::now(),
+            kind,
+        }
+    }
+
+    fn message(text: &str) -> VelorenEvent {
+        event(VelorenEventKind::Message {
+            message: text.to_string(),
+            ty: MessageType::World,
+            recipient: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn a_failing_event_only_drops_itself() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("db/logs/migrations")
+            .run(&pool)
+            .await
+            .unwrap();
+        // Firings of a rule missing from the database break their foreign key.
+        let rules = RuleSet::default();
+        rules.write().await.push(Rule {
+            id: Some(99),
+            name: "missing".to_string(),
+            enabled: true,
+            dry_run: true,
+            conditions: vec![Condition::Contains {
+                text: "forbidden".to_string(),
+            }],
+            actions: vec![Action::Alert],
+        });
+        let (sx_db, _rx_db) = mpsc::channel(1);
+        let (sx_bot, _rx_bot) = mpsc::channel(8);
+        let (sx, mut rx) = broadcast::channel(16);
+        let player_list = PlayerList::default();
+        let mut ingestion = Ingestion {
+            store: Arc::new(SqliteStore::new(pool.clone())),
+            player_list: player_list.clone(),
+            // Two messages in the window are a flood.
+            spam: SpamDetector::new(SpamConfig {
+                flood_messages: 2,
+                ..SpamConfig::default()
+            }),
+            rules,
+            rates: RateTracker::default(),
+            bot: sx_bot,
+            sx,
+            metrics: Metrics::new(PlayerList::default(), sx_db.downgrade()),
+        };
+
+        ingestion
+            .ingest(vec![
+                event(VelorenEventKind::Activity { online: true }),
+                message("something forbidden"),
+                message("hello"),
+            ])
+            .await;
+
+        assert!(matches!(rx.try_recv(), Ok(NetworkEvent::Activity(_))));
+        // The dropped message isn't in the spam window, so this is no flood.
+        match rx.try_recv() {
+            Ok(NetworkEvent::Message(message)) => assert_eq!(message.message, "hello"),
+            _ => panic!("expected the message to be broadcast"),
+        }
+        assert!(rx.try_recv().is_err());
+
+        let messages = sqlx::query_scalar::<_, String>("select content from messages;")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(messages, ["hello"]);
+        assert_eq!(player_list.read().await.len(), 1);
+    }
+}
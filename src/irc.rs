@@ -0,0 +1,485 @@
+use std::{io, time::Duration};
+
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedWriteHalf, TcpStream},
+    sync::{
+        broadcast::{error::RecvError, Receiver},
+        mpsc::Sender,
+    },
+    time::Instant,
+};
+
+use crate::{store::Store, veloren::BotCommand, Message, MessageType, NetworkEvent};
+
+/// IRC lines are limited to 512 bytes including the command, leave room for it.
+const MAX_TEXT_LEN: usize = 400;
+
+/// Bridge settings, read from the `irc` table in `Rocket.toml`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct IrcConfig {
+    pub enabled: bool,
+    /// Plain text IRC server, i.e `127.0.0.1:6667`.
+    pub server: String,
+    pub nick: String,
+    pub channel: String,
+    pub password: Option<String>,
+    /// Messages relayed per direction within `rate_window_secs` before further
+    /// ones are dropped.
+    pub rate_limit: u32,
+    pub rate_window_secs: u64,
+}
+
+impl Default for IrcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            server: "127.0.0.1:6667".to_string(),
+            nick: "mod-panel".to_string(),
+            channel: "#veloren".to_string(),
+            password: None,
+            rate_limit: 5,
+            rate_window_secs: 10,
+        }
+    }
+}
+
+/// Token bucket allowing `capacity` messages per `window`.
+struct RateLimiter {
+    capacity: f64,
+    tokens: f64,
+    per_sec: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    fn new(capacity: u32, window: Duration) -> Self {
+        let capacity = capacity.max(1) as f64;
+        Self {
+            capacity,
+            tokens: capacity,
+            per_sec: capacity / window.as_secs_f64().max(1.0),
+            last: Instant::now(),
+        }
+    }
+
+    fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let refill = (now - self.last).as_secs_f64() * self.per_sec;
+        self.tokens = (self.tokens + refill).min(self.capacity);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct IrcMessage<'a> {
+    nick: Option<&'a str>,
+    command: &'a str,
+    params: Vec<&'a str>,
+}
+
+fn parse_line(line: &str) -> Option<IrcMessage> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (prefix, rest) = match line.strip_prefix(':') {
+        Some(line) => {
+            let (prefix, rest) = line.split_once(' ')?;
+            (Some(prefix), rest)
+        }
+        None => (None, line),
+    };
+    let (rest, trailing) = match rest.split_once(" :") {
+        Some((rest, trailing)) => (rest, Some(trailing)),
+        None => (rest, None),
+    };
+    let mut parts = rest.split(' ').filter(|p| !p.is_empty());
+    let command = parts.next()?;
+    let mut params = parts.collect::<Vec<_>>();
+    params.extend(trailing);
+
+    Some(IrcMessage {
+        nick: prefix.map(|p| p.split_once('!').map_or(p, |(nick, _)| nick)),
+        command,
+        params,
+    })
+}
+
+/// Strips line breaks and cuts `text` to fit in an IRC line.
+fn sanitize(text: &str) -> String {
+    let mut text = text.replace(['\r', '\n'], " ");
+    if text.len() > MAX_TEXT_LEN {
+        let mut end = MAX_TEXT_LEN;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    text
+}
+
+async fn send_line(write: &mut OwnedWriteHalf, line: &str) -> io::Result<()> {
+    write.write_all(line.as_bytes()).await?;
+    write.write_all(b"\r\n").await
+}
+
+struct Bridge {
+    config: IrcConfig,
+    /// Alias of the bot account, world messages from it were relayed from IRC
+    /// and must not be sent back. Unset when the bot doesn't run.
+    bot_alias: Option<String>,
+    bot: Sender<BotCommand>,
+    store: Store,
+}
+
+impl Bridge {
+    async fn handle_line(
+        &self,
+        write: &mut OwnedWriteHalf,
+        line: &str,
+        to_game: &mut RateLimiter,
+    ) -> io::Result<()> {
+        let Some(msg) = parse_line(line) else {
+            return Ok(());
+        };
+        match (msg.command, msg.params.as_slice()) {
+            ("PING", params) => {
+                let token = params.first().copied().unwrap_or("");
+                send_line(write, &format!("PONG :{token}")).await?;
+            }
+            // Welcome, registration is done.
+            ("001", _) => {
+                send_line(write, &format!("JOIN {}", self.config.channel)).await?;
+                tracing::info!("Joined {} on {}", self.config.channel, self.config.server);
+            }
+            ("PRIVMSG", [target, text]) if target.eq_ignore_ascii_case(&self.config.channel) => {
+                let Some(nick) = msg.nick else {
+                    return Ok(());
+                };
+                // Skip our own messages and CTCP requests.
+                if nick.eq_ignore_ascii_case(&self.config.nick) || text.starts_with('\u{1}') {
+                    return Ok(());
+                }
+                if !to_game.try_take() {
+                    tracing::warn!("Dropping IRC message from {nick}, rate limited");
+                    return Ok(());
+                }
+                let _ = self
+                    .bot
+                    .send(BotCommand::World {
+                        message: format!("<{nick}> {text}"),
+                    })
+                    .await;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn handle_message(
+        &self,
+        write: &mut OwnedWriteHalf,
+        message: Message,
+        to_irc: &mut RateLimiter,
+    ) -> io::Result<()> {
+        if message.ty != MessageType::World {
+            return Ok(());
+        }
+        let Ok(Some(alias)) = self.store.player_alias(message.player_id).await else {
+            return Ok(());
+        };
+        if self
+            .bot_alias
+            .as_deref()
+            .map_or(false, |bot_alias| alias.eq_ignore_ascii_case(bot_alias))
+        {
+            return Ok(());
+        }
+        if !to_irc.try_take() {
+            tracing::warn!("Dropping world message from {alias}, rate limited");
+            return Ok(());
+        }
+        let text = sanitize(&format!("<{alias}> {}", message.message.trim()));
+        send_line(write, &format!("PRIVMSG {} :{text}", self.config.channel)).await
+    }
+
+    async fn session(&self, rx: &mut Receiver<NetworkEvent>) -> io::Result<()> {
+        let stream = TcpStream::connect(&self.config.server).await?;
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+
+        if let Some(password) = &self.config.password {
+            send_line(&mut write, &format!("PASS {password}")).await?;
+        }
+        send_line(&mut write, &format!("NICK {}", self.config.nick)).await?;
+        send_line(
+            &mut write,
+            &format!("USER {} 0 * :Veloren Mod Panel", self.config.nick),
+        )
+        .await?;
+
+        let window = Duration::from_secs(self.config.rate_window_secs);
+        let mut to_irc = RateLimiter::new(self.config.rate_limit, window);
+        let mut to_game = RateLimiter::new(self.config.rate_limit, window);
+
+        loop {
+            tokio::select! {
+                line = lines.next_line() => match line? {
+                    Some(line) => self.handle_line(&mut write, &line, &mut to_game).await?,
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "IRC server closed the connection",
+                        ))
+                    }
+                },
+                event = rx.recv() => match event {
+                    Ok(NetworkEvent::Message(message)) => {
+                        self.handle_message(&mut write, message, &mut to_irc).await?
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(n)) => {
+                        tracing::warn!("IRC bridge lagged behind, skipped {n} events");
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+            }
+        }
+    }
+}
+
+/// Relays world chat to an IRC channel and the channel back into the game
+/// through the bot, reconnecting whenever the IRC connection drops.
+pub async fn run(
+    config: IrcConfig,
+    bot_alias: Option<String>,
+    mut rx: Receiver<NetworkEvent>,
+    bot: Sender<BotCommand>,
+    store: Store,
+) {
+    let bridge = Bridge {
+        config,
+        bot_alias,
+        bot,
+        store,
+    };
+    let mut retry_cnt = 0u32;
+    loop {
+        let connected = Instant::now();
+        match bridge.session(&mut rx).await {
+            Ok(()) => break,
+            Err(e) => {
+                if connected.elapsed() > Duration::from_secs(60) {
+                    retry_cnt = 0;
+                }
+                tracing::error!("IRC bridge disconnected: {}, retry: {}", e, retry_cnt);
+                retry_cnt += 1;
+                tokio::time::sleep(Duration::from_secs(5) * retry_cnt.min(12)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::Utc;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use tokio::{
+        io::Lines,
+        net::{tcp::OwnedReadHalf, TcpListener},
+        sync::{broadcast, mpsc},
+        time::timeout,
+    };
+
+    use super::*;
+    use crate::store::SqliteStore;
+
+    #[test]
+    fn parse_line_splits_prefix_command_and_params() {
+        let msg = parse_line(":alice!a@example.org PRIVMSG #veloren :hello there\r\n").unwrap();
+        assert_eq!(msg.nick, Some("alice"));
+        assert_eq!(msg.command, "PRIVMSG");
+        assert_eq!(msg.params, ["#veloren", "hello there"]);
+
+        let msg = parse_line("PING :irc.example.org").unwrap();
+        assert_eq!(msg.nick, None);
+        assert_eq!(msg.command, "PING");
+        assert_eq!(msg.params, ["irc.example.org"]);
+
+        let msg = parse_line(":irc.example.org 001 mod-panel :Welcome").unwrap();
+        assert_eq!(msg.nick, Some("irc.example.org"));
+        assert_eq!(msg.command, "001");
+        assert_eq!(msg.params, ["mod-panel", "Welcome"]);
+    }
+
+    #[test]
+    fn parse_line_handles_extra_spaces_and_empty_trailing() {
+        let msg = parse_line("MODE  #veloren   +o  mod-panel").unwrap();
+        assert_eq!(msg.params, ["#veloren", "+o", "mod-panel"]);
+
+        let msg = parse_line("PRIVMSG #veloren :").unwrap();
+        assert_eq!(msg.params, ["#veloren", ""]);
+    }
+
+    #[test]
+    fn parse_line_rejects_empty_lines() {
+        assert!(parse_line("").is_none());
+        assert!(parse_line("\r\n").is_none());
+        assert!(parse_line(":prefix-only").is_none());
+    }
+
+    #[test]
+    fn sanitize_replaces_line_breaks() {
+        assert_eq!(sanitize("one\rtwo\nthree"), "one two three");
+        assert_eq!(sanitize("short"), "short");
+    }
+
+    #[test]
+    fn sanitize_truncates_on_char_boundary() {
+        assert_eq!(sanitize(&"a".repeat(MAX_TEXT_LEN + 10)).len(), MAX_TEXT_LEN);
+
+        // Two byte characters put the limit in the middle of one.
+        let text = format!("a{}", "é".repeat(MAX_TEXT_LEN));
+        let sanitized = sanitize(&text);
+        assert_eq!(sanitized.len(), MAX_TEXT_LEN - 1);
+        assert!(text.starts_with(&sanitized));
+    }
+
+    #[test]
+    fn rate_limiter_allows_capacity_then_refills() {
+        let mut limiter = RateLimiter::new(2, Duration::from_secs(60));
+        assert!(limiter.try_take());
+        assert!(limiter.try_take());
+        assert!(!limiter.try_take());
+
+        // 45 seconds at 2 per minute refill one and a half messages.
+        limiter.last -= Duration::from_secs(45);
+        assert!(limiter.try_take());
+        assert!(!limiter.try_take());
+    }
+
+    #[test]
+    fn rate_limiter_never_exceeds_capacity() {
+        let mut limiter = RateLimiter::new(1, Duration::from_secs(10));
+        limiter.last -= Duration::from_secs(600);
+        assert!(limiter.try_take());
+        assert!(!limiter.try_take());
+    }
+
+    async fn next_line(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> String {
+        timeout(Duration::from_secs(5), lines.next_line())
+            .await
+            .expect("timed out waiting for the bridge")
+            .unwrap()
+            .expect("bridge closed the connection")
+    }
+
+    async fn next_command(bot: &mut mpsc::Receiver<BotCommand>) -> String {
+        match timeout(Duration::from_secs(5), bot.recv()).await {
+            Ok(Some(BotCommand::World { message })) => message,
+            other => panic!("expected a world message, got {other:?}"),
+        }
+    }
+
+    fn world_message(player_id: u32, message: &str) -> NetworkEvent {
+        NetworkEvent::Message(Message {
+            id: 0,
+            player_id,
+            message: message.to_string(),
+            ty: MessageType::World,
+            time: Utc::now(),
+            spam_score: 0.0,
+            recipient_id: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn relays_both_ways_over_loopback() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("db/logs/migrations")
+            .run(&pool)
+            .await
+            .unwrap();
+        for (uuid, alias) in [("bob-uuid", "Bob"), ("bot-uuid", "Mod-Bot")] {
+            sqlx::query("insert into players (uuid, alias) values ($1, $2);")
+                .bind(uuid)
+                .bind(alias)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (sx_bot, mut rx_bot) = mpsc::channel(16);
+        let (sx_events, mut rx_events) = broadcast::channel(16);
+        let bridge = Bridge {
+            config: IrcConfig {
+                enabled: true,
+                server: listener.local_addr().unwrap().to_string(),
+                rate_limit: 100,
+                ..IrcConfig::default()
+            },
+            bot_alias: Some("mod-bot".to_string()),
+            bot: sx_bot,
+            store: Arc::new(SqliteStore::new(pool)),
+        };
+        let session = tokio::spawn(async move { bridge.session(&mut rx_events).await });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+
+        assert_eq!(next_line(&mut lines).await, "NICK mod-panel");
+        assert_eq!(
+            next_line(&mut lines).await,
+            "USER mod-panel 0 * :Veloren Mod Panel"
+        );
+        send_line(&mut write, ":irc.example.org 001 mod-panel :Welcome")
+            .await
+            .unwrap();
+        assert_eq!(next_line(&mut lines).await, "JOIN #veloren");
+        send_line(&mut write, "PING :irc.example.org")
+            .await
+            .unwrap();
+        assert_eq!(next_line(&mut lines).await, "PONG :irc.example.org");
+
+        // IRC to game, skipping our own nick, CTCP and other channels.
+        for line in [
+            ":mod-panel!m@example.org PRIVMSG #veloren :echo",
+            ":alice!a@example.org PRIVMSG #veloren :\u{1}ACTION waves\u{1}",
+            ":alice!a@example.org PRIVMSG #elsewhere :wrong channel",
+            ":alice!a@example.org PRIVMSG #Veloren :hello",
+        ] {
+            send_line(&mut write, line).await.unwrap();
+        }
+        assert_eq!(next_command(&mut rx_bot).await, "<alice> hello");
+
+        // Game to IRC, world messages of the bot came from IRC and must not be
+        // sent back.
+        sx_events.send(world_message(2, "<alice> hello")).unwrap();
+        sx_events.send(world_message(1, " hi\nthere ")).unwrap();
+        assert_eq!(
+            next_line(&mut lines).await,
+            "PRIVMSG #veloren :<Bob> hi there"
+        );
+
+        drop(sx_events);
+        timeout(Duration::from_secs(5), session)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(rx_bot.try_recv().is_err());
+    }
+}
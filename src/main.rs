@@ -2,40 +2,79 @@
 use std::{
     collections::HashSet,
     fmt::{Debug, Display},
+    path::PathBuf,
+    process::ExitCode,
+    str::FromStr,
     sync::Arc,
 };
 
-use chrono::{DateTime, Duration, Utc};
-use futures::executor::block_on;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
 use rocket::{
     fairing::{self, AdHoc},
+    form::{self, FromFormField, ValueField},
     fs::{relative, FileServer},
-    response::stream::{Event, EventStream},
     serde::json::Json,
-    Build, Request, Rocket, Shutdown, State,
+    Build, Request, Rocket, State,
 };
-use rocket_db_pools::{sqlx, Connection, Database};
+use rocket_db_pools::{sqlx, Database};
 use rocket_dyn_templates::{handlebars::Handlebars, Template};
-use serde::{Deserialize, Serialize};
-use sqlx::{pool::PoolConnection, sqlite::SqliteArguments, Arguments, FromRow, Pool, Row, Sqlite};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use sqlx::{sqlite::SqliteArguments, Arguments, FromRow, Pool, Sqlite};
 use tokio::{
-    sync::{
-        broadcast::{channel, error::RecvError, Receiver, Sender},
-        RwLock,
-    },
-    time::timeout,
+    sync::{broadcast::channel, RwLock},
+    task::JoinHandle,
 };
+use tracing::{error, warn};
+use utoipa::{IntoParams, ToSchema};
 use veloren_common::uuid::Uuid;
 
-use crate::veloren::{env_key, run};
+use crate::{
+    backup::{BackupConfig, Backups, DatabaseLock},
+    error::{ApiError, ApiResult, UnknownVariant},
+    health::HealthConfig,
+    ingest::{DbDrop, IngestConfig, Ingestion},
+    irc::IrcConfig,
+    metrics::Metrics,
+    reports::Report,
+    retention::RetentionConfig,
+    rules::{RateTracker, RuleFiring, RuleSet, Watchlist},
+    spam::{SpamConfig, SpamDetector, SpamReason},
+    store::{Store, TimedStore},
+    telemetry::{RequestTrace, TracingConfig},
+    tickets::TicketEvent,
+    veloren::{env_key, run, BotCommand, BotConfig, BotState},
+    webhooks::{WebhookConfig, WebhookSet},
+    websocket::WebSocketConfig,
+};
 
 #[macro_use]
 extern crate rocket;
 
+mod backup;
+mod cli;
+mod error;
+mod events;
+mod export;
+mod health;
+mod ingest;
+mod irc;
+mod messages;
+mod metrics;
+mod player_data;
+mod reports;
+mod retention;
+mod rules;
+mod spam;
+mod store;
+mod telemetry;
+mod tickets;
+mod v1;
 mod veloren;
+mod webhooks;
+mod websocket;
 
 #[get("/")]
-async fn index(player_list: &State<PlayerList>, mut db: Connection<Db>) -> Template {
+async fn index(player_list: &State<PlayerList>, store: &State<Store>) -> ApiResult<Template> {
     #[derive(Serialize)]
     struct Player {
         alias: String,
@@ -49,32 +88,24 @@ async fn index(player_list: &State<PlayerList>, mut db: Connection<Db>) -> Templ
     let mut context = Context::default();
     let ids = player_list.read().await.iter().copied().collect::<Vec<_>>();
     for id in ids {
-        let alias = sqlx::query_scalar::<_, String>(
-            "
-            select alias
-            from players
-            where id = ?;
-        ",
-        )
-        .bind(id)
-        .fetch_one(&mut *db)
-        .await
-        .unwrap();
+        let Some(alias) = store.player_alias(id).await? else {
+            continue;
+        };
         context.players.push(Player {
             alias,
             id: format!("player-{id}"),
             entry_id: format!("player-entry-{id}"),
         });
     }
-    Template::render("home", context)
+    Ok(Template::render("home", context))
 }
 
 #[catch(404)]
 fn not_found(req: &Request) -> String {
-    print!("{}", req);
+    tracing::debug!(method = %req.method(), uri = %req.uri(), "No route matched");
     format!("Oh no! We couldn't find the requested path '{}'", req.uri())
 }
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, FromFormField, ToSchema)]
 #[repr(u32)]
 pub enum MessageType {
     World,
@@ -82,13 +113,15 @@ pub enum MessageType {
     Faction,
 }
 
-impl From<&str> for MessageType {
-    fn from(value: &str) -> Self {
+impl FromStr for MessageType {
+    type Err = UnknownVariant;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
         match value {
-            "World" => MessageType::World,
-            "Tell" => MessageType::Tell,
-            "Faction" => MessageType::Faction,
-            _ => panic!(),
+            "World" => Ok(MessageType::World),
+            "Tell" => Ok(MessageType::Tell),
+            "Faction" => Ok(MessageType::Faction),
+            _ => Err(UnknownVariant::new("message type", value)),
         }
     }
 }
@@ -103,12 +136,23 @@ impl Display for MessageType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 enum VelorenEventKind {
-    Message { message: String, ty: MessageType },
+    Message {
+        message: String,
+        ty: MessageType,
+        /// Alias and uuid of the receiver of a tell.
+        recipient: Option<(String, Uuid)>,
+    },
     Activity { online: bool },
+    Report {
+        reported_alias: String,
+        reported_uuid: Option<Uuid>,
+        reason: String,
+    },
 }
 
+#[derive(Clone)]
 pub struct VelorenEvent {
     player_alias: String,
     player_uuid: Uuid,
@@ -116,55 +160,70 @@ pub struct VelorenEvent {
     kind: VelorenEventKind,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
 struct Message {
     id: u32,
     player_id: u32,
     message: String,
     ty: MessageType,
     time: DateTime<Utc>,
+    spam_score: f32,
+    recipient_id: Option<u32>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
 struct Activity {
+    id: u32,
     player_id: u32,
     online: bool,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-enum NetworkEvent {
-    Message(Message),
-    Activity(Activity),
+enum AlertKind {
+    Spam {
+        score: f32,
+        reasons: Vec<SpamReason>,
+    },
+    Rule {
+        rule_id: u32,
+        name: String,
+    },
 }
 
-#[get("/events")]
-async fn events(queue: &State<Receiver<NetworkEvent>>, mut end: Shutdown) -> EventStream![] {
-    let mut rx = queue.resubscribe();
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Alert {
+    player_id: u32,
+    message_id: Option<u32>,
+    time: DateTime<Utc>,
+    kind: AlertKind,
+}
 
-    EventStream! {
-        loop {
-            let msg = rocket::tokio::select! {
-                msg = rx.recv() => match msg {
-                    Ok(msg) => msg,
-                    Err(RecvError::Closed) => break,
-                    Err(RecvError::Lagged(_)) => continue,
-                },
-                _ = &mut end => break,
-            };
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct BotConnection {
+    connected: bool,
+    time: DateTime<Utc>,
+}
 
-            yield Event::json(&msg);
-        }
-    }
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum NetworkEvent {
+    Message(Message),
+    Activity(Activity),
+    Alert(Alert),
+    RuleFiring(RuleFiring),
+    Report(Report),
+    Ticket(TicketEvent),
+    Connection(BotConnection),
 }
 
 #[post("/player_alias", data = "<id>")]
-async fn player_alias(mut db: Connection<Db>, id: &str) -> Option<String> {
-    sqlx::query("select alias from players where id = ?")
-        .bind(id.parse::<u32>().ok()?)
-        .fetch_one(&mut *db)
-        .await
-        .and_then(|r| r.try_get(0))
-        .ok()
+async fn player_alias(store: &State<Store>, id: &str) -> ApiResult<String> {
+    let id = id
+        .parse::<u32>()
+        .map_err(|_| ApiError::BadRequest(format!("invalid player id '{id}'")))?;
+    store
+        .player_alias(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("player {id}")))
 }
 
 #[derive(FromRow)]
@@ -174,259 +233,271 @@ struct DbMessage {
     time: DateTime<Utc>,
     content: String,
     ty: String,
+    spam_score: f32,
+    recipient_id: Option<u32>,
 }
 
-impl From<DbMessage> for Message {
-    fn from(msg: DbMessage) -> Self {
-        Message {
+impl TryFrom<DbMessage> for Message {
+    type Error = sqlx::Error;
+
+    fn try_from(msg: DbMessage) -> Result<Self, Self::Error> {
+        Ok(Message {
             id: msg.id,
             player_id: msg.player_id,
             time: msg.time,
             message: msg.content,
-            ty: MessageType::from(&*msg.ty),
-        }
+            ty: msg.ty.parse()?,
+            spam_score: msg.spam_score,
+            recipient_id: msg.recipient_id,
+        })
     }
 }
 
-#[post("/messages_before?<id>")]
-async fn messages_before(mut db: Connection<Db>, id: Option<u32>) -> Json<Vec<Message>> {
-    let messages = if let Some(id) = id {
-        sqlx::query_as::<_, DbMessage>(
-            "
-            select *
-            from messages
-            where id < ?
-            order by id desc
-            limit 50;
-        ",
-        )
-        .bind(id)
-        .fetch_all(&mut *db)
-    } else {
-        sqlx::query_as::<_, DbMessage>(
-            "
-            select *
-            from messages
-            order by id desc
-            limit 50;
-        ",
-        )
-        .bind(id)
-        .fetch_all(&mut *db)
+#[post("/players")]
+async fn player_list(player_list: &State<PlayerList>) -> Json<Vec<u32>> {
+    Json(player_list.read().await.iter().copied().collect())
+}
+
+/// A point in time, given as RFC 3339, RFC 2822 or Unix seconds. Times
+/// without an offset, like `2023-03-01T12:00:00` or `2023-03-01`, are UTC.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timestamp(pub DateTime<Utc>);
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let time = if let Ok(secs) = value.parse::<i64>() {
+            Utc.timestamp_opt(secs, 0).single()
+        } else if let Ok(secs) = value.parse::<f64>()
+            && secs.is_finite()
+        {
+            let nanos = ((secs - secs.floor()) * 1e9) as u32;
+            Utc.timestamp_opt(secs.floor() as i64, nanos).single()
+        } else if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+            Some(time.with_timezone(&Utc))
+        } else if let Ok(time) = DateTime::parse_from_rfc2822(value) {
+            Some(time.with_timezone(&Utc))
+        } else if let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f")
+            .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f"))
+        {
+            Some(Utc.from_utc_datetime(&time))
+        } else if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            date.and_hms_opt(0, 0, 0).map(|time| Utc.from_utc_datetime(&time))
+        } else {
+            None
+        };
+        time.map(Timestamp).ok_or_else(|| {
+            format!("invalid time '{value}', expected RFC 3339 or Unix seconds")
+        })
     }
-    .await
-    .unwrap();
-
-    Json(messages.into_iter().map(Message::from).collect())
-}
-
-#[post("/messages_after?<id>")]
-async fn messages_after(mut db: Connection<Db>, id: Option<u32>) -> Json<Vec<Message>> {
-    let messages = if let Some(id) = id {
-        sqlx::query_as::<_, DbMessage>(
-            "
-            select *
-            from messages
-            where id > ?
-            order by id asc
-            limit 50;
-        ",
-        )
-        .bind(id)
-        .fetch_all(&mut *db)
-    } else {
-        sqlx::query_as::<_, DbMessage>(
-            "
-            select *
-            from messages
-            order by id asc
-            limit 50;
-        ",
-        )
-        .bind(id)
-        .fetch_all(&mut *db)
+}
+
+impl<'v> FromFormField<'v> for Timestamp {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        Ok(field.value.parse().map_err(form::Error::validation)?)
     }
-    .await
-    .unwrap();
+}
 
-    Json(messages.into_iter().map(Message::from).collect())
+impl Serialize for Timestamp {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+    }
 }
 
-#[post("/players")]
-async fn player_list(player_list: &State<PlayerList>) -> Json<Vec<u32>> {
-    Json(player_list.read().await.iter().copied().collect())
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Secs(i64),
+            FractionalSecs(f64),
+            Text(String),
+        }
+        let value = match Raw::deserialize(deserializer)? {
+            Raw::Secs(secs) => secs.to_string(),
+            Raw::FractionalSecs(secs) => secs.to_string(),
+            Raw::Text(text) => text,
+        };
+        value.parse().map_err(de::Error::custom)
+    }
 }
 
-#[derive(Deserialize)]
-struct MessageQuery {
-    per_page: Option<u32>,
-    page: Option<u32>,
+/// Filters shared by everything that lists messages.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize, FromForm, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MessageFilter {
     player_id: Option<u32>,
-    after: Option<String>,
-    before: Option<String>,
+    /// Only messages sent after this time.
+    #[param(value_type = Option<String>)]
+    after: Option<Timestamp>,
+    /// Only messages sent before this time.
+    #[param(value_type = Option<String>)]
+    before: Option<Timestamp>,
+    ty: Option<MessageType>,
+    /// Only messages containing this text, ignoring case.
+    text: Option<String>,
+    /// Only tells sent to this player.
+    recipient_id: Option<u32>,
 }
 
-#[post("/query_messages", data = "<query>")]
-async fn query_messages(mut db: Connection<Db>, query: Json<MessageQuery>) -> Json<Vec<Message>> {
-    let mut args = SqliteArguments::default();
-    let mut where_statements = Vec::new();
-    let mut input_n = 1;
-    if let Some(player_id) = query.player_id {
-        where_statements.push(format!("player_id = ${input_n}"));
-        input_n += 1;
-        args.add(player_id);
-    }
-    let convert_dt = |dt: &str| {
-        Some(DateTime::<Utc>::from(
-            DateTime::parse_from_rfc2822(&dt).ok()?,
-        ))
-    };
-    match (
-        query.after.as_deref().and_then(convert_dt),
-        query.before.as_deref().and_then(convert_dt),
-    ) {
-        (Some(after), Some(before)) => {
-            where_statements.push(format!(
-                "time between date(${input_n}) and date(${})",
-                input_n + 1
-            ));
-            input_n += 2;
-            args.add(after);
-            args.add(before);
+impl MessageFilter {
+    /// Builds the `where` clause for the filter, binding its values to `args`.
+    /// Returns the clause and the number of the next free placeholder.
+    ///
+    /// Times are stored as RFC 3339 in UTC, so comparing them as text keeps
+    /// their full precision.
+    pub fn where_clause(&self, args: &mut SqliteArguments<'_>) -> (String, u32) {
+        let mut where_statements = Vec::new();
+        let mut input_n = 1;
+        if let Some(player_id) = self.player_id {
+            where_statements.push(format!("player_id = ${input_n}"));
+            input_n += 1;
+            args.add(player_id);
         }
-        (Some(after), None) => {
-            where_statements.push(format!("date(time) > date(${input_n})"));
+        if let Some(ty) = &self.ty {
+            where_statements.push(format!("ty = ${input_n}"));
             input_n += 1;
-            args.add(after);
+            args.add(ty.to_string());
         }
-        (None, Some(before)) => {
-            where_statements.push(format!("date(time) < date(${input_n})"));
+        if let Some(after) = self.after {
+            where_statements.push(format!("time > ${input_n}"));
             input_n += 1;
-            args.add(before);
+            args.add(after.0);
         }
-        (None, None) => {}
-    }
-    let mut where_statements = where_statements.into_iter();
-    let where_statement = where_statements.next();
-    let where_statement = where_statement
-        .map(|acc| {
-            where_statements.fold("where ".to_owned() + &acc, |mut acc, b| {
-                acc.push_str(" and ");
-                acc.push_str(&b);
-                acc
+        if let Some(before) = self.before {
+            where_statements.push(format!("time < ${input_n}"));
+            input_n += 1;
+            args.add(before.0);
+        }
+        if let Some(text) = &self.text {
+            where_statements.push(format!("instr(lower(content), lower(${input_n})) > 0"));
+            input_n += 1;
+            args.add(text.clone());
+        }
+        if let Some(recipient_id) = self.recipient_id {
+            where_statements.push(format!("recipient_id = ${input_n}"));
+            input_n += 1;
+            args.add(recipient_id);
+        }
+        let mut where_statements = where_statements.into_iter();
+        let where_statement = where_statements.next();
+        let where_statement = where_statement
+            .map(|acc| {
+                where_statements.fold("where ".to_owned() + &acc, |mut acc, b| {
+                    acc.push_str(" and ");
+                    acc.push_str(&b);
+                    acc
+                })
             })
-        })
-        .unwrap_or(String::new());
-    let per_page = query.per_page.unwrap_or(50);
-    args.add(query.per_page);
+            .unwrap_or(String::new());
 
-    args.add(query.page.unwrap_or(0) * per_page);
-    let query = format!(
-        "select * from messages {where_statement} order by id desc limit ${input_n} offset ${}",
-        input_n + 1
-    );
+        (where_statement, input_n)
+    }
+}
 
-    let messages = sqlx::query_as_with::<_, DbMessage, _>(&query, args)
-        .fetch_all(&mut *db)
-        .await
-        .unwrap();
+/// Largest page `query_messages` and `list_messages` return.
+const MAX_PER_PAGE: u32 = 500;
 
-    Json(messages.into_iter().map(Message::from).collect())
+#[derive(Deserialize)]
+struct MessageQuery {
+    per_page: Option<u32>,
+    page: Option<u32>,
+    #[serde(flatten)]
+    filter: MessageFilter,
+}
+
+#[derive(Serialize)]
+struct MessagePage {
+    messages: Vec<Message>,
+    /// Messages matching the filter across all pages.
+    total: u64,
+    page: u32,
+    per_page: u32,
+    pages: u64,
+}
+
+#[post("/query_messages", data = "<query>")]
+async fn query_messages(
+    store: &State<Store>,
+    query: Json<MessageQuery>,
+) -> ApiResult<Json<MessagePage>> {
+    let per_page = query.per_page.unwrap_or(50).clamp(1, MAX_PER_PAGE);
+    let page = query.page.unwrap_or(0);
+    let messages = store.query_messages(&query.filter, per_page, page).await?;
+    let total = store.count_messages(&query.filter).await?;
+
+    Ok(Json(MessagePage {
+        messages,
+        total,
+        page,
+        per_page,
+        pages: total.div_ceil(per_page as u64),
+    }))
 }
 
 #[post("/players?<alias>")]
-async fn query_players(mut db: Connection<Db>, alias: Option<String>) -> Json<Vec<u32>> {
-    let like = format!("%{}%", alias.as_deref().unwrap_or(""));
-    let ids = sqlx::query_scalar(
-        "
-        select id
-        from players
-        where alias like ?;
-    ",
-    )
-    .bind(like)
-    .fetch_all(&mut *db)
-    .await
-    .unwrap();
-
-    Json(ids)
-}
-
-async fn query_playtime(db: &mut Connection<Db>, id: u32) -> (Duration, bool) {
-    #[derive(FromRow)]
-    struct Activity {
-        time: DateTime<Utc>,
-        online: bool,
-    }
-    sqlx::query_as::<_, Activity>(
-        "
-        select time,online
-        from activity
-        where player_id = ?;
-    ",
-    )
-    .bind(id)
-    .fetch_all(&mut **db)
-    .await
-    .map(|activity| {
-        let mut expects_online = true;
-        let mut duration = Duration::zero();
-        let mut start = Utc::now();
-        for a in activity {
-            if a.online == expects_online {
-                if a.online {
-                    start = a.time;
-                } else {
-                    duration = duration.checked_add(&(a.time - start)).unwrap();
-                }
+async fn query_players(store: &State<Store>, alias: Option<String>) -> ApiResult<Json<Vec<u32>>> {
+    Ok(Json(store.find_players(alias.as_deref().unwrap_or("")).await?))
+}
 
-                expects_online = !expects_online;
-            } else {
-                rocket::error!("Expected online = {expects_online}");
+/// Pairs up logins and logouts into sessions, the last one is open while the
+/// player is online.
+fn sessions(activity: Vec<(DateTime<Utc>, bool)>) -> Vec<(DateTime<Utc>, Option<DateTime<Utc>>)> {
+    let mut sessions = Vec::new();
+    let mut start = None;
+    for (time, online) in activity {
+        match (start, online) {
+            (None, true) => start = Some(time),
+            (Some(from), false) => {
+                sessions.push((from, Some(time)));
+                start = None;
             }
+            _ => tracing::error!("Expected online = {}", start.is_none()),
         }
-        if !expects_online {
-            duration = duration.checked_add(&(Utc::now() - start)).unwrap();
-        }
-        (duration, !expects_online)
-    })
-    .unwrap()
+    }
+    if let Some(from) = start {
+        sessions.push((from, None));
+    }
+    sessions
+}
+
+async fn query_playtime(store: &Store, id: u32) -> sqlx::Result<(Duration, bool)> {
+    let sessions = sessions(store.activity(id).await?);
+    let now = Utc::now();
+    let duration = sessions
+        .iter()
+        .fold(Duration::zero(), |duration, (start, end)| {
+            duration + (end.unwrap_or(now) - *start)
+        });
+    let online = matches!(sessions.last(), Some((_, None)));
+    Ok((duration, online))
 }
 
 #[get("/user/<id>")]
-async fn user_page(mut db: Connection<Db>, id: u32) -> Template {
-    match sqlx::query_scalar::<_, String>(
-        "
-        select alias
-        from players
-        where id = ?;
-    ",
-    )
-    .bind(id)
-    .fetch_one(&mut *db)
-    .await
-    {
-        Ok(alias) => {
+async fn user_page(store: &State<Store>, id: u32) -> ApiResult<Template> {
+    match store.player_alias(id).await? {
+        Some(alias) => {
             #[derive(Serialize)]
             struct Context {
+                id: u32,
                 alias: String,
                 play_time: u64,
                 online: bool,
             }
 
-            let (pt, online) = query_playtime(&mut db, id).await;
+            let (pt, online) = query_playtime(store, id).await?;
             let context = Context {
+                id,
                 alias,
                 play_time: pt.num_seconds() as u64,
                 online,
             };
 
-            Template::render("user", context)
-        }
-        Err(e) => {
-            dbg!(e);
-            return Template::render("user_not_found", ());
+            Ok(Template::render("user", context))
         }
+        None => Ok(Template::render("user_not_found", ())),
     }
 }
 
@@ -434,15 +505,135 @@ async fn user_page(mut db: Connection<Db>, id: u32) -> Template {
 #[database("logs")]
 struct Db(sqlx::SqlitePool);
 
+/// Rows a pending migration has to move out of the way before adding its
+/// constraints.
+struct PreflightCheck {
+    /// Version of the migration adding the constraint.
+    migration: i64,
+    description: &'static str,
+    /// What the migration does with the rows.
+    fate: &'static str,
+    /// Counts the rows.
+    query: &'static str,
+}
+
+const HARDENING: i64 = 20230312;
+const FOREIGN_KEYS: i64 = 20230326;
+
+const PREFLIGHT_CHECKS: &[PreflightCheck] = &[
+    PreflightCheck {
+        migration: HARDENING,
+        description: "messages without a known player",
+        fate: "moved to quarantine",
+        query: "select count(*) from messages where player_id is null or player_id not in (select id from players);",
+    },
+    PreflightCheck {
+        migration: HARDENING,
+        description: "messages without a time",
+        fate: "moved to quarantine",
+        query: "select count(*) from messages where time is null;",
+    },
+    PreflightCheck {
+        migration: HARDENING,
+        description: "messages with an unknown type",
+        fate: "moved to quarantine",
+        query: "select count(*) from messages where ty not in ('World', 'Tell', 'Faction');",
+    },
+    PreflightCheck {
+        migration: HARDENING,
+        description: "activity entries without a known player",
+        fate: "moved to quarantine",
+        query: "select count(*) from activity where player_id is null or player_id not in (select id from players);",
+    },
+    PreflightCheck {
+        migration: HARDENING,
+        description: "activity entries without a time or state",
+        fate: "moved to quarantine",
+        query: "select count(*) from activity where time is null or online is null;",
+    },
+    PreflightCheck {
+        migration: FOREIGN_KEYS,
+        description: "reports without a known reporter or reported player",
+        fate: "dropped",
+        query: "select count(*) from reports where reporter_id not in (select id from players) or reported_id not in (select id from players);",
+    },
+    PreflightCheck {
+        migration: FOREIGN_KEYS,
+        description: "tickets of a deleted report",
+        fate: "unlinked from it",
+        query: "select count(*) from tickets where report_id not in (select id from reports);",
+    },
+    PreflightCheck {
+        migration: FOREIGN_KEYS,
+        description: "rule firings without a known rule or player",
+        fate: "dropped",
+        query: "select count(*) from rule_firings where rule_id not in (select id from rules) or player_id not in (select id from players);",
+    },
+    PreflightCheck {
+        migration: FOREIGN_KEYS,
+        description: "report messages without a known report or message",
+        fate: "dropped",
+        query: "select count(*) from report_messages where report_id not in (select id from reports) or message_id not in (select id from messages);",
+    },
+    PreflightCheck {
+        migration: FOREIGN_KEYS,
+        description: "ticket links without a known ticket, player or message",
+        fate: "dropped",
+        query: "select (select count(*) from ticket_players where ticket_id not in (select id from tickets) or player_id not in (select id from players)) \
+            + (select count(*) from ticket_messages where ticket_id not in (select id from tickets) or message_id not in (select id from messages)) \
+            + (select count(*) from ticket_comments where ticket_id not in (select id from tickets));",
+    },
+    PreflightCheck {
+        migration: FOREIGN_KEYS,
+        description: "player tags, aliases and notes without a known player",
+        fate: "dropped",
+        query: "select (select count(*) from player_tags where player_id not in (select id from players)) \
+            + (select count(*) from player_aliases where player_id not in (select id from players)) \
+            + (select count(*) from player_notes where player_id not in (select id from players));",
+    },
+    PreflightCheck {
+        migration: FOREIGN_KEYS,
+        description: "webhook deliveries of a deleted webhook",
+        fate: "dropped",
+        query: "select count(*) from webhook_deliveries where webhook_id not in (select id from webhooks);",
+    },
+];
+
+/// Reports rows that violate the constraints of pending migrations before
+/// they run, so the checks only run once. Once applied the constraints keep
+/// these at zero.
+async fn preflight(pool: &Pool<Sqlite>) {
+    // Fails on a fresh database, where there is nothing to check.
+    let Ok(pending) = health::pending_migrations(pool).await else {
+        return;
+    };
+    for check in PREFLIGHT_CHECKS {
+        if !pending.contains(&check.migration) {
+            continue;
+        }
+        match sqlx::query_scalar::<_, u32>(check.query).fetch_one(pool).await {
+            Ok(count) if count > 0 => warn!(
+                "Found {count} {}, migration {} will leave them {}",
+                check.description, check.migration, check.fate
+            ),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to count {}: {e}", check.description),
+        }
+    }
+}
+
 async fn run_migrations(rocket: Rocket<Build>) -> fairing::Result {
     match Db::fetch(&rocket) {
-        Some(db) => match sqlx::migrate!("db/logs/migrations").run(&**db).await {
-            Ok(_) => Ok(rocket),
-            Err(e) => {
-                error!("Failed to initialize SQLx database: {}", e);
-                Err(rocket)
+        Some(db) => {
+            preflight(&**db).await;
+            match sqlx::migrate!("db/logs/migrations").run(&**db).await {
+                Ok(_) => Ok(rocket),
+                Err(e) => {
+                    error!("Failed to initialize SQLx database: {}", e);
+                    Err(rocket)
+                }
             }
-        },
+        }
         None => Err(rocket),
     }
 }
@@ -456,107 +647,175 @@ pub fn customize_hbs(hbs: &mut Handlebars) {
 }
 
 type PlayerList = Arc<RwLock<HashSet<u32>>>;
-async fn handle_database_msg(
-    mut conn: PoolConnection<Sqlite>,
-    msg: VelorenEvent,
-    player_list: &PlayerList,
-    sx: &Sender<NetworkEvent>,
-) {
-    let mut args = SqliteArguments::default();
-    args.add(msg.player_uuid.to_string());
-    args.add(msg.player_alias);
-    let player_id = sqlx::query_scalar_with::<_, u32, _>(
-        "
-        insert or ignore into players (uuid, alias) values ($1, $2);
-        select id from players where uuid = $1;
-        ",
-        args,
-    )
-    .fetch_one(&mut conn)
-    .await
-    .unwrap();
-
-    let mut args = SqliteArguments::default();
-    args.add(player_id);
-    args.add(msg.time);
-    match msg.kind {
-        VelorenEventKind::Message { message, ty } => {
-            args.add(message.clone());
-            args.add(ty.to_string());
 
-            let id = sqlx::query_scalar_with::<_, u32, _>(
-                "
-                insert into messages (player_id, time, content, ty) values ($1, $2, $3, $4);
-                select last_insert_rowid() as id;
-                ",
-                args,
-            )
-            .fetch_one(&mut conn)
-            .await
-            .unwrap();
-
-            let _ = sx.send(NetworkEvent::Message(Message {
-                id,
-                player_id,
-                message,
-                time: msg.time,
-                ty,
-            }));
-        }
-        VelorenEventKind::Activity { online } => {
-            if online {
-                player_list.write().await.insert(player_id);
-            } else {
-                player_list.write().await.remove(&player_id);
-            }
-            args.add(online);
-            sqlx::query_with(
-                "
-                insert into activity (player_id, time, online) values ($1, $2, $3);
-            ",
-                args,
-            )
-            .execute(&mut conn)
-            .await
-            .unwrap();
-
-            let _ = sx.send(NetworkEvent::Activity(Activity { player_id, online }));
+/// Path of the logs database, as configured in `Rocket.toml`.
+fn database_path(figment: &rocket::figment::Figment) -> Result<PathBuf, rocket::figment::Error> {
+    figment.extract_inner::<PathBuf>("databases.logs.url")
+}
+
+/// The bot's ends of the channels set up by [`rocket`], started once the
+/// rest is running.
+struct Bot {
+    sx_db: tokio::sync::mpsc::Sender<VelorenEvent>,
+    commands: tokio::sync::mpsc::Receiver<BotCommand>,
+    status: tokio::sync::broadcast::Sender<NetworkEvent>,
+    state: BotState,
+}
+
+impl Bot {
+    /// Connects to the server in `VELOREN_SERVER` and records its chat until
+    /// `shutdown`.
+    fn start(self, metrics: Metrics, shutdown: rocket::Shutdown) -> JoinHandle<()> {
+        let veloren_server = veloren_client::addr::ConnectionArgs::Tcp {
+            hostname: std::env::var("VELOREN_SERVER")
+                .expect("No environment variable 'VELOREN_SERVER' found."),
+            prefer_ipv6: false,
+        };
+
+        let handle = run(
+            BotConfig {
+                addr: veloren_server,
+                username: env_key("VELOREN_USERNAME"),
+                password: env_key("VELOREN_PASSWORD"),
+                trusted_auth_server: env_key("VELOREN_TRUSTED_AUTH_SERVER"),
+            },
+            self.sx_db,
+            self.commands,
+            self.status,
+            self.state,
+            metrics,
+            std::sync::Arc::new(
+                rocket::tokio::runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap(),
+            ),
+            shutdown,
+        );
+
+        tracing::info!(
+            "Veloren-Common/Client version: {}",
+            *veloren_common::util::DISPLAY_VERSION_LONG
+        );
+        handle
+    }
+}
+
+/// Runs the web interface, and the bot along with it if `bot` is set.
+async fn serve(bot: bool) -> ExitCode {
+    let (rocket, client) = rocket(true);
+    let rocket = if bot {
+        rocket.attach(AdHoc::on_liftoff("Veloren client", |rocket| {
+            Box::pin(async move {
+                let metrics = rocket.state::<Metrics>().expect("metrics are managed");
+                client.start(metrics.clone(), rocket.shutdown());
+            })
+        }))
+    } else {
+        rocket
+    };
+    match rocket.launch().await {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
         }
     }
 }
 
-struct DbDrop {
-    rx: tokio::sync::mpsc::Receiver<VelorenEvent>,
-    sx: Sender<NetworkEvent>,
-    pool: Pool<Sqlite>,
-    player_list: PlayerList,
+/// Runs the bot and the background tasks without the web interface, until
+/// interrupted.
+async fn collect() -> ExitCode {
+    let (rocket, client) = rocket(false);
+    let rocket = match rocket.ignite().await {
+        Ok(rocket) => rocket,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let shutdown = rocket.shutdown();
+    rocket::tokio::task::spawn(async move {
+        match tokio::signal::ctrl_c().await {
+            Ok(()) => shutdown.notify(),
+            Err(e) => error!("Failed to listen for Ctrl-C: {}", e),
+        }
+    });
+
+    let metrics = rocket.state::<Metrics>().expect("metrics are managed");
+    match client.start(metrics.clone(), rocket.shutdown()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("The bot stopped: {}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
 
-impl Drop for DbDrop {
-    fn drop(&mut self) {
-        block_on(async {
-            while let Ok(Some(msg)) =
-                timeout(std::time::Duration::from_millis(100), self.rx.recv()).await
-            {
-                if let Ok(conn) = self.pool.acquire().await {
-                    handle_database_msg(conn, msg, &self.player_list, &self.sx).await
-                }
-            }
+#[rocket::main]
+async fn main() -> ExitCode {
+    // Loaded first, so every command sees the same environment.
+    let _ = kankyo::init();
+    let tracing_config = rocket::Config::figment()
+        .focus("tracing")
+        .extract::<TracingConfig>()
+        .unwrap_or_else(|e| {
+            eprintln!("Invalid tracing config: {e}");
+            TracingConfig::default()
         });
+    telemetry::init(&tracing_config);
+
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        None | Some("serve") => serve(true).await,
+        Some("web") => serve(false).await,
+        Some("collect") => collect().await,
+        Some(command) => cli::run(command, args).await,
     }
 }
 
-#[launch]
-async fn rocket() -> _ {
-    kankyo::init().unwrap();
+/// Sets up the database, ingestion and the background tasks, along with the
+/// web interface if `web` is set. The bot is left to the caller.
+fn rocket(web: bool) -> (Rocket<Build>, Bot) {
     let (sx_db, rx_db) = tokio::sync::mpsc::channel::<VelorenEvent>(256);
     let (sx, rx) = channel::<NetworkEvent>(256);
+    let (sx_bot, rx_bot) = tokio::sync::mpsc::channel::<BotCommand>(64);
     let player_list = PlayerList::default();
-    rocket::build()
+    let metrics = Metrics::new(player_list.clone(), sx_db.downgrade());
+    let rule_set = RuleSet::default();
+    let watchlist = Watchlist::default();
+    let bot_state = BotState::default();
+    let webhook_set = WebhookSet::default();
+    let rx_webhooks = rx.resubscribe();
+    let rx_irc = rx.resubscribe();
+    let sx_irc = sx_bot.clone();
+    let rx_websocket = rx.resubscribe();
+    let sx_websocket = sx_bot.clone();
+    let sx_status = sx.clone();
+    let rocket = rocket::build()
         .manage(rx)
+        .manage(sx.clone())
         .manage(player_list.clone())
+        .manage(metrics)
+        .manage(rule_set.clone())
+        .manage(watchlist.clone())
+        .manage(bot_state.clone())
+        .manage(webhook_set.clone())
+        .attach(RequestTrace::default())
         .attach(Db::init())
         .attach(AdHoc::try_on_ignite("Logs db migrations", run_migrations))
+        .attach(AdHoc::try_on_ignite("Storage", |rocket| async {
+            let store = match Db::fetch(&rocket) {
+                Some(pool) => store::open(pool.0.clone()),
+                None => return Err(rocket),
+            };
+            let store: Store = match rocket.state::<Metrics>() {
+                Some(metrics) => Arc::new(TimedStore::new(store, metrics.clone())),
+                None => return Err(rocket),
+            };
+
+            Ok(rocket.manage(store))
+        }))
         .attach(AdHoc::try_on_ignite(
             "Route through database",
             |rocket| async {
@@ -564,80 +823,241 @@ async fn rocket() -> _ {
                     Some(pool) => pool.0.clone(),
                     None => return Err(rocket),
                 };
+                let spam_config = match rocket.figment().focus("spam").extract::<SpamConfig>() {
+                    Ok(config) => config,
+                    Err(e) => {
+                        error!("Invalid spam config: {}", e);
+                        return Err(rocket);
+                    }
+                };
+                let ingest_config = match rocket
+                    .figment()
+                    .focus("ingestion")
+                    .extract::<IngestConfig>()
+                {
+                    Ok(config) => config,
+                    Err(e) => {
+                        error!("Invalid ingestion config: {}", e);
+                        return Err(rocket);
+                    }
+                };
+                let (store, metrics) = match (rocket.state::<Store>(), rocket.state::<Metrics>()) {
+                    (Some(store), Some(metrics)) => (store.clone(), metrics.clone()),
+                    _ => return Err(rocket),
+                };
+                *rule_set.write().await = match rules::load_rules(&pool).await {
+                    Ok(rules) => rules,
+                    Err(e) => {
+                        error!("Failed to load rules: {}", e);
+                        return Err(rocket);
+                    }
+                };
+                *watchlist.write().await = match rules::load_watchlist(&pool).await {
+                    Ok(watchlist) => watchlist,
+                    Err(e) => {
+                        error!("Failed to load the watchlist: {}", e);
+                        return Err(rocket);
+                    }
+                };
 
-                rocket::tokio::task::spawn(async move {
-                    let mut db = DbDrop {
-                        rx: rx_db,
-                        pool,
+                rocket::tokio::task::spawn(ingest::run(DbDrop {
+                    rx: rx_db,
+                    state: Ingestion {
+                        store,
                         player_list,
+                        spam: SpamDetector::new(spam_config),
+                        rules: rule_set,
+                        rates: RateTracker::default(),
+                        bot: sx_bot,
                         sx,
-                    };
-                    loop {
-                        match db.rx.recv().await {
-                            Some(msg) => {
-                                if let Ok(conn) = db.pool.acquire().await {
-                                    handle_database_msg(conn, msg, &db.player_list, &db.sx).await
-                                }
-                            }
-                            None => {
-                                println!("No more messages");
-                                break;
-                            }
-                        }
-                    }
-                });
+                        metrics,
+                    },
+                    config: ingest_config,
+                }));
 
                 Ok(rocket)
             },
         ))
-        .attach(AdHoc::on_liftoff("Veloren client", |rocket| {
-            Box::pin(async move {
-                let veloren_server = veloren_client::addr::ConnectionArgs::Tcp {
-                    hostname: std::env::var("VELOREN_SERVER")
-                        .expect("No environment variable 'VELOREN_SERVER' found."),
-                    prefer_ipv6: false,
-                };
+        .attach(AdHoc::try_on_ignite("Webhooks", |rocket| async {
+            let pool = match Db::fetch(&rocket) {
+                Some(pool) => pool.0.clone(),
+                None => return Err(rocket),
+            };
+            let config = match rocket.figment().focus("webhooks").extract::<WebhookConfig>() {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("Invalid webhooks config: {}", e);
+                    return Err(rocket);
+                }
+            };
+            *webhook_set.write().await = match webhooks::load_webhooks(&pool).await {
+                Ok(webhooks) => webhooks,
+                Err(e) => {
+                    error!("Failed to load webhooks: {}", e);
+                    return Err(rocket);
+                }
+            };
 
-                let veloren_username = env_key("VELOREN_USERNAME");
-                let veloren_password = env_key("VELOREN_PASSWORD");
-                let trusted_auth_server = env_key("VELOREN_TRUSTED_AUTH_SERVER");
-                run(
-                    veloren_server,
-                    veloren_username,
-                    veloren_password,
-                    trusted_auth_server,
-                    sx_db,
-                    std::sync::Arc::new(
-                        rocket::tokio::runtime::Builder::new_multi_thread()
-                            .enable_all()
-                            .build()
-                            .unwrap(),
-                    ),
-                    rocket.shutdown(),
-                );
-
-                rocket::info!(
-                    "Veloren-Common/Client version: {}",
-                    *veloren_common::util::DISPLAY_VERSION_LONG
-                );
-            })
+            rocket::tokio::task::spawn(webhooks::run(rx_webhooks, pool, webhook_set, config));
+
+            Ok(rocket)
+        }))
+        .attach(AdHoc::try_on_ignite("Backups", |rocket| async {
+            let config = match rocket.figment().focus("backups").extract::<BackupConfig>() {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("Invalid backups config: {}", e);
+                    return Err(rocket);
+                }
+            };
+            let database = match database_path(rocket.figment()) {
+                Ok(path) => path,
+                Err(e) => {
+                    error!("Invalid database config: {}", e);
+                    return Err(rocket);
+                }
+            };
+            let lock = match DatabaseLock::shared(&database) {
+                Ok(lock) => lock,
+                Err(e) => {
+                    error!("Failed to lock {}: {}", database.display(), e);
+                    return Err(rocket);
+                }
+            };
+            if config.enabled {
+                rocket::tokio::task::spawn(backup::run(database.clone(), config.clone()));
+            }
+
+            Ok(rocket.manage(Backups {
+                database,
+                config,
+                lock,
+            }))
+        }))
+        .attach(AdHoc::try_on_ignite("Retention", |rocket| async {
+            let config = match rocket.figment().focus("retention").extract::<RetentionConfig>() {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("Invalid retention config: {}", e);
+                    return Err(rocket);
+                }
+            };
+            if !config.enabled {
+                return Ok(rocket);
+            }
+            let store = match rocket.state::<Store>() {
+                Some(store) => store.clone(),
+                None => return Err(rocket),
+            };
+
+            rocket::tokio::task::spawn(retention::run(store, config));
+
+            Ok(rocket)
+        }))
+        .attach(AdHoc::try_on_ignite("Health checks", |rocket| async {
+            match rocket.figment().focus("health").extract::<HealthConfig>() {
+                Ok(config) => Ok(rocket.manage(config)),
+                Err(e) => {
+                    error!("Invalid health config: {}", e);
+                    Err(rocket)
+                }
+            }
+        }))
+        .attach(AdHoc::try_on_ignite("IRC bridge", |rocket| async {
+            let config = match rocket.figment().focus("irc").extract::<IrcConfig>() {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("Invalid IRC config: {}", e);
+                    return Err(rocket);
+                }
+            };
+            if !config.enabled {
+                return Ok(rocket);
+            }
+            let store = match rocket.state::<Store>() {
+                Some(store) => store.clone(),
+                None => return Err(rocket),
+            };
+
+            rocket::tokio::task::spawn(irc::run(
+                config,
+                std::env::var("VELOREN_USERNAME").ok(),
+                rx_irc,
+                sx_irc,
+                store,
+            ));
+
+            Ok(rocket)
+        }));
+    let bot = Bot {
+        sx_db,
+        commands: rx_bot,
+        status: sx_status,
+        state: bot_state,
+    };
+    if !web {
+        return (rocket, bot);
+    }
+
+    let rocket = rocket
+        .attach(AdHoc::try_on_ignite("WebSocket", |rocket| async {
+            let config = match rocket.figment().focus("websocket").extract::<WebSocketConfig>() {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("Invalid WebSocket config: {}", e);
+                    return Err(rocket);
+                }
+            };
+            if !config.enabled {
+                return Ok(rocket);
+            }
+            let (store, watchlist) = match (rocket.state::<Store>(), rocket.state::<Watchlist>()) {
+                (Some(store), Some(watchlist)) => (store.clone(), watchlist.clone()),
+                _ => return Err(rocket),
+            };
+
+            rocket::tokio::task::spawn(websocket::run(
+                config,
+                rx_websocket,
+                store,
+                watchlist,
+                sx_websocket,
+            ));
+
+            Ok(rocket)
         }))
         .attach(Template::custom(|engine| {
             customize_hbs(&mut engine.handlebars);
         }))
         .register("/", catchers!(not_found))
+        .register("/api", error::api_catchers())
         .mount("/", routes![index, user_page])
+        .mount("/", health::routes())
+        .mount("/", metrics::routes())
+        .mount("/", messages::page_routes())
+        .mount("/", rules::page_routes())
+        .mount("/", tickets::page_routes())
+        .mount("/", webhooks::page_routes())
         .mount(
             "/api",
             routes![
                 query_players,
-                events,
                 player_alias,
-                messages_before,
-                messages_after,
                 query_messages,
                 player_list
             ],
         )
-        .mount("/static", FileServer::from(relative!("static")))
+        .mount("/api", backup::api_routes())
+        .mount("/api", events::api_routes())
+        .mount("/api", export::api_routes())
+        .mount("/api", messages::api_routes())
+        .mount("/api", player_data::api_routes())
+        .mount("/api", retention::api_routes())
+        .mount("/api", rules::api_routes())
+        .mount("/api", reports::api_routes())
+        .mount("/api", tickets::api_routes())
+        .mount("/api", webhooks::api_routes())
+        .mount("/api/v1", v1::api_routes())
+        .mount("/static", FileServer::from(relative!("static")));
+    (rocket, bot)
 }
@@ -0,0 +1,377 @@
+use std::{fmt::Display, str::FromStr};
+
+use rocket::{
+    serde::json::{self, Json},
+    Route, State,
+};
+use rocket_dyn_templates::Template;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    error::{ApiError, ApiResult},
+    store::Store,
+    Message, MessageFilter, MAX_PER_PAGE,
+};
+
+/// Which way a listing walks through the chat log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize, FromFormField, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Newest first, towards the start of the log.
+    Older,
+    /// Oldest first, towards the latest message.
+    Newer,
+}
+
+impl Direction {
+    fn reverse(self) -> Self {
+        match self {
+            Direction::Older => Direction::Newer,
+            Direction::Newer => Direction::Older,
+        }
+    }
+}
+
+/// Position in a listing, handed to clients as an opaque string. Listings are
+/// keyed on ids, which only grow, so a cursor keeps pointing at the same place
+/// while new rows arrive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    /// Last row seen, the next page starts right after it.
+    pub id: u32,
+    pub direction: Direction,
+    /// Filter and page size of the listing as JSON, so following the cursor
+    /// continues the same listing.
+    pub query: String,
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let direction = match self.direction {
+            Direction::Older => 'o',
+            Direction::Newer => 'n',
+        };
+        f.write_str(&hex::encode(format!(
+            "{direction}{}:{}",
+            self.id, self.query
+        )))
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = ApiError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || ApiError::BadRequest(format!("invalid cursor '{value}'"));
+        let raw = hex::decode(value).map_err(|_| invalid())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+        let direction = match raw.chars().next() {
+            Some('o') => Direction::Older,
+            Some('n') => Direction::Newer,
+            _ => return Err(invalid()),
+        };
+        let (id, query) = raw[1..].split_once(':').ok_or_else(invalid)?;
+        let id = id.parse().map_err(|_| invalid())?;
+        Ok(Cursor {
+            id,
+            direction,
+            query: query.to_string(),
+        })
+    }
+}
+
+impl Serialize for Cursor {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Page size of a listing, 50 unless given.
+pub fn page_size(limit: Option<u32>) -> u32 {
+    limit.unwrap_or(50).clamp(1, MAX_PER_PAGE)
+}
+
+/// What a listing lists, kept in its cursors.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Listing<F> {
+    pub filter: F,
+    pub limit: u32,
+}
+
+impl<F: Serialize> Listing<F> {
+    fn query(&self) -> String {
+        json::to_string(self).expect("listing serializes to JSON")
+    }
+}
+
+/// Where a listing starts and what it lists.
+pub struct Start<F> {
+    pub from: Option<u32>,
+    pub direction: Direction,
+    pub listing: Listing<F>,
+}
+
+/// Where a listing starts, from a previous `cursor` or from the message
+/// `from` in `direction`. A cursor continues the listing it came from with
+/// its direction, filter and page size. A filter or page size given along
+/// with it has to match the cursor's.
+pub fn start<F>(
+    cursor: Option<&str>,
+    from: Option<u32>,
+    direction: Option<Direction>,
+    filter: F,
+    limit: Option<u32>,
+) -> ApiResult<Start<F>>
+where
+    F: Default + PartialEq + DeserializeOwned,
+{
+    let Some(value) = cursor else {
+        return Ok(Start {
+            from,
+            direction: direction.unwrap_or(Direction::Older),
+            listing: Listing {
+                filter,
+                limit: page_size(limit),
+            },
+        });
+    };
+    let cursor = value.parse::<Cursor>()?;
+    let listing = json::from_str::<Listing<F>>(&cursor.query)
+        .map_err(|_| ApiError::BadRequest(format!("invalid cursor '{value}'")))?;
+    if (filter != F::default() && filter != listing.filter)
+        || limit.map_or(false, |limit| page_size(Some(limit)) != listing.limit)
+    {
+        return Err(ApiError::BadRequest(
+            "the cursor belongs to a listing with another filter or page size".to_string(),
+        ));
+    }
+    Ok(Start {
+        from: Some(cursor.id),
+        direction: cursor.direction,
+        listing,
+    })
+}
+
+/// Trims `rows`, fetched with one row more than the page size to tell whether
+/// there is another page, and returns the cursors to the next and previous
+/// pages.
+///
+/// The next cursor is always set for [`Direction::Newer`], to poll for rows
+/// that haven't arrived yet, and only set for [`Direction::Older`] while
+/// there is more to read.
+pub fn paginate<T, F: Serialize>(
+    rows: &mut Vec<T>,
+    id: impl Fn(&T) -> u32,
+    start: &Start<F>,
+) -> (Option<Cursor>, Option<Cursor>) {
+    let limit = start.listing.limit as usize;
+    let more = rows.len() > limit;
+    rows.truncate(limit);
+
+    let query = start.listing.query();
+    let next = match (start.direction, rows.last()) {
+        (Direction::Older, Some(last)) if more => Some(id(last)),
+        (Direction::Older, _) => None,
+        (Direction::Newer, Some(last)) => Some(id(last)),
+        (Direction::Newer, None) => start.from,
+    }
+    .map(|id| Cursor {
+        id,
+        direction: start.direction,
+        query: query.clone(),
+    });
+    let prev = rows.first().map(|first| Cursor {
+        id: id(first),
+        direction: start.direction.reverse(),
+        query,
+    });
+
+    (next, prev)
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    /// Continues a previous listing.
+    cursor: Option<String>,
+    /// Starts next to this message, for links into the log. Ignored with
+    /// `cursor`.
+    from: Option<u32>,
+    direction: Option<Direction>,
+    limit: Option<u32>,
+    #[serde(flatten)]
+    filter: MessageFilter,
+}
+
+#[derive(Serialize)]
+struct MessageList {
+    /// Messages in the order they were walked, newest first for `older`.
+    messages: Vec<Message>,
+    next: Option<Cursor>,
+    /// Walks back from the first message of this page.
+    prev: Option<Cursor>,
+}
+
+/// Lists messages matching the filter page by page. Without a cursor, `older`
+/// starts at the latest message and `newer` at the first one.
+#[post("/messages", data = "<query>")]
+async fn list_messages(
+    store: &State<Store>,
+    query: Json<ListQuery>,
+) -> ApiResult<Json<MessageList>> {
+    let query = query.into_inner();
+    let start = start(
+        query.cursor.as_deref(),
+        query.from,
+        query.direction,
+        query.filter,
+        query.limit,
+    )?;
+
+    let mut messages = store
+        .list_messages(
+            &start.listing.filter,
+            start.from,
+            start.direction,
+            start.listing.limit + 1,
+        )
+        .await?;
+    let (next, prev) = paginate(&mut messages, |message| message.id, &start);
+
+    Ok(Json(MessageList {
+        messages,
+        next,
+        prev,
+    }))
+}
+
+#[derive(Serialize)]
+struct MessageContext {
+    message: Message,
+    /// The message together with those around it, oldest first.
+    messages: Vec<Message>,
+    /// Continues towards older messages, unset at the start of the log.
+    older: Option<Cursor>,
+    /// Continues towards newer messages.
+    newer: Option<Cursor>,
+}
+
+/// Up to `before` messages sent before the message `id` and `after` messages
+/// sent after it. With `same_channel`, only messages of the same type.
+#[post("/messages/<id>/context?<before>&<after>&<same_channel>")]
+async fn message_context(
+    store: &State<Store>,
+    id: u32,
+    before: Option<u32>,
+    after: Option<u32>,
+    same_channel: Option<bool>,
+) -> ApiResult<Json<MessageContext>> {
+    let message = store
+        .message(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("message {id}")))?;
+    let filter = MessageFilter {
+        ty: same_channel.unwrap_or(false).then(|| message.ty.clone()),
+        ..Default::default()
+    };
+    let before = before.unwrap_or(25).min(MAX_PER_PAGE);
+    let after = after.unwrap_or(25).min(MAX_PER_PAGE);
+
+    let mut older = store
+        .list_messages(&filter, Some(id), Direction::Older, before + 1)
+        .await?;
+    let more = older.len() > before as usize;
+    older.truncate(before as usize);
+    let mut newer = store
+        .list_messages(&filter, Some(id), Direction::Newer, after + 1)
+        .await?;
+    newer.truncate(after as usize);
+
+    // Like the cursors of a listing with the default page size, but starting
+    // from the message itself when one side is empty.
+    let query = Listing {
+        filter,
+        limit: page_size(None),
+    }
+    .query();
+    let older_cursor = more.then(|| Cursor {
+        id: older.last().map_or(id, |message| message.id),
+        direction: Direction::Older,
+        query: query.clone(),
+    });
+    let newer_cursor = Some(Cursor {
+        id: newer.last().map_or(id, |message| message.id),
+        direction: Direction::Newer,
+        query,
+    });
+
+    let mut messages = older;
+    messages.reverse();
+    messages.push(message.clone());
+    messages.extend(newer);
+
+    Ok(Json(MessageContext {
+        message,
+        messages,
+        older: older_cursor,
+        newer: newer_cursor,
+    }))
+}
+
+/// Permalink to a message, shown in the live chat among those around it.
+#[get("/message/<id>")]
+async fn message_page(store: &State<Store>, id: u32) -> ApiResult<Template> {
+    #[derive(Serialize)]
+    struct Context {
+        message: Message,
+        alias: String,
+    }
+
+    let Some(message) = store.message(id).await? else {
+        return Ok(Template::render("message_not_found", ()));
+    };
+    let alias = store
+        .player_alias(message.player_id)
+        .await?
+        .unwrap_or_default();
+
+    Ok(Template::render("message", Context { message, alias }))
+}
+
+pub fn page_routes() -> Vec<Route> {
+    routes![message_page]
+}
+
+pub fn api_routes() -> Vec<Route> {
+    routes![list_messages, message_context]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(player_id: u32) -> MessageFilter {
+        MessageFilter {
+            player_id: Some(player_id),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn cursors_continue_their_listing() {
+        let first = start(None, None, None, filter(3), Some(20)).unwrap();
+        let mut rows = (1..=30).rev().collect::<Vec<u32>>();
+        let (next, _) = paginate(&mut rows, |id| *id, &first);
+        let next = next.unwrap().to_string();
+
+        let second =
+            start::<MessageFilter>(Some(&next), None, None, Default::default(), None).unwrap();
+        assert_eq!(second.from, Some(11));
+        assert_eq!(second.direction, Direction::Older);
+        assert_eq!(second.listing, first.listing);
+
+        assert!(start(Some(&next), None, None, filter(3), Some(20)).is_ok());
+        assert!(start(Some(&next), None, None, filter(4), None).is_err());
+        assert!(start(Some(&next), None, None, filter(3), Some(10)).is_err());
+    }
+}
@@ -0,0 +1,149 @@
+use std::time::Instant;
+
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use rocket::{http::ContentType, Route, State};
+use tokio::sync::mpsc::WeakSender;
+
+use crate::{
+    error::{ApiError, ApiResult},
+    MessageType, PlayerList, VelorenEvent,
+};
+
+/// The panel's own health, scraped by Prometheus from `/metrics`. Cheap to
+/// clone, clones share the same values.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    online_players: IntGauge,
+    ingest_queue_depth: IntGauge,
+    messages_ingested: IntCounterVec,
+    pub event_stream_lagged: IntCounter,
+    pub event_stream_skipped: IntCounter,
+    pub bot_tick_seconds: Histogram,
+    pub bot_tick_errors: IntCounter,
+    pub bot_reconnects: IntCounter,
+    db_query_seconds: HistogramVec,
+    player_list: PlayerList,
+    /// Weak, so the metrics don't keep ingestion running once the bot stops.
+    ingest_queue: WeakSender<VelorenEvent>,
+}
+
+impl Metrics {
+    pub fn new(player_list: PlayerList, ingest_queue: WeakSender<VelorenEvent>) -> Self {
+        let registry = Registry::new_custom(Some("veloren_mod_panel".to_string()), None)
+            .expect("valid metrics prefix");
+        let metrics = Metrics {
+            online_players: IntGauge::new("online_players", "Players currently online")
+                .expect("valid metric"),
+            ingest_queue_depth: IntGauge::new(
+                "ingest_queue_depth",
+                "Events waiting in the queue between the bot and ingestion",
+            )
+            .expect("valid metric"),
+            messages_ingested: IntCounterVec::new(
+                Opts::new(
+                    "messages_ingested_total",
+                    "Messages written to the chat log",
+                ),
+                &["ty"],
+            )
+            .expect("valid metric"),
+            event_stream_lagged: IntCounter::new(
+                "event_stream_lagged_total",
+                "Times a live event subscriber fell behind the broadcast channel",
+            )
+            .expect("valid metric"),
+            event_stream_skipped: IntCounter::new(
+                "event_stream_skipped_total",
+                "Events live event subscribers missed by falling behind",
+            )
+            .expect("valid metric"),
+            bot_tick_seconds: Histogram::with_opts(
+                HistogramOpts::new("bot_tick_seconds", "Time the bot spends in a client tick")
+                    .buckets(vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]),
+            )
+            .expect("valid metric"),
+            bot_tick_errors: IntCounter::new("bot_tick_errors_total", "Failed bot client ticks")
+                .expect("valid metric"),
+            bot_reconnects: IntCounter::new(
+                "bot_reconnects_total",
+                "Times the bot reconnected to the game server",
+            )
+            .expect("valid metric"),
+            db_query_seconds: HistogramVec::new(
+                HistogramOpts::new("db_query_seconds", "Latency of chat log queries"),
+                &["query"],
+            )
+            .expect("valid metric"),
+            registry,
+            player_list,
+            ingest_queue,
+        };
+
+        let collectors: [Box<dyn prometheus::core::Collector>; 9] = [
+            Box::new(metrics.online_players.clone()),
+            Box::new(metrics.ingest_queue_depth.clone()),
+            Box::new(metrics.messages_ingested.clone()),
+            Box::new(metrics.event_stream_lagged.clone()),
+            Box::new(metrics.event_stream_skipped.clone()),
+            Box::new(metrics.bot_tick_seconds.clone()),
+            Box::new(metrics.bot_tick_errors.clone()),
+            Box::new(metrics.bot_reconnects.clone()),
+            Box::new(metrics.db_query_seconds.clone()),
+        ];
+        for collector in collectors {
+            metrics
+                .registry
+                .register(collector)
+                .expect("metric names are unique");
+        }
+        metrics
+    }
+
+    pub fn message_ingested(&self, ty: &MessageType) {
+        self.messages_ingested
+            .with_label_values(&[&ty.to_string()])
+            .inc();
+    }
+
+    /// Records how long the chat log query `query` took since `start`.
+    pub fn query_done(&self, query: &str, start: Instant) {
+        self.db_query_seconds
+            .with_label_values(&[query])
+            .observe(start.elapsed().as_secs_f64());
+    }
+
+    /// Samples the gauges and renders everything in the Prometheus text
+    /// format.
+    async fn render(&self) -> ApiResult<String> {
+        self.online_players
+            .set(self.player_list.read().await.len() as i64);
+        let depth = match self.ingest_queue.upgrade() {
+            Some(queue) => queue.max_capacity() - queue.capacity(),
+            None => 0,
+        };
+        self.ingest_queue_depth.set(depth as i64);
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| ApiError::Internal(format!("failed to encode metrics: {e}")))?;
+        String::from_utf8(buffer)
+            .map_err(|e| ApiError::Internal(format!("failed to encode metrics: {e}")))
+    }
+}
+
+#[get("/metrics")]
+async fn metrics(metrics: &State<Metrics>) -> ApiResult<(ContentType, String)> {
+    Ok((
+        ContentType::new("text", "plain").with_params(("version", "0.0.4")),
+        metrics.render().await?,
+    ))
+}
+
+pub fn routes() -> Vec<Route> {
+    routes![metrics]
+}
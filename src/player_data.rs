@@ -0,0 +1,595 @@
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
+use rocket::{
+    http::{ContentType, Header},
+    serde::json::{self, Json},
+    Route, State,
+};
+use rocket_db_pools::Connection;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteConnection};
+
+use crate::{
+    error::{ApiError, ApiResult},
+    export::Export,
+    reports::Report,
+    rules::Watchlist,
+    store::Store,
+    Db, DbMessage, Message, PlayerList,
+};
+
+/// Statements run by [`ErasureMode::Delete`], in order, `$1` is the player id.
+const DELETE_STATEMENTS: &[&str] = &[
+    // Deliveries keep the serialized event, including its player ids and
+    // text, and ticket events carry the title and comments.
+    "delete from webhook_deliveries where json_extract(payload, '$.event.Message.player_id') = $1
+        or json_extract(payload, '$.event.Message.recipient_id') = $1
+        or json_extract(payload, '$.event.Activity.player_id') = $1
+        or json_extract(payload, '$.event.Alert.player_id') = $1
+        or json_extract(payload, '$.event.RuleFiring.player_id') = $1
+        or json_extract(payload, '$.event.Report.reporter_id') = $1
+        or json_extract(payload, '$.event.Report.reported_id') = $1
+        or json_extract(payload, '$.event.Ticket.ticket.id') in (
+            select ticket_id from ticket_players where player_id = $1
+            union select ticket_id from ticket_messages where message_id in (select id from messages where player_id = $1)
+            union select id from tickets where report_id in (select id from reports where reporter_id = $1 or reported_id = $1)
+        );",
+    // Comments on the player's tickets are about them, keep who wrote them.
+    "update ticket_comments set content = '(erased)' where ticket_id in (
+        select ticket_id from ticket_players where player_id = $1
+        union select ticket_id from ticket_messages where message_id in (select id from messages where player_id = $1)
+        union select id from tickets where report_id in (select id from reports where reporter_id = $1 or reported_id = $1)
+    );",
+    "delete from report_messages where message_id in (select id from messages where player_id = $1);",
+    "delete from ticket_messages where message_id in (select id from messages where player_id = $1);",
+    "delete from report_messages where report_id in (select id from reports where reporter_id = $1 or reported_id = $1);",
+    // The title of a ticket opened for a report carries the report's reason.
+    "update tickets set title = 'Report #' || report_id || ' (erased)', report_id = null where report_id in (select id from reports where reporter_id = $1 or reported_id = $1);",
+    "delete from reports where reporter_id = $1 or reported_id = $1;",
+    "delete from ticket_players where player_id = $1;",
+    "delete from rule_firings where player_id = $1;",
+    "delete from messages where player_id = $1;",
+    "update messages set recipient_id = null where recipient_id = $1;",
+    "update messages_archive set recipient_id = null where recipient_id = $1;",
+    "delete from messages_archive where player_id = $1;",
+    "delete from activity where player_id = $1;",
+    "delete from activity_archive where player_id = $1;",
+    "delete from quarantined_messages where player_id = $1;",
+    "delete from quarantined_activity where player_id = $1;",
+    "delete from player_aliases where player_id = $1;",
+    "delete from player_notes where player_id = $1;",
+    "delete from player_tags where player_id = $1;",
+    "delete from players where id = $1;",
+];
+
+/// Statements run by [`ErasureMode::Pseudonymize`], in order, `$1` is the
+/// player id.
+const PSEUDONYMIZE_STATEMENTS: &[&str] = &[
+    "update players set uuid = 'erased-' || id, alias = 'Erased #' || id where id = $1;",
+    "delete from player_aliases where player_id = $1;",
+    "delete from player_notes where player_id = $1;",
+    "delete from player_tags where player_id = $1;",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, FromFormField)]
+pub enum ErasureMode {
+    /// Removes the player and everything referencing them.
+    Delete,
+    /// Replaces the uuid and alias and drops alias history, notes and tags,
+    /// but keeps messages, activity and reports under the anonymous id.
+    Pseudonymize,
+}
+
+impl Display for ErasureMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ErasureMode::Delete => "Delete",
+            ErasureMode::Pseudonymize => "Pseudonymize",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+pub struct Note {
+    pub id: u32,
+    pub player_id: u32,
+    pub author: String,
+    pub content: String,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct Player {
+    id: u32,
+    uuid: String,
+    alias: String,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct Alias {
+    alias: String,
+    first_seen: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct ActivityEntry {
+    id: u32,
+    time: DateTime<Utc>,
+    online: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+pub struct Erasure {
+    id: u32,
+    player_id: u32,
+    mode: String,
+    operator: String,
+    reason: String,
+    affected_rows: u32,
+    time: DateTime<Utc>,
+}
+
+/// Records the alias a player was seen with, called for every ingested event.
+pub async fn record_alias(
+    conn: &mut SqliteConnection,
+    player_id: u32,
+    alias: &str,
+    time: DateTime<Utc>,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "
+        insert into player_aliases (player_id, alias, first_seen, last_seen) values ($1, $2, $3, $3)
+        on conflict (player_id, alias) do update set last_seen = excluded.last_seen;
+    ",
+    )
+    .bind(player_id)
+    .bind(alias)
+    .bind(time)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Everything stored about one player.
+pub struct PlayerData {
+    player: Player,
+    aliases: Vec<Alias>,
+    messages: Vec<Message>,
+    activity: Vec<ActivityEntry>,
+    notes: Vec<Note>,
+    tags: Vec<String>,
+    reports: Vec<Report>,
+}
+
+/// Loads everything stored about the player `id`, `None` if there is no such
+/// player.
+pub async fn load(conn: &mut SqliteConnection, id: u32) -> sqlx::Result<Option<PlayerData>> {
+    let Some(player) =
+        sqlx::query_as::<_, Player>("select id, uuid, alias from players where id = ?;")
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await?
+    else {
+        return Ok(None);
+    };
+    let aliases = sqlx::query_as::<_, Alias>(
+        "
+        select alias, first_seen, last_seen
+        from player_aliases
+        where player_id = ?
+        order by first_seen asc;
+    ",
+    )
+    .bind(id)
+    .fetch_all(&mut *conn)
+    .await?;
+    let messages = sqlx::query_as::<_, DbMessage>(
+        "
+        select id, player_id, time, content, ty, spam_score, recipient_id
+        from messages
+        where player_id = $1
+        union all
+        select id, player_id, time, content, ty, spam_score, recipient_id
+        from messages_archive
+        where player_id = $1
+        order by id asc;
+    ",
+    )
+    .bind(id)
+    .fetch_all(&mut *conn)
+    .await?
+    .into_iter()
+    .map(Message::try_from)
+    .collect::<sqlx::Result<Vec<_>>>()?;
+    let activity = sqlx::query_as::<_, ActivityEntry>(
+        "
+        select id, time, online
+        from activity
+        where player_id = $1
+        union all
+        select id, time, online
+        from activity_archive
+        where player_id = $1
+        order by id asc;
+    ",
+    )
+    .bind(id)
+    .fetch_all(&mut *conn)
+    .await?;
+    let notes = sqlx::query_as::<_, Note>(
+        "
+        select *
+        from player_notes
+        where player_id = ?
+        order by id asc;
+    ",
+    )
+    .bind(id)
+    .fetch_all(&mut *conn)
+    .await?;
+    let tags = sqlx::query_scalar::<_, String>("select tag from player_tags where player_id = ?;")
+        .bind(id)
+        .fetch_all(&mut *conn)
+        .await?;
+    let reports = sqlx::query_as::<_, Report>(
+        "
+        select *
+        from reports
+        where reporter_id = $1 or reported_id = $1
+        order by id asc;
+    ",
+    )
+    .bind(id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(Some(PlayerData {
+        player,
+        aliases,
+        messages,
+        activity,
+        notes,
+        tags,
+        reports,
+    }))
+}
+
+/// Erases the player `id` and records who did it and why, without any
+/// identifying data. `None` if there is no such player. Meant to run within a
+/// transaction.
+pub async fn erase(
+    conn: &mut SqliteConnection,
+    id: u32,
+    mode: ErasureMode,
+    operator: &str,
+    reason: &str,
+) -> sqlx::Result<Option<Erasure>> {
+    let exists = sqlx::query_scalar::<_, u32>("select id from players where id = ?;")
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    if exists.is_none() {
+        return Ok(None);
+    }
+
+    let statements = match mode {
+        ErasureMode::Delete => DELETE_STATEMENTS,
+        ErasureMode::Pseudonymize => PSEUDONYMIZE_STATEMENTS,
+    };
+    let mut affected_rows = 0;
+    for statement in statements {
+        affected_rows += sqlx::query(statement)
+            .bind(id)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected() as u32;
+    }
+    let time = Utc::now();
+    let erasure_id = sqlx::query_scalar::<_, u32>(
+        "
+        insert into erasures (player_id, mode, operator, reason, affected_rows, time) values ($1, $2, $3, $4, $5, $6);
+        select last_insert_rowid() as id;
+        ",
+    )
+    .bind(id)
+    .bind(mode.to_string())
+    .bind(operator)
+    .bind(reason)
+    .bind(affected_rows)
+    .bind(time)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(Some(Erasure {
+        id: erasure_id,
+        player_id: id,
+        mode: mode.to_string(),
+        operator: operator.to_string(),
+        reason: reason.to_string(),
+        affected_rows,
+        time,
+    }))
+}
+
+fn append_json<T: Serialize>(
+    archive: &mut tar::Builder<Vec<u8>>,
+    path: &str,
+    value: &T,
+) -> ApiResult<()> {
+    let data = json::to_pretty_string(value)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    archive.append_data(&mut header, path, data.as_bytes())?;
+    Ok(())
+}
+
+/// Everything stored about one player as a tar archive with one JSON file per
+/// kind of data.
+#[get("/players/<id>/export")]
+async fn export_player(store: &State<Store>, id: u32) -> ApiResult<Export<Vec<u8>>> {
+    let data = store
+        .player_data(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("player {id}")))?;
+
+    let dir = format!("player-{id}");
+    let mut archive = tar::Builder::new(Vec::new());
+    append_json(&mut archive, &format!("{dir}/player.json"), &data.player)?;
+    append_json(&mut archive, &format!("{dir}/aliases.json"), &data.aliases)?;
+    append_json(
+        &mut archive,
+        &format!("{dir}/messages.json"),
+        &data.messages,
+    )?;
+    append_json(
+        &mut archive,
+        &format!("{dir}/activity.json"),
+        &data.activity,
+    )?;
+    append_json(&mut archive, &format!("{dir}/notes.json"), &data.notes)?;
+    append_json(&mut archive, &format!("{dir}/tags.json"), &data.tags)?;
+    append_json(&mut archive, &format!("{dir}/reports.json"), &data.reports)?;
+
+    Ok(Export {
+        inner: archive.into_inner()?,
+        content_type: ContentType::new("application", "x-tar"),
+        disposition: Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"{dir}.tar\""),
+        ),
+    })
+}
+
+/// Erases a player's data in a single transaction and keeps an audit record
+/// of who did it and why, without any identifying data.
+#[post("/players/<id>/erase?<mode>&<operator>&<reason>")]
+async fn erase_player(
+    store: &State<Store>,
+    player_list: &State<PlayerList>,
+    watchlist: &State<Watchlist>,
+    id: u32,
+    mode: ErasureMode,
+    operator: &str,
+    reason: &str,
+) -> ApiResult<Json<Erasure>> {
+    let erasure = store
+        .erase_player(id, mode, operator, reason)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("player {id}")))?;
+
+    if mode == ErasureMode::Delete {
+        player_list.write().await.remove(&id);
+    }
+    // Both modes drop the player's tags.
+    watchlist.write().await.remove(&id);
+    tracing::info!(
+        "{operator} erased player {id} ({mode}), {} rows affected",
+        erasure.affected_rows
+    );
+
+    Ok(Json(erasure))
+}
+
+#[post("/erasures")]
+async fn erasures(mut db: Connection<Db>) -> ApiResult<Json<Vec<Erasure>>> {
+    let erasures = sqlx::query_as::<_, Erasure>(
+        "
+        select *
+        from erasures
+        order by id desc
+        limit 100;
+    ",
+    )
+    .fetch_all(&mut *db)
+    .await?;
+
+    Ok(Json(erasures))
+}
+
+#[post("/player_notes?<id>")]
+async fn player_notes(mut db: Connection<Db>, id: u32) -> ApiResult<Json<Vec<Note>>> {
+    let notes = sqlx::query_as::<_, Note>(
+        "
+        select *
+        from player_notes
+        where player_id = ?
+        order by id asc;
+    ",
+    )
+    .bind(id)
+    .fetch_all(&mut *db)
+    .await?;
+
+    Ok(Json(notes))
+}
+
+#[derive(Deserialize)]
+struct NewNote {
+    player_id: u32,
+    author: String,
+    content: String,
+}
+
+#[post("/player_notes/add", data = "<note>")]
+async fn add_player_note(mut db: Connection<Db>, note: Json<NewNote>) -> ApiResult<Json<Note>> {
+    let time = Utc::now();
+    let id = sqlx::query_scalar::<_, u32>(
+        "
+        insert into player_notes (player_id, author, content, time) values ($1, $2, $3, $4);
+        select last_insert_rowid() as id;
+        ",
+    )
+    .bind(note.player_id)
+    .bind(&note.author)
+    .bind(&note.content)
+    .bind(time)
+    .fetch_one(&mut *db)
+    .await?;
+
+    Ok(Json(Note {
+        id,
+        player_id: note.player_id,
+        author: note.author.clone(),
+        content: note.content.clone(),
+        time,
+    }))
+}
+
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        export_player,
+        erase_player,
+        erasures,
+        player_notes,
+        add_player_note
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::sqlite::SqlitePoolOptions;
+
+    use super::*;
+    use crate::{MessageType, NetworkEvent};
+
+    fn delivery(player_id: u32, text: &str) -> String {
+        let time = "2023-03-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let message = Message {
+            id: player_id,
+            player_id,
+            message: text.to_string(),
+            ty: MessageType::World,
+            time,
+            spam_score: 0.0,
+            recipient_id: None,
+        };
+        json::json!({
+            "kind": "Message",
+            "time": time,
+            "event": NetworkEvent::Message(message),
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn deleting_leaves_nothing_behind() {
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::migrate!("db/logs/migrations")
+            .run(&pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            "
+            insert into players (id, uuid, alias) values (1, 'alice', 'Alice'), (4242, 'mallory', 'Mallory');
+            insert into player_aliases (player_id, alias, first_seen, last_seen) values (4242, 'Mallory', '2023-03-01', '2023-03-01');
+            insert into player_notes (player_id, author, content, time) values (4242, 'op', 'Mallory griefs', '2023-03-01');
+            insert into player_tags (player_id, tag) values (4242, 'Mallory-watch');
+            insert into messages (id, player_id, time, content, ty, recipient_id) values
+                (1, 4242, '2023-03-01', 'griefing is fun', 'World', null),
+                (2, 1, '2023-03-01', 'hello', 'Tell', 4242);
+            insert into messages_archive (id, player_id, time, content, ty) values (3, 4242, '2023-02-01', 'griefing again', 'World');
+            insert into activity (player_id, time, online) values (4242, '2023-03-01', 1);
+            insert into activity_archive (id, player_id, time, online) values (9, 4242, '2023-02-01', 1);
+            insert into quarantined_messages (id, player_id, time, content, reason) values (4, 4242, '2023-03-01', 'griefing quarantined', 'unknown type');
+            insert into quarantined_activity (id, player_id, time, online, reason) values (5, 4242, '2023-03-01', null, 'missing online');
+            insert into reports (id, reporter_id, reported_id, reason, time) values (1, 1, 4242, 'griefing at spawn', '2023-03-01');
+            insert into report_messages (report_id, message_id) values (1, 1);
+            insert into tickets (id, title, status, report_id, created, updated) values
+                (1, 'Report #1: griefing at spawn', 'Open', 1, '2023-03-01', '2023-03-01'),
+                (2, 'Unrelated', 'Open', null, '2023-03-01', '2023-03-01');
+            insert into ticket_players (ticket_id, player_id) values (1, 4242);
+            insert into ticket_comments (ticket_id, author, content, time) values
+                (1, 'op', 'Mallory keeps griefing', '2023-03-01'),
+                (2, 'op', 'nothing to see', '2023-03-01');
+            insert into webhooks (id, url, secret, events, filter, enabled) values (1, 'http://localhost', 'secret', '[]', '{}', 1);
+            ",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        for (player_id, text) in [(4242, "griefing is fun"), (1, "hello")] {
+            sqlx::query(
+                "insert into webhook_deliveries (webhook_id, event, payload, attempts, delivered, time) values (1, '\"Message\"', $1, 1, 1, '2023-03-01');",
+            )
+            .bind(delivery(player_id, text))
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let mut tx = pool.begin().await.unwrap();
+        let erasure = erase(&mut tx, 4242, ErasureMode::Delete, "op", "request")
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        assert!(erasure.is_some());
+
+        let tables = sqlx::query_scalar::<_, String>(
+            "select name from sqlite_master where type = 'table' and name not in ('erasures', 'sqlite_sequence', '_sqlx_migrations');",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        for table in tables {
+            let columns =
+                sqlx::query_scalar::<_, String>("select name from pragma_table_info($1);")
+                    .bind(&table)
+                    .fetch_all(&pool)
+                    .await
+                    .unwrap();
+            let condition = columns
+                .iter()
+                .map(|column| {
+                    format!(
+                        "instr(\"{column}\", '4242') or instr(\"{column}\", 'Mallory') or instr(\"{column}\", 'griefing')"
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" or ");
+            let left = sqlx::query_scalar::<_, i64>(&format!(
+                "select count(*) from {table} where {condition};"
+            ))
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            assert_eq!(left, 0, "{table} still refers to the erased player");
+        }
+
+        // Everything about other players stays.
+        let kept = sqlx::query_scalar::<_, i64>(
+            "select (select count(*) from messages) + (select count(*) from webhook_deliveries) + (select count(*) from ticket_comments);",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(kept, 4);
+    }
+}
@@ -0,0 +1,157 @@
+use chrono::{DateTime, Utc};
+use rocket::{serde::json::Json, Route, State};
+use rocket_db_pools::Connection;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteConnection};
+use veloren_common::uuid::Uuid;
+
+use crate::{
+    error::{decode_rows, ApiResult},
+    store::Store,
+    Db, DbMessage, Message,
+};
+
+/// How many messages around a report are linked to it as context.
+const CONTEXT_MESSAGES: u32 = 25;
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+pub struct Report {
+    pub id: u32,
+    pub reporter_id: u32,
+    pub reported_id: u32,
+    pub reason: String,
+    pub time: DateTime<Utc>,
+}
+
+/// Finds the reported player, by uuid if they were online and by their
+/// latest alias otherwise.
+pub async fn find_reported(
+    conn: &mut SqliteConnection,
+    alias: &str,
+    uuid: Option<Uuid>,
+) -> sqlx::Result<Option<u32>> {
+    if let Some(uuid) = uuid {
+        sqlx::query_scalar::<_, u32>("select id from players where uuid = ?;")
+            .bind(uuid.to_string())
+            .fetch_optional(&mut *conn)
+            .await
+    } else {
+        sqlx::query_scalar::<_, u32>(
+            "
+            select id
+            from players
+            where alias = ? collate nocase
+            order by id desc
+            limit 1;
+        ",
+        )
+        .bind(alias)
+        .fetch_optional(&mut *conn)
+        .await
+    }
+}
+
+/// Files a report and links the latest world chat and messages of both
+/// players to it.
+pub async fn file_report(
+    conn: &mut SqliteConnection,
+    reporter_id: u32,
+    reported_id: u32,
+    reason: &str,
+    time: DateTime<Utc>,
+) -> sqlx::Result<Report> {
+    let id = sqlx::query_scalar::<_, u32>(
+        "
+        insert into reports (reporter_id, reported_id, reason, time) values ($1, $2, $3, $4);
+        select last_insert_rowid() as id;
+        ",
+    )
+    .bind(reporter_id)
+    .bind(reported_id)
+    .bind(reason)
+    .bind(time)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "
+        insert into report_messages (report_id, message_id)
+        select $1, id
+        from messages
+        where player_id = $2 or player_id = $3 or ty = 'World'
+        order by id desc
+        limit $4;
+    ",
+    )
+    .bind(id)
+    .bind(reporter_id)
+    .bind(reported_id)
+    .bind(CONTEXT_MESSAGES)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(Report {
+        id,
+        reporter_id,
+        reported_id,
+        reason: reason.to_string(),
+        time,
+    })
+}
+
+#[post("/reports?<player_id>")]
+async fn reports(mut db: Connection<Db>, player_id: Option<u32>) -> ApiResult<Json<Vec<Report>>> {
+    let reports = if let Some(player_id) = player_id {
+        sqlx::query_as::<_, Report>(
+            "
+            select *
+            from reports
+            where reporter_id = $1 or reported_id = $1
+            order by id desc
+            limit 100;
+        ",
+        )
+        .bind(player_id)
+        .fetch_all(&mut *db)
+    } else {
+        sqlx::query_as::<_, Report>(
+            "
+            select *
+            from reports
+            order by id desc
+            limit 100;
+        ",
+        )
+        .fetch_all(&mut *db)
+    }
+    .await?;
+
+    Ok(Json(reports))
+}
+
+/// Messages linked to the report `id` as context, oldest first.
+pub async fn linked_messages(conn: &mut SqliteConnection, id: u32) -> sqlx::Result<Vec<Message>> {
+    let messages = sqlx::query_as::<_, DbMessage>(
+        "
+        select messages.*
+        from report_messages
+        join messages on messages.id = report_messages.message_id
+        where report_messages.report_id = ?
+        order by messages.id asc;
+    ",
+    )
+    .bind(id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(decode_rows("message", messages, |message| message.id))
+}
+
+#[post("/report_messages?<id>")]
+async fn report_messages(store: &State<Store>, id: u32) -> ApiResult<Json<Vec<Message>>> {
+    Ok(Json(store.report_messages(id).await?))
+}
+
+pub fn api_routes() -> Vec<Route> {
+    routes![reports, report_messages]
+}
@@ -0,0 +1,294 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+use rocket::{
+    serde::json::{self, Json},
+    Route, State,
+};
+use rocket_db_pools::Connection;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteConnection};
+
+use crate::{
+    error::{decode_rows, ApiError, ApiResult},
+    store::Store,
+    Db, MessageType,
+};
+
+/// Rows of players on legal hold and messages attached to reports or tickets
+/// are never pruned.
+const PRUNABLE_MESSAGE: &str = "
+    player_id not in (select id from players where legal_hold)
+    and id not in (select message_id from report_messages)
+    and id not in (select message_id from ticket_messages)
+";
+
+/// Activity is pruned by whole sessions, an entry goes once the player logged
+/// out after it and before `$1`. The login of a session that is still open, or
+/// ends after `$1`, stays with its logout.
+const PRUNABLE_ACTIVITY: &str = "
+    time < $1
+    and player_id not in (select id from players where legal_hold)
+    and exists (
+        select 1
+        from activity as logout
+        where logout.player_id = activity.player_id
+            and not logout.online
+            and logout.id >= activity.id
+            and logout.time < $1
+    )
+";
+
+/// Retention settings, read from the `retention` table in `Rocket.toml`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// Move pruned rows to the `*_archive` tables instead of deleting them.
+    pub archive: bool,
+    /// Days messages of each type are kept, types without an entry are kept
+    /// forever.
+    pub messages: HashMap<MessageType, u32>,
+    /// Days activity is kept, forever if unset.
+    pub activity: Option<u32>,
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 3600,
+            archive: true,
+            messages: HashMap::new(),
+            activity: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PruneRun {
+    pub id: u32,
+    pub time: DateTime<Utc>,
+    pub archived: bool,
+    pub messages: u32,
+    pub activity: u32,
+    /// Pruned messages per type.
+    pub details: HashMap<MessageType, u32>,
+}
+
+#[derive(FromRow)]
+struct DbPruneRun {
+    id: u32,
+    time: DateTime<Utc>,
+    archived: bool,
+    messages: u32,
+    activity: u32,
+    details: String,
+}
+
+impl TryFrom<DbPruneRun> for PruneRun {
+    type Error = json::serde_json::Error;
+
+    fn try_from(run: DbPruneRun) -> Result<Self, Self::Error> {
+        Ok(PruneRun {
+            id: run.id,
+            time: run.time,
+            archived: run.archived,
+            messages: run.messages,
+            activity: run.activity,
+            details: json::from_str(&run.details)?,
+        })
+    }
+}
+
+async fn prune_messages(
+    conn: &mut SqliteConnection,
+    ty: &MessageType,
+    before: DateTime<Utc>,
+    archive: bool,
+) -> sqlx::Result<u32> {
+    if archive {
+        sqlx::query(&format!(
+            "
+            insert into messages_archive (id, player_id, time, content, ty, spam_score, recipient_id)
+            select id, player_id, time, content, ty, spam_score, recipient_id
+            from messages
+            where ty = $1 and time < $2 and {PRUNABLE_MESSAGE};
+        "
+        ))
+        .bind(ty.to_string())
+        .bind(before)
+        .execute(&mut *conn)
+        .await?;
+    } else {
+        // Archived messages keep their ids, so firings stay resolvable.
+        sqlx::query(&format!(
+            "
+            delete from rule_firings
+            where message_id in (select id from messages where ty = $1 and time < $2 and {PRUNABLE_MESSAGE});
+        "
+        ))
+        .bind(ty.to_string())
+        .bind(before)
+        .execute(&mut *conn)
+        .await?;
+    }
+    let pruned = sqlx::query(&format!(
+        "
+        delete from messages
+        where ty = $1 and time < $2 and {PRUNABLE_MESSAGE};
+    "
+    ))
+    .bind(ty.to_string())
+    .bind(before)
+    .execute(&mut *conn)
+    .await?;
+    Ok(pruned.rows_affected() as u32)
+}
+
+async fn prune_activity(
+    conn: &mut SqliteConnection,
+    before: DateTime<Utc>,
+    archive: bool,
+) -> sqlx::Result<u32> {
+    if archive {
+        sqlx::query(&format!(
+            "
+            insert into activity_archive (id, player_id, time, online)
+            select id, player_id, time, online
+            from activity
+            where {PRUNABLE_ACTIVITY};
+        "
+        ))
+        .bind(before)
+        .execute(&mut *conn)
+        .await?;
+    }
+    let pruned = sqlx::query(&format!(
+        "
+        delete from activity
+        where {PRUNABLE_ACTIVITY};
+    "
+    ))
+    .bind(before)
+    .execute(&mut *conn)
+    .await?;
+    Ok(pruned.rows_affected() as u32)
+}
+
+/// Applies the retention policies once and records what was pruned. Meant to
+/// run within a transaction.
+pub async fn prune(
+    conn: &mut SqliteConnection,
+    config: &RetentionConfig,
+) -> sqlx::Result<PruneRun> {
+    let now = Utc::now();
+
+    let mut details = HashMap::new();
+    for (ty, days) in &config.messages {
+        let before = now - chrono::Duration::days(*days as i64);
+        let pruned = prune_messages(conn, ty, before, config.archive).await?;
+        details.insert(ty.clone(), pruned);
+    }
+    let activity = match config.activity {
+        Some(days) => {
+            let before = now - chrono::Duration::days(days as i64);
+            prune_activity(conn, before, config.archive).await?
+        }
+        None => 0,
+    };
+    let messages = details.values().sum();
+    let encoded = json::to_string(&details).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+
+    let id = sqlx::query_scalar::<_, u32>(
+        "
+        insert into prune_runs (time, archived, messages, activity, details) values ($1, $2, $3, $4, $5);
+        select last_insert_rowid() as id;
+        ",
+    )
+    .bind(now)
+    .bind(config.archive)
+    .bind(messages)
+    .bind(activity)
+    .bind(encoded)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(PruneRun {
+        id,
+        time: now,
+        archived: config.archive,
+        messages,
+        activity,
+        details,
+    })
+}
+
+/// Prunes every `interval_secs` for as long as the server runs.
+pub async fn run(store: Store, config: RetentionConfig) {
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(60)));
+    loop {
+        interval.tick().await;
+        match store.prune(&config).await {
+            Ok(run) if run.messages > 0 || run.activity > 0 => tracing::info!(
+                "Pruned {} messages and {} activity entries",
+                run.messages,
+                run.activity
+            ),
+            Ok(_) => {}
+            Err(e) => tracing::error!("Pruning failed: {}", e),
+        }
+    }
+}
+
+/// Sets the legal hold flag of the player `id` if `hold` is given and returns
+/// it, `None` if there is no such player.
+pub async fn set_legal_hold(
+    conn: &mut SqliteConnection,
+    id: u32,
+    hold: Option<bool>,
+) -> sqlx::Result<Option<bool>> {
+    if let Some(hold) = hold {
+        sqlx::query("update players set legal_hold = $1 where id = $2;")
+            .bind(hold)
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    sqlx::query_scalar::<_, bool>("select legal_hold from players where id = ?;")
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await
+}
+
+/// Sets the legal hold flag if `hold` is given and returns it, players on
+/// hold are exempt from pruning.
+#[post("/players/<id>/legal_hold?<hold>")]
+async fn legal_hold(store: &State<Store>, id: u32, hold: Option<bool>) -> ApiResult<Json<bool>> {
+    store
+        .legal_hold(id, hold)
+        .await?
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found(format!("player {id}")))
+}
+
+#[post("/prune_runs")]
+async fn prune_runs(mut db: Connection<Db>) -> ApiResult<Json<Vec<PruneRun>>> {
+    let runs = sqlx::query_as::<_, DbPruneRun>(
+        "
+        select *
+        from prune_runs
+        order by id desc
+        limit 100;
+    ",
+    )
+    .fetch_all(&mut *db)
+    .await?;
+
+    Ok(Json(decode_rows("prune run", runs, |run| run.id)))
+}
+
+pub fn api_routes() -> Vec<Route> {
+    routes![legal_hold, prune_runs]
+}
@@ -0,0 +1,900 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Duration, Utc};
+use regex::Regex;
+use rocket::{
+    serde::json::{self, Json},
+    Route, State,
+};
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::Template;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteConnection, SqliteExecutor};
+use tokio::sync::RwLock;
+
+use crate::{
+    error::{decode_rows, ApiError, ApiResult},
+    veloren::BotCommand,
+    Db, MessageType,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Condition {
+    /// Message contains `text`, ignoring case.
+    Contains {
+        text: String,
+    },
+    /// Message matches the regular expression `pattern`.
+    Matches {
+        #[serde(with = "serde_regex")]
+        pattern: Regex,
+    },
+    MessageType {
+        ty: MessageType,
+    },
+    HasTag {
+        tag: String,
+    },
+    /// Player was first seen less than `secs` seconds ago.
+    AccountAgeBelow {
+        secs: i64,
+    },
+    /// Player sent more than `messages` messages in the last `secs` seconds.
+    RateAbove {
+        messages: usize,
+        secs: i64,
+    },
+    SpamScoreAbove {
+        score: f32,
+    },
+    Not {
+        condition: Box<Condition>,
+    },
+    Any {
+        conditions: Vec<Condition>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Action {
+    Alert,
+    /// Mutes the player through the bot.
+    Mute {
+        secs: u64,
+        reason: String,
+    },
+    /// Sends the player a tell from the bot.
+    Warn {
+        message: String,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rule {
+    #[serde(default)]
+    pub id: Option<u32>,
+    pub name: String,
+    pub enabled: bool,
+    /// Only record what the rule would have done.
+    pub dry_run: bool,
+    /// All conditions have to match for the rule to fire.
+    pub conditions: Vec<Condition>,
+    pub actions: Vec<Action>,
+}
+
+pub type RuleSet = Arc<RwLock<Vec<Rule>>>;
+
+/// Players tagged with this are on the watchlist.
+pub const WATCHLIST_TAG: &str = "watchlist";
+
+/// Ids of the players on the watchlist.
+pub type Watchlist = Arc<RwLock<HashSet<u32>>>;
+
+/// Everything a rule can look at when a message comes in.
+pub struct RuleInput<'a> {
+    pub time: DateTime<Utc>,
+    pub message: &'a str,
+    pub ty: &'a MessageType,
+    pub spam_score: f32,
+    pub tags: &'a [String],
+    pub account_age: Duration,
+    pub recent: &'a VecDeque<DateTime<Utc>>,
+}
+
+impl Condition {
+    pub fn matches(&self, input: &RuleInput) -> bool {
+        match self {
+            Condition::Contains { text } => {
+                input.message.to_lowercase().contains(&text.to_lowercase())
+            }
+            Condition::Matches { pattern } => pattern.is_match(input.message),
+            Condition::MessageType { ty } => ty == input.ty,
+            Condition::HasTag { tag } => input.tags.iter().any(|t| t == tag),
+            Condition::AccountAgeBelow { secs } => input.account_age < Duration::seconds(*secs),
+            Condition::RateAbove { messages, secs } => {
+                let since = input.time - Duration::seconds(*secs);
+                input.recent.iter().filter(|t| **t >= since).count() > *messages
+            }
+            Condition::SpamScoreAbove { score } => input.spam_score > *score,
+            Condition::Not { condition } => !condition.matches(input),
+            Condition::Any { conditions } => conditions.iter().any(|c| c.matches(input)),
+        }
+    }
+
+    fn rate_window(&self) -> i64 {
+        match self {
+            Condition::RateAbove { secs, .. } => *secs,
+            Condition::Not { condition } => condition.rate_window(),
+            Condition::Any { conditions } => conditions
+                .iter()
+                .map(Condition::rate_window)
+                .max()
+                .unwrap_or(0),
+            _ => 0,
+        }
+    }
+}
+
+/// What carrying out an action amounts to.
+#[derive(Debug)]
+pub enum Effect {
+    Alert,
+    Command(BotCommand),
+}
+
+impl Rule {
+    pub fn matches(&self, input: &RuleInput) -> bool {
+        self.enabled
+            && !self.conditions.is_empty()
+            && self.conditions.iter().all(|c| c.matches(input))
+    }
+
+    /// What firing on a message of `player_alias` does, nothing for a dry run.
+    pub fn effects(&self, player_alias: &str) -> Vec<Effect> {
+        if self.dry_run {
+            return Vec::new();
+        }
+        self.actions
+            .iter()
+            .map(|action| match action {
+                Action::Alert => Effect::Alert,
+                Action::Mute { secs, reason } => Effect::Command(BotCommand::Mute {
+                    alias: player_alias.to_string(),
+                    secs: *secs,
+                    reason: reason.clone(),
+                }),
+                Action::Warn { message } => Effect::Command(BotCommand::Tell {
+                    alias: player_alias.to_string(),
+                    message: message.clone(),
+                }),
+            })
+            .collect()
+    }
+
+    /// How far back the rule looks at message rates, in seconds.
+    pub fn rate_window(&self) -> i64 {
+        self.conditions
+            .iter()
+            .map(Condition::rate_window)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Recent message times per player, used by [`Condition::RateAbove`].
+#[derive(Default)]
+pub struct RateTracker {
+    recent: HashMap<u32, VecDeque<DateTime<Utc>>>,
+}
+
+/// The message times of the players a transaction touched, kept by
+/// [`RateTracker::commit`] once it commits. `None` is a forgotten player.
+#[derive(Default)]
+pub struct StagedRates {
+    recent: HashMap<u32, Option<VecDeque<DateTime<Utc>>>>,
+}
+
+impl StagedRates {
+    pub fn forget(&mut self, player_id: u32) {
+        self.recent.insert(player_id, None);
+    }
+}
+
+impl RateTracker {
+    /// Records a message and returns the player's messages within
+    /// `window_secs`. Only their copy in `staged` changes.
+    pub fn record<'a>(
+        &self,
+        staged: &'a mut StagedRates,
+        player_id: u32,
+        time: DateTime<Utc>,
+        window_secs: i64,
+    ) -> &'a VecDeque<DateTime<Utc>> {
+        let recent = staged
+            .recent
+            .entry(player_id)
+            .or_insert_with(|| self.recent.get(&player_id).cloned())
+            .get_or_insert_with(VecDeque::new);
+        let cutoff = time - Duration::seconds(window_secs);
+        while recent.front().map_or(false, |t| *t < cutoff) {
+            recent.pop_front();
+        }
+        recent.push_back(time);
+        recent
+    }
+
+    /// Keeps the message times of a committed transaction.
+    pub fn commit(&mut self, staged: StagedRates) {
+        for (player_id, recent) in staged.recent {
+            match recent {
+                Some(recent) => self.recent.insert(player_id, recent),
+                None => self.recent.remove(&player_id),
+            };
+        }
+    }
+}
+
+#[derive(FromRow)]
+struct DbRule {
+    id: u32,
+    name: String,
+    enabled: bool,
+    dry_run: bool,
+    conditions: String,
+    actions: String,
+}
+
+impl TryFrom<DbRule> for Rule {
+    type Error = json::serde_json::Error;
+
+    fn try_from(rule: DbRule) -> Result<Self, Self::Error> {
+        Ok(Rule {
+            id: Some(rule.id),
+            name: rule.name,
+            enabled: rule.enabled,
+            dry_run: rule.dry_run,
+            conditions: json::from_str(&rule.conditions)?,
+            actions: json::from_str(&rule.actions)?,
+        })
+    }
+}
+
+pub async fn load_rules<'e>(executor: impl SqliteExecutor<'e>) -> sqlx::Result<Vec<Rule>> {
+    Ok(
+        sqlx::query_as::<_, DbRule>("select * from rules order by id;")
+            .fetch_all(executor)
+            .await?
+            .into_iter()
+            .filter_map(|rule| {
+                let id = rule.id;
+                Rule::try_from(rule)
+                    .map_err(|e| tracing::error!("Failed to load rule {id}: {e}"))
+                    .ok()
+            })
+            .collect(),
+    )
+}
+
+pub async fn load_watchlist<'e>(executor: impl SqliteExecutor<'e>) -> sqlx::Result<HashSet<u32>> {
+    let ids = sqlx::query_scalar::<_, u32>("select player_id from player_tags where tag = ?;")
+        .bind(WATCHLIST_TAG)
+        .fetch_all(executor)
+        .await?;
+
+    Ok(ids.into_iter().collect())
+}
+
+/// Tags and first-seen time of a player, needed to evaluate rules.
+pub async fn player_context(
+    conn: &mut SqliteConnection,
+    player_id: u32,
+) -> sqlx::Result<(Vec<String>, Option<DateTime<Utc>>)> {
+    let tags = sqlx::query_scalar::<_, String>(
+        "
+        select tag
+        from player_tags
+        where player_id = ?;
+    ",
+    )
+    .bind(player_id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let first_seen = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+        "
+        select min(time) from (
+            select time from activity where player_id = $1
+            union all
+            select time from messages where player_id = $1
+        );
+    ",
+    )
+    .bind(player_id)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok((tags, first_seen))
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RuleFiring {
+    pub id: u32,
+    pub rule_id: u32,
+    pub message_id: u32,
+    pub player_id: u32,
+    pub time: DateTime<Utc>,
+    pub action: Action,
+    pub dry_run: bool,
+}
+
+#[derive(FromRow)]
+struct DbRuleFiring {
+    id: u32,
+    rule_id: u32,
+    message_id: u32,
+    player_id: u32,
+    time: DateTime<Utc>,
+    action: String,
+    dry_run: bool,
+}
+
+impl TryFrom<DbRuleFiring> for RuleFiring {
+    type Error = json::serde_json::Error;
+
+    fn try_from(firing: DbRuleFiring) -> Result<Self, Self::Error> {
+        Ok(RuleFiring {
+            id: firing.id,
+            rule_id: firing.rule_id,
+            message_id: firing.message_id,
+            player_id: firing.player_id,
+            time: firing.time,
+            action: json::from_str(&firing.action)?,
+            dry_run: firing.dry_run,
+        })
+    }
+}
+
+pub async fn log_firing(
+    conn: &mut SqliteConnection,
+    rule_id: u32,
+    message_id: u32,
+    player_id: u32,
+    time: DateTime<Utc>,
+    action: &Action,
+    dry_run: bool,
+) -> sqlx::Result<RuleFiring> {
+    let encoded = json::to_string(action).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+    let id = sqlx::query_scalar::<_, u32>(
+        "
+        insert into rule_firings (rule_id, message_id, player_id, time, action, dry_run) values ($1, $2, $3, $4, $5, $6);
+        select last_insert_rowid() as id;
+        ",
+    )
+    .bind(rule_id)
+    .bind(message_id)
+    .bind(player_id)
+    .bind(time)
+    .bind(encoded)
+    .bind(dry_run)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    Ok(RuleFiring {
+        id,
+        rule_id,
+        message_id,
+        player_id,
+        time,
+        action: action.clone(),
+        dry_run,
+    })
+}
+
+#[get("/rules")]
+async fn rules_page() -> Template {
+    Template::render("rules", ())
+}
+
+#[post("/rules")]
+async fn list_rules(rules: &State<RuleSet>) -> Json<Vec<Rule>> {
+    Json(rules.read().await.clone())
+}
+
+#[post("/rules/save", data = "<rule>")]
+async fn save_rule(
+    mut db: Connection<Db>,
+    rules: &State<RuleSet>,
+    rule: Json<Rule>,
+) -> ApiResult<Json<u32>> {
+    let conditions = json::to_string(&rule.conditions)?;
+    let actions = json::to_string(&rule.actions)?;
+    let id = match rule.id {
+        Some(id) => {
+            let result = sqlx::query(
+                "
+                update rules
+                set name = $1, enabled = $2, dry_run = $3, conditions = $4, actions = $5
+                where id = $6;
+            ",
+            )
+            .bind(&rule.name)
+            .bind(rule.enabled)
+            .bind(rule.dry_run)
+            .bind(conditions)
+            .bind(actions)
+            .bind(id)
+            .execute(&mut *db)
+            .await?;
+            if result.rows_affected() == 0 {
+                return Err(ApiError::not_found(format!("rule {id}")));
+            }
+            id
+        }
+        None => sqlx::query_scalar::<_, u32>(
+            "
+            insert into rules (name, enabled, dry_run, conditions, actions) values ($1, $2, $3, $4, $5);
+            select last_insert_rowid() as id;
+            ",
+        )
+        .bind(&rule.name)
+        .bind(rule.enabled)
+        .bind(rule.dry_run)
+        .bind(conditions)
+        .bind(actions)
+        .fetch_one(&mut *db)
+        .await?,
+    };
+
+    *rules.write().await = load_rules(&mut *db).await?;
+    Ok(Json(id))
+}
+
+#[post("/rules/delete?<id>")]
+async fn delete_rule(mut db: Connection<Db>, rules: &State<RuleSet>, id: u32) -> ApiResult<()> {
+    sqlx::query("delete from rules where id = ?;")
+        .bind(id)
+        .execute(&mut *db)
+        .await?;
+
+    *rules.write().await = load_rules(&mut *db).await?;
+    Ok(())
+}
+
+#[post("/rule_firings?<rule_id>")]
+async fn rule_firings(
+    mut db: Connection<Db>,
+    rule_id: Option<u32>,
+) -> ApiResult<Json<Vec<RuleFiring>>> {
+    let firings = if let Some(rule_id) = rule_id {
+        sqlx::query_as::<_, DbRuleFiring>(
+            "
+            select *
+            from rule_firings
+            where rule_id = ?
+            order by id desc
+            limit 100;
+        ",
+        )
+        .bind(rule_id)
+        .fetch_all(&mut *db)
+    } else {
+        sqlx::query_as::<_, DbRuleFiring>(
+            "
+            select *
+            from rule_firings
+            order by id desc
+            limit 100;
+        ",
+        )
+        .fetch_all(&mut *db)
+    }
+    .await?;
+
+    Ok(Json(decode_rows("rule firing", firings, |firing| {
+        firing.id
+    })))
+}
+
+#[post("/player_tags?<id>")]
+async fn player_tags(mut db: Connection<Db>, id: u32) -> ApiResult<Json<Vec<String>>> {
+    let tags = sqlx::query_scalar(
+        "
+        select tag
+        from player_tags
+        where player_id = ?;
+    ",
+    )
+    .bind(id)
+    .fetch_all(&mut *db)
+    .await?;
+
+    Ok(Json(tags))
+}
+
+#[post("/player_tags/add?<id>&<tag>")]
+async fn add_player_tag(
+    mut db: Connection<Db>,
+    watchlist: &State<Watchlist>,
+    id: u32,
+    tag: &str,
+) -> ApiResult<()> {
+    sqlx::query("insert or ignore into player_tags (player_id, tag) values ($1, $2);")
+        .bind(id)
+        .bind(tag)
+        .execute(&mut *db)
+        .await?;
+    if tag == WATCHLIST_TAG {
+        watchlist.write().await.insert(id);
+    }
+    Ok(())
+}
+
+#[post("/player_tags/remove?<id>&<tag>")]
+async fn remove_player_tag(
+    mut db: Connection<Db>,
+    watchlist: &State<Watchlist>,
+    id: u32,
+    tag: &str,
+) -> ApiResult<()> {
+    sqlx::query("delete from player_tags where player_id = $1 and tag = $2;")
+        .bind(id)
+        .bind(tag)
+        .execute(&mut *db)
+        .await?;
+    if tag == WATCHLIST_TAG {
+        watchlist.write().await.remove(&id);
+    }
+    Ok(())
+}
+
+pub fn page_routes() -> Vec<Route> {
+    routes![rules_page]
+}
+
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        list_rules,
+        save_rule,
+        delete_rule,
+        rule_firings,
+        player_tags,
+        add_player_tag,
+        remove_player_tag
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 3, 1, 12, 0, 0).unwrap()
+    }
+
+    struct Input {
+        message: &'static str,
+        ty: MessageType,
+        spam_score: f32,
+        tags: Vec<String>,
+        account_age: Duration,
+        recent: VecDeque<DateTime<Utc>>,
+    }
+
+    impl Default for Input {
+        fn default() -> Self {
+            Self {
+                message: "hello there",
+                ty: MessageType::World,
+                spam_score: 0.0,
+                tags: Vec::new(),
+                account_age: Duration::days(30),
+                recent: VecDeque::from([now()]),
+            }
+        }
+    }
+
+    impl Input {
+        fn get(&self) -> RuleInput<'_> {
+            RuleInput {
+                time: now(),
+                message: self.message,
+                ty: &self.ty,
+                spam_score: self.spam_score,
+                tags: &self.tags,
+                account_age: self.account_age,
+                recent: &self.recent,
+            }
+        }
+    }
+
+    fn contains(text: &str) -> Condition {
+        Condition::Contains {
+            text: text.to_string(),
+        }
+    }
+
+    fn rule(conditions: Vec<Condition>, actions: Vec<Action>) -> Rule {
+        Rule {
+            id: Some(1),
+            name: "test".to_string(),
+            enabled: true,
+            dry_run: false,
+            conditions,
+            actions,
+        }
+    }
+
+    #[test]
+    fn contains_ignores_case() {
+        let input = Input {
+            message: "Buy GOLD now",
+            ..Input::default()
+        };
+        assert!(contains("gold").matches(&input.get()));
+        assert!(!contains("silver").matches(&input.get()));
+    }
+
+    #[test]
+    fn matches_a_pattern() {
+        let condition = Condition::Matches {
+            pattern: Regex::new(r"\bg[o0]ld\b").unwrap(),
+        };
+        let input = Input {
+            message: "cheap g0ld here",
+            ..Input::default()
+        };
+        assert!(condition.matches(&input.get()));
+        let input = Input {
+            message: "golden",
+            ..Input::default()
+        };
+        assert!(!condition.matches(&input.get()));
+    }
+
+    #[test]
+    fn message_type() {
+        let condition = Condition::MessageType {
+            ty: MessageType::Tell,
+        };
+        assert!(!condition.matches(&Input::default().get()));
+        let input = Input {
+            ty: MessageType::Tell,
+            ..Input::default()
+        };
+        assert!(condition.matches(&input.get()));
+    }
+
+    #[test]
+    fn has_tag() {
+        let condition = Condition::HasTag {
+            tag: WATCHLIST_TAG.to_string(),
+        };
+        assert!(!condition.matches(&Input::default().get()));
+        let input = Input {
+            tags: vec!["trusted".to_string(), WATCHLIST_TAG.to_string()],
+            ..Input::default()
+        };
+        assert!(condition.matches(&input.get()));
+    }
+
+    #[test]
+    fn account_age_below() {
+        let condition = Condition::AccountAgeBelow { secs: 3600 };
+        assert!(!condition.matches(&Input::default().get()));
+        let input = Input {
+            account_age: Duration::minutes(5),
+            ..Input::default()
+        };
+        assert!(condition.matches(&input.get()));
+    }
+
+    #[test]
+    fn rate_above_only_counts_the_window() {
+        let condition = Condition::RateAbove {
+            messages: 2,
+            secs: 10,
+        };
+        let input = Input {
+            recent: VecDeque::from([
+                now() - Duration::seconds(30),
+                now() - Duration::seconds(20),
+                now() - Duration::seconds(5),
+                now(),
+            ]),
+            ..Input::default()
+        };
+        assert!(!condition.matches(&input.get()));
+        let input = Input {
+            recent: VecDeque::from([
+                now() - Duration::seconds(8),
+                now() - Duration::seconds(5),
+                now(),
+            ]),
+            ..Input::default()
+        };
+        assert!(condition.matches(&input.get()));
+    }
+
+    #[test]
+    fn spam_score_above() {
+        let condition = Condition::SpamScoreAbove { score: 1.0 };
+        assert!(!condition.matches(&Input::default().get()));
+        let input = Input {
+            spam_score: 1.5,
+            ..Input::default()
+        };
+        assert!(condition.matches(&input.get()));
+    }
+
+    #[test]
+    fn not_and_any() {
+        let input = Input::default();
+        let not = Condition::Not {
+            condition: Box::new(contains("hello")),
+        };
+        assert!(!not.matches(&input.get()));
+        let any = Condition::Any {
+            conditions: vec![contains("bye"), contains("there")],
+        };
+        assert!(any.matches(&input.get()));
+        let none = Condition::Any {
+            conditions: Vec::new(),
+        };
+        assert!(!none.matches(&input.get()));
+    }
+
+    #[test]
+    fn conditions_deserialize_by_kind() {
+        let conditions: Vec<Condition> = json::from_str(
+            r#"[
+                {"kind": "contains", "text": "gold"},
+                {"kind": "matches", "pattern": "^!"},
+                {"kind": "rate_above", "messages": 5, "secs": 10},
+                {"kind": "not", "condition": {"kind": "has_tag", "tag": "trusted"}}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(conditions.len(), 4);
+        assert!(matches!(
+            conditions[2],
+            Condition::RateAbove {
+                messages: 5,
+                secs: 10
+            }
+        ));
+    }
+
+    #[test]
+    fn rules_need_every_condition() {
+        let input = Input::default();
+        let both = rule(
+            vec![contains("hello"), contains("there")],
+            vec![Action::Alert],
+        );
+        assert!(both.matches(&input.get()));
+        let one = rule(
+            vec![contains("hello"), contains("bye")],
+            vec![Action::Alert],
+        );
+        assert!(!one.matches(&input.get()));
+    }
+
+    #[test]
+    fn disabled_and_empty_rules_never_match() {
+        let input = Input::default();
+        let mut disabled = rule(vec![contains("hello")], vec![Action::Alert]);
+        disabled.enabled = false;
+        assert!(!disabled.matches(&input.get()));
+        assert!(!rule(Vec::new(), vec![Action::Alert]).matches(&input.get()));
+    }
+
+    #[test]
+    fn effects_carry_out_actions() {
+        let rule = rule(
+            vec![contains("hello")],
+            vec![
+                Action::Alert,
+                Action::Mute {
+                    secs: 60,
+                    reason: "spam".to_string(),
+                },
+                Action::Warn {
+                    message: "calm down".to_string(),
+                },
+            ],
+        );
+        let effects = rule.effects("Alice");
+        assert_eq!(effects.len(), 3);
+        assert!(matches!(effects[0], Effect::Alert));
+        assert!(matches!(
+            &effects[1],
+            Effect::Command(BotCommand::Mute { alias, secs: 60, reason })
+                if alias == "Alice" && reason == "spam"
+        ));
+        assert!(matches!(
+            &effects[2],
+            Effect::Command(BotCommand::Tell { alias, message })
+                if alias == "Alice" && message == "calm down"
+        ));
+    }
+
+    #[test]
+    fn dry_runs_have_no_effects() {
+        let mut rule = rule(
+            vec![contains("hello")],
+            vec![
+                Action::Alert,
+                Action::Mute {
+                    secs: 60,
+                    reason: "spam".to_string(),
+                },
+            ],
+        );
+        rule.dry_run = true;
+        assert!(rule.matches(&Input::default().get()));
+        assert!(rule.effects("Alice").is_empty());
+    }
+
+    #[test]
+    fn rate_window_looks_into_nested_conditions() {
+        let rule = rule(
+            vec![
+                Condition::RateAbove {
+                    messages: 3,
+                    secs: 10,
+                },
+                Condition::Not {
+                    condition: Box::new(Condition::Any {
+                        conditions: vec![Condition::RateAbove {
+                            messages: 10,
+                            secs: 60,
+                        }],
+                    }),
+                },
+            ],
+            Vec::new(),
+        );
+        assert_eq!(rule.rate_window(), 60);
+    }
+
+    #[test]
+    fn rate_tracker_keeps_the_window() {
+        let tracker = RateTracker::default();
+        let mut staged = StagedRates::default();
+        tracker.record(&mut staged, 1, now() - Duration::seconds(30), 10);
+        tracker.record(&mut staged, 1, now() - Duration::seconds(5), 10);
+        assert_eq!(tracker.record(&mut staged, 1, now(), 10).len(), 2);
+        assert_eq!(tracker.record(&mut staged, 2, now(), 10).len(), 1);
+
+        staged.forget(1);
+        assert_eq!(tracker.record(&mut staged, 1, now(), 10).len(), 1);
+    }
+
+    #[test]
+    fn rate_tracker_changes_only_on_commit() {
+        let mut tracker = RateTracker::default();
+        let mut staged = StagedRates::default();
+        tracker.record(&mut staged, 1, now() - Duration::seconds(5), 10);
+        let mut rolled_back = StagedRates::default();
+        assert_eq!(tracker.record(&mut rolled_back, 1, now(), 10).len(), 1);
+
+        tracker.commit(staged);
+        let mut staged = StagedRates::default();
+        assert_eq!(tracker.record(&mut staged, 1, now(), 10).len(), 2);
+        staged.forget(1);
+        tracker.commit(staged);
+        assert_eq!(
+            tracker
+                .record(&mut StagedRates::default(), 1, now(), 10)
+                .len(),
+            1
+        );
+    }
+}
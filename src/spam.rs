@@ -0,0 +1,362 @@
+use std::collections::{HashMap, VecDeque};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Thresholds for the spam detector, read from the `spam` table in `Rocket.toml`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct SpamConfig {
+    /// Length of the sliding window in seconds.
+    pub window_secs: i64,
+    /// Messages within the window before it counts as a flood.
+    pub flood_messages: usize,
+    /// Identical messages within the window before it counts as repetition.
+    pub repeat_messages: usize,
+    /// Share of uppercase letters before a message counts as excessive caps.
+    pub caps_ratio: f32,
+    /// Letters a message needs before the caps check applies.
+    pub caps_min_letters: usize,
+    /// Length of a run of the same character before it counts as character spam.
+    pub char_run: usize,
+    /// Score at which an alert is raised.
+    pub alert_score: f32,
+}
+
+impl Default for SpamConfig {
+    fn default() -> Self {
+        Self {
+            window_secs: 10,
+            flood_messages: 6,
+            repeat_messages: 3,
+            caps_ratio: 0.8,
+            caps_min_letters: 8,
+            char_run: 8,
+            alert_score: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpamReason {
+    Flood,
+    Repeat,
+    Caps,
+    CharSpam,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SpamReport {
+    /// Sum of how far each check is towards its threshold, every check
+    /// contributing at most `1.0`.
+    pub score: f32,
+    /// Checks whose threshold was crossed.
+    pub reasons: Vec<SpamReason>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    time: DateTime<Utc>,
+    normalized: String,
+}
+
+/// Per-player sliding window over recent chat messages.
+pub struct SpamDetector {
+    config: SpamConfig,
+    windows: HashMap<u32, VecDeque<Entry>>,
+}
+
+/// The windows of the players a transaction touched, kept by
+/// [`SpamDetector::commit`] once it commits. `None` is a forgotten window.
+#[derive(Default)]
+pub struct StagedWindows {
+    windows: HashMap<u32, Option<VecDeque<Entry>>>,
+}
+
+impl StagedWindows {
+    /// Drops the window of a player, i.e when they go offline.
+    pub fn forget(&mut self, player_id: u32) {
+        self.windows.insert(player_id, None);
+    }
+}
+
+impl SpamDetector {
+    pub fn new(config: SpamConfig) -> Self {
+        Self {
+            config,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Records `message` for `player_id` and scores it against the player's
+    /// recent messages. Only the copy of their window in `staged` changes.
+    pub fn analyze(
+        &self,
+        staged: &mut StagedWindows,
+        player_id: u32,
+        time: DateTime<Utc>,
+        message: &str,
+    ) -> SpamReport {
+        let window = staged
+            .windows
+            .entry(player_id)
+            .or_insert_with(|| self.windows.get(&player_id).cloned())
+            .get_or_insert_with(VecDeque::new);
+        let cutoff = time - Duration::seconds(self.config.window_secs);
+        while window.front().map_or(false, |e| e.time < cutoff) {
+            window.pop_front();
+        }
+
+        let normalized = normalize(message);
+        let repeats = window
+            .iter()
+            .filter(|e| !normalized.is_empty() && e.normalized == normalized)
+            .count()
+            + 1;
+        window.push_back(Entry { time, normalized });
+
+        let checks = [
+            (
+                SpamReason::Flood,
+                ratio(window.len(), self.config.flood_messages),
+            ),
+            (
+                SpamReason::Repeat,
+                ratio(repeats, self.config.repeat_messages),
+            ),
+            (SpamReason::Caps, caps_score(message, &self.config)),
+            (
+                SpamReason::CharSpam,
+                ratio(longest_run(message), self.config.char_run),
+            ),
+        ];
+
+        let mut report = SpamReport::default();
+        for (reason, value) in checks {
+            report.score += value.min(1.0);
+            if value >= 1.0 {
+                report.reasons.push(reason);
+            }
+        }
+        report
+    }
+
+    /// Whether `report` should raise an alert.
+    pub fn is_alert(&self, report: &SpamReport) -> bool {
+        !report.reasons.is_empty() && report.score >= self.config.alert_score
+    }
+
+    /// Keeps the windows of a committed transaction.
+    pub fn commit(&mut self, staged: StagedWindows) {
+        for (player_id, window) in staged.windows {
+            match window {
+                Some(window) => self.windows.insert(player_id, window),
+                None => self.windows.remove(&player_id),
+            };
+        }
+    }
+}
+
+fn ratio(value: usize, threshold: usize) -> f32 {
+    if threshold == 0 {
+        0.0
+    } else {
+        value as f32 / threshold as f32
+    }
+}
+
+fn normalize(message: &str) -> String {
+    message
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn caps_score(message: &str, config: &SpamConfig) -> f32 {
+    let (letters, upper) = message
+        .chars()
+        .filter(|c| c.is_alphabetic())
+        .fold((0usize, 0usize), |(letters, upper), c| {
+            (letters + 1, upper + c.is_uppercase() as usize)
+        });
+    if letters < config.caps_min_letters || config.caps_ratio <= 0.0 {
+        return 0.0;
+    }
+    (upper as f32 / letters as f32) / config.caps_ratio
+}
+
+fn longest_run(message: &str) -> usize {
+    let mut longest = 0;
+    let mut current = 0;
+    let mut last = None;
+    for c in message.chars().filter(|c| !c.is_whitespace()) {
+        if Some(c) == last {
+            current += 1;
+        } else {
+            current = 1;
+            last = Some(c);
+        }
+        longest = longest.max(current);
+    }
+    longest
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn start() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 3, 1, 12, 0, 0).unwrap()
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        start() + Duration::seconds(secs)
+    }
+
+    #[test]
+    fn flood_needs_flood_messages_within_the_window() {
+        let detector = SpamDetector::new(SpamConfig::default());
+        let mut staged = StagedWindows::default();
+        for i in 0..5 {
+            let report = detector.analyze(&mut staged, 1, at(i), &format!("message {i}"));
+            assert!(!report.reasons.contains(&SpamReason::Flood));
+        }
+        let report = detector.analyze(&mut staged, 1, at(5), "message 5");
+        assert!(report.reasons.contains(&SpamReason::Flood));
+    }
+
+    #[test]
+    fn flood_window_slides() {
+        let detector = SpamDetector::new(SpamConfig::default());
+        let mut staged = StagedWindows::default();
+        for i in 0..10 {
+            let report = detector.analyze(&mut staged, 1, at(i * 11), &format!("message {i}"));
+            assert!(report.reasons.is_empty());
+        }
+    }
+
+    #[test]
+    fn players_have_separate_windows() {
+        let detector = SpamDetector::new(SpamConfig::default());
+        let mut staged = StagedWindows::default();
+        for i in 0..5 {
+            detector.analyze(&mut staged, 1, at(i), &format!("message {i}"));
+        }
+        let report = detector.analyze(&mut staged, 2, at(5), "message 5");
+        assert!(report.reasons.is_empty());
+    }
+
+    #[test]
+    fn repeat_ignores_case_and_whitespace() {
+        let detector = SpamDetector::new(SpamConfig::default());
+        let mut staged = StagedWindows::default();
+        assert!(detector
+            .analyze(&mut staged, 1, at(0), "buy gold")
+            .reasons
+            .is_empty());
+        assert!(detector
+            .analyze(&mut staged, 1, at(1), "Buy  gold")
+            .reasons
+            .is_empty());
+        let report = detector.analyze(&mut staged, 1, at(2), " buy GOLD ");
+        assert_eq!(report.reasons, vec![SpamReason::Repeat]);
+    }
+
+    #[test]
+    fn caps_needs_enough_letters() {
+        let detector = SpamDetector::new(SpamConfig::default());
+        let mut staged = StagedWindows::default();
+        assert!(detector
+            .analyze(&mut staged, 1, at(0), "HEY YOU")
+            .reasons
+            .is_empty());
+        let report = detector.analyze(&mut staged, 1, at(1), "STOP SHOUTING");
+        assert_eq!(report.reasons, vec![SpamReason::Caps]);
+        assert!(detector
+            .analyze(&mut staged, 1, at(2), "Stop shouting")
+            .reasons
+            .is_empty());
+
+        let detector = SpamDetector::new(SpamConfig {
+            caps_min_letters: 4,
+            ..SpamConfig::default()
+        });
+        let mut staged = StagedWindows::default();
+        let report = detector.analyze(&mut staged, 1, at(0), "HEY YOU");
+        assert_eq!(report.reasons, vec![SpamReason::Caps]);
+    }
+
+    #[test]
+    fn char_run_ignores_whitespace() {
+        let detector = SpamDetector::new(SpamConfig::default());
+        let mut staged = StagedWindows::default();
+        assert!(detector
+            .analyze(&mut staged, 1, at(0), "nooooooo")
+            .reasons
+            .is_empty());
+        let report = detector.analyze(&mut staged, 1, at(1), "nooooo ooo");
+        assert_eq!(report.reasons, vec![SpamReason::CharSpam]);
+    }
+
+    #[test]
+    fn checks_contribute_at_most_one() {
+        let detector = SpamDetector::new(SpamConfig::default());
+        let mut staged = StagedWindows::default();
+        // Caps and character runs are both far past their thresholds, the
+        // first message is a sixth of a flood and a third of a repeat.
+        let report = detector.analyze(&mut staged, 1, at(0), &"A".repeat(40));
+        assert_eq!(report.reasons, vec![SpamReason::Caps, SpamReason::CharSpam]);
+        assert!((report.score - 2.5).abs() < 1e-5);
+        assert!(detector.is_alert(&report));
+    }
+
+    #[test]
+    fn no_alert_without_a_crossed_threshold() {
+        let detector = SpamDetector::new(SpamConfig {
+            alert_score: 0.1,
+            ..SpamConfig::default()
+        });
+        let mut staged = StagedWindows::default();
+        let report = detector.analyze(&mut staged, 1, at(0), "hello");
+        assert!(report.score > 0.1);
+        assert!(!detector.is_alert(&report));
+    }
+
+    #[test]
+    fn windows_change_only_on_commit() {
+        let mut detector = SpamDetector::new(SpamConfig::default());
+        let mut staged = StagedWindows::default();
+        for i in 0..2 {
+            detector.analyze(&mut staged, 1, at(i), "same message");
+        }
+        let report = detector.analyze(&mut StagedWindows::default(), 1, at(2), "same message");
+        assert!(report.reasons.is_empty());
+
+        detector.commit(staged);
+        let report = detector.analyze(&mut StagedWindows::default(), 1, at(2), "same message");
+        assert_eq!(report.reasons, vec![SpamReason::Repeat]);
+
+        let mut staged = StagedWindows::default();
+        staged.forget(1);
+        detector.commit(staged);
+        let report = detector.analyze(&mut StagedWindows::default(), 1, at(3), "same message");
+        assert!(report.reasons.is_empty());
+    }
+
+    #[test]
+    fn forget_drops_the_window() {
+        let detector = SpamDetector::new(SpamConfig::default());
+        let mut staged = StagedWindows::default();
+        for i in 0..5 {
+            detector.analyze(&mut staged, 1, at(i), "same message");
+        }
+        staged.forget(1);
+        let report = detector.analyze(&mut staged, 1, at(5), "same message");
+        assert!(report.reasons.is_empty());
+        // A sixth of a flood, a third of a repeat and the run of two `s`.
+        assert!((report.score - (1.0 / 6.0 + 1.0 / 3.0 + 2.0 / 8.0)).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,206 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, Pool, Sqlite};
+use veloren_common::uuid::Uuid;
+
+use crate::{
+    export::ExportRow,
+    messages::Direction,
+    player_data::{Erasure, ErasureMode, PlayerData},
+    reports::Report,
+    retention::{PruneRun, RetentionConfig},
+    rules::{Action, RuleFiring},
+    tickets::Ticket,
+    Activity, Message, MessageFilter, MessageType,
+};
+
+mod sqlite;
+mod timed;
+
+pub use sqlite::SqliteStore;
+pub use timed::TimedStore;
+
+#[derive(Clone, Debug, FromRow)]
+pub struct Player {
+    pub id: u32,
+    pub uuid: String,
+    pub alias: String,
+}
+
+/// Writes to the chat log, and the moderation data derived from it during
+/// ingestion, within a transaction. Nothing is visible to other connections
+/// until [`ChatWriter::commit`].
+#[rocket::async_trait]
+pub trait ChatWriter: Send {
+    /// Returns the id of the player with `uuid`, creating them if needed and
+    /// updating their alias otherwise.
+    async fn upsert_player(&mut self, uuid: &str, alias: &str) -> sqlx::Result<u32>;
+
+    async fn insert_message(
+        &mut self,
+        player_id: u32,
+        time: DateTime<Utc>,
+        content: &str,
+        ty: &MessageType,
+        spam_score: f32,
+        recipient_id: Option<u32>,
+    ) -> sqlx::Result<u32>;
+
+    async fn insert_activity(
+        &mut self,
+        player_id: u32,
+        time: DateTime<Utc>,
+        online: bool,
+    ) -> sqlx::Result<u32>;
+
+    /// Records the alias a player was seen with.
+    async fn record_alias(
+        &mut self,
+        player_id: u32,
+        alias: &str,
+        time: DateTime<Utc>,
+    ) -> sqlx::Result<()>;
+
+    /// Tags of a player and when they were first seen, rules match on both.
+    async fn player_context(
+        &mut self,
+        player_id: u32,
+    ) -> sqlx::Result<(Vec<String>, Option<DateTime<Utc>>)>;
+
+    async fn log_firing(
+        &mut self,
+        rule_id: u32,
+        message_id: u32,
+        player_id: u32,
+        time: DateTime<Utc>,
+        action: &Action,
+        dry_run: bool,
+    ) -> sqlx::Result<RuleFiring>;
+
+    /// Finds the reported player, by uuid if they were online and by their
+    /// latest alias otherwise.
+    async fn find_reported(&mut self, alias: &str, uuid: Option<Uuid>)
+        -> sqlx::Result<Option<u32>>;
+
+    /// Files a report with the latest chat of both players as context.
+    async fn file_report(
+        &mut self,
+        reporter_id: u32,
+        reported_id: u32,
+        reason: &str,
+        time: DateTime<Utc>,
+    ) -> sqlx::Result<Report>;
+
+    /// Opens a ticket for a freshly filed report.
+    async fn ticket_for_report(&mut self, report: &Report) -> sqlx::Result<Ticket>;
+
+    async fn commit(self: Box<Self>) -> sqlx::Result<()>;
+}
+
+/// Where the chat log, players, messages and activity, is kept, along with
+/// everything that joins against it.
+#[rocket::async_trait]
+pub trait ChatStore: Send + Sync {
+    async fn begin(&self) -> sqlx::Result<Box<dyn ChatWriter>>;
+
+    async fn player_alias(&self, id: u32) -> sqlx::Result<Option<String>>;
+
+    /// Ids of players whose alias contains `alias`.
+    async fn find_players(&self, alias: &str) -> sqlx::Result<Vec<u32>>;
+
+    async fn player(&self, id: u32) -> sqlx::Result<Option<Player>>;
+
+    /// Up to `limit` players whose alias contains `alias`, walked by id past
+    /// the player `from` like [`ChatStore::list_messages`].
+    async fn list_players(
+        &self,
+        alias: Option<&str>,
+        from: Option<u32>,
+        direction: Direction,
+        limit: u32,
+    ) -> sqlx::Result<Vec<Player>>;
+
+    async fn message(&self, id: u32) -> sqlx::Result<Option<Message>>;
+
+    /// Up to `limit` messages matching `filter` past the message `from` in
+    /// `direction`, in the order they are walked. Without `from` the listing
+    /// starts at the latest message for [`Direction::Older`] and at the first
+    /// one for [`Direction::Newer`].
+    async fn list_messages(
+        &self,
+        filter: &MessageFilter,
+        from: Option<u32>,
+        direction: Direction,
+        limit: u32,
+    ) -> sqlx::Result<Vec<Message>>;
+
+    /// Page `page` of the messages matching `filter`, newest first.
+    async fn query_messages(
+        &self,
+        filter: &MessageFilter,
+        per_page: u32,
+        page: u32,
+    ) -> sqlx::Result<Vec<Message>>;
+
+    /// Up to `limit` messages matching `filter` after the message `from`
+    /// together with their player, oldest first.
+    async fn export_messages(
+        &self,
+        filter: &MessageFilter,
+        from: u32,
+        limit: u32,
+    ) -> sqlx::Result<Vec<ExportRow>>;
+
+    /// Number of messages matching `filter`.
+    async fn count_messages(&self, filter: &MessageFilter) -> sqlx::Result<u64>;
+
+    /// Ids of the latest message and activity entry, zero while there are none.
+    async fn latest_ids(&self) -> sqlx::Result<(u32, u32)>;
+
+    /// Checks that the database answers.
+    async fn ping(&self) -> sqlx::Result<()>;
+
+    /// Up to `limit` activity entries after the entry `id`, oldest first.
+    async fn activity_after(&self, id: u32, limit: u32) -> sqlx::Result<Vec<Activity>>;
+
+    /// Online and offline events of a player, oldest first.
+    async fn activity(&self, player_id: u32) -> sqlx::Result<Vec<(DateTime<Utc>, bool)>>;
+
+    /// Messages linked to a report as context, oldest first.
+    async fn report_messages(&self, report_id: u32) -> sqlx::Result<Vec<Message>>;
+
+    async fn ticket_players(&self, ticket_id: u32) -> sqlx::Result<Vec<Player>>;
+
+    /// Messages linked to a ticket, oldest first.
+    async fn ticket_messages(&self, ticket_id: u32) -> sqlx::Result<Vec<Message>>;
+
+    /// Sets the legal hold flag of a player if `hold` is given and returns
+    /// it, `None` if there is no such player.
+    async fn legal_hold(&self, id: u32, hold: Option<bool>) -> sqlx::Result<Option<bool>>;
+
+    /// Applies the retention policies once in a single transaction and
+    /// records what was pruned.
+    async fn prune(&self, config: &RetentionConfig) -> sqlx::Result<PruneRun>;
+
+    /// Everything stored about a player, `None` if there is no such player.
+    async fn player_data(&self, id: u32) -> sqlx::Result<Option<PlayerData>>;
+
+    /// Erases a player along with the moderation data referencing them in a
+    /// single transaction and records the erasure, `None` if there is no such
+    /// player.
+    async fn erase_player(
+        &self,
+        id: u32,
+        mode: ErasureMode,
+        operator: &str,
+        reason: &str,
+    ) -> sqlx::Result<Option<Erasure>>;
+}
+
+pub type Store = Arc<dyn ChatStore>;
+
+/// Opens the chat log store on the `logs` database.
+pub fn open(pool: Pool<Sqlite>) -> Store {
+    Arc::new(SqliteStore::new(pool))
+}
@@ -0,0 +1,440 @@
+use chrono::{DateTime, Utc};
+use sqlx::{sqlite::SqliteArguments, Arguments, FromRow, Pool, Sqlite, Transaction};
+use veloren_common::uuid::Uuid;
+
+use super::{ChatStore, ChatWriter, Player};
+use crate::{
+    error::decode_rows,
+    export::ExportRow,
+    messages::Direction,
+    player_data::{self, Erasure, ErasureMode, PlayerData},
+    reports::{self, Report},
+    retention::{self, PruneRun, RetentionConfig},
+    rules::{self, Action, RuleFiring},
+    tickets::{self, Ticket},
+    Activity, DbMessage, Message, MessageFilter, MessageType,
+};
+
+pub struct SqliteStore {
+    pool: Pool<Sqlite>,
+}
+
+impl SqliteStore {
+    pub fn new(pool: Pool<Sqlite>) -> Self {
+        Self { pool }
+    }
+}
+
+#[derive(FromRow)]
+struct DbActivity {
+    time: DateTime<Utc>,
+    online: bool,
+}
+
+pub struct SqliteWriter {
+    tx: Transaction<'static, Sqlite>,
+}
+
+#[rocket::async_trait]
+impl ChatWriter for SqliteWriter {
+    async fn upsert_player(&mut self, uuid: &str, alias: &str) -> sqlx::Result<u32> {
+        sqlx::query_scalar::<_, u32>(
+            "
+            insert into players (uuid, alias) values ($1, $2)
+            on conflict (uuid) do update set alias = excluded.alias;
+            select id from players where uuid = $1;
+            ",
+        )
+        .bind(uuid)
+        .bind(alias)
+        .fetch_one(&mut self.tx)
+        .await
+    }
+
+    async fn insert_message(
+        &mut self,
+        player_id: u32,
+        time: DateTime<Utc>,
+        content: &str,
+        ty: &MessageType,
+        spam_score: f32,
+        recipient_id: Option<u32>,
+    ) -> sqlx::Result<u32> {
+        sqlx::query_scalar::<_, u32>(
+            "
+            insert into messages (player_id, time, content, ty, spam_score, recipient_id) values ($1, $2, $3, $4, $5, $6);
+            select last_insert_rowid() as id;
+            ",
+        )
+        .bind(player_id)
+        .bind(time)
+        .bind(content)
+        .bind(ty.to_string())
+        .bind(spam_score)
+        .bind(recipient_id)
+        .fetch_one(&mut self.tx)
+        .await
+    }
+
+    async fn insert_activity(
+        &mut self,
+        player_id: u32,
+        time: DateTime<Utc>,
+        online: bool,
+    ) -> sqlx::Result<u32> {
+        sqlx::query_scalar::<_, u32>(
+            "
+            insert into activity (player_id, time, online) values ($1, $2, $3);
+            select last_insert_rowid() as id;
+            ",
+        )
+        .bind(player_id)
+        .bind(time)
+        .bind(online)
+        .fetch_one(&mut self.tx)
+        .await
+    }
+
+    async fn record_alias(
+        &mut self,
+        player_id: u32,
+        alias: &str,
+        time: DateTime<Utc>,
+    ) -> sqlx::Result<()> {
+        player_data::record_alias(&mut self.tx, player_id, alias, time).await
+    }
+
+    async fn player_context(
+        &mut self,
+        player_id: u32,
+    ) -> sqlx::Result<(Vec<String>, Option<DateTime<Utc>>)> {
+        rules::player_context(&mut self.tx, player_id).await
+    }
+
+    async fn log_firing(
+        &mut self,
+        rule_id: u32,
+        message_id: u32,
+        player_id: u32,
+        time: DateTime<Utc>,
+        action: &Action,
+        dry_run: bool,
+    ) -> sqlx::Result<RuleFiring> {
+        rules::log_firing(
+            &mut self.tx,
+            rule_id,
+            message_id,
+            player_id,
+            time,
+            action,
+            dry_run,
+        )
+        .await
+    }
+
+    async fn find_reported(
+        &mut self,
+        alias: &str,
+        uuid: Option<Uuid>,
+    ) -> sqlx::Result<Option<u32>> {
+        reports::find_reported(&mut self.tx, alias, uuid).await
+    }
+
+    async fn file_report(
+        &mut self,
+        reporter_id: u32,
+        reported_id: u32,
+        reason: &str,
+        time: DateTime<Utc>,
+    ) -> sqlx::Result<Report> {
+        reports::file_report(&mut self.tx, reporter_id, reported_id, reason, time).await
+    }
+
+    async fn ticket_for_report(&mut self, report: &Report) -> sqlx::Result<Ticket> {
+        tickets::ticket_for_report(&mut self.tx, report).await
+    }
+
+    async fn commit(self: Box<Self>) -> sqlx::Result<()> {
+        self.tx.commit().await
+    }
+}
+
+#[rocket::async_trait]
+impl ChatStore for SqliteStore {
+    async fn begin(&self) -> sqlx::Result<Box<dyn ChatWriter>> {
+        Ok(Box::new(SqliteWriter {
+            tx: self.pool.begin().await?,
+        }))
+    }
+
+    async fn player_alias(&self, id: u32) -> sqlx::Result<Option<String>> {
+        sqlx::query_scalar::<_, String>(
+            "
+            select alias
+            from players
+            where id = ?;
+        ",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn find_players(&self, alias: &str) -> sqlx::Result<Vec<u32>> {
+        sqlx::query_scalar(
+            "
+            select id
+            from players
+            where alias like ?;
+        ",
+        )
+        .bind(format!("%{alias}%"))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn player(&self, id: u32) -> sqlx::Result<Option<Player>> {
+        sqlx::query_as::<_, Player>(
+            "
+            select id, uuid, alias
+            from players
+            where id = ?;
+        ",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    async fn list_players(
+        &self,
+        alias: Option<&str>,
+        from: Option<u32>,
+        direction: Direction,
+        limit: u32,
+    ) -> sqlx::Result<Vec<Player>> {
+        let (comparison, order) = match direction {
+            Direction::Older => ("id < $2", "desc"),
+            Direction::Newer => ("id > $2", "asc"),
+        };
+        let query = format!(
+            "
+            select id, uuid, alias
+            from players
+            where ($1 is null or alias like '%' || $1 || '%') and ($2 is null or {comparison})
+            order by id {order}
+            limit $3;
+        "
+        );
+        sqlx::query_as::<_, Player>(&query)
+            .bind(alias)
+            .bind(from)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn message(&self, id: u32) -> sqlx::Result<Option<Message>> {
+        sqlx::query_as::<_, DbMessage>(
+            "
+            select *
+            from messages
+            where id = ?;
+        ",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(Message::try_from)
+        .transpose()
+    }
+
+    async fn list_messages(
+        &self,
+        filter: &MessageFilter,
+        from: Option<u32>,
+        direction: Direction,
+        limit: u32,
+    ) -> sqlx::Result<Vec<Message>> {
+        let mut args = SqliteArguments::default();
+        let (mut where_statement, mut input_n) = filter.where_clause(&mut args);
+        let (comparison, order) = match direction {
+            Direction::Older => ("<", "desc"),
+            Direction::Newer => (">", "asc"),
+        };
+        if let Some(from) = from {
+            let keyword = if where_statement.is_empty() {
+                "where"
+            } else {
+                " and"
+            };
+            where_statement.push_str(&format!("{keyword} id {comparison} ${input_n}"));
+            input_n += 1;
+            args.add(from);
+        }
+        args.add(limit);
+        let query = format!(
+            "select * from messages {where_statement} order by id {order} limit ${input_n}"
+        );
+
+        let messages = sqlx::query_as_with::<_, DbMessage, _>(&query, args)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(decode_rows("message", messages, |message| message.id))
+    }
+
+    async fn query_messages(
+        &self,
+        filter: &MessageFilter,
+        per_page: u32,
+        page: u32,
+    ) -> sqlx::Result<Vec<Message>> {
+        let mut args = SqliteArguments::default();
+        let (where_statement, input_n) = filter.where_clause(&mut args);
+        args.add(per_page);
+        args.add(page.saturating_mul(per_page));
+        let query = format!(
+            "select * from messages {where_statement} order by id desc limit ${input_n} offset ${}",
+            input_n + 1
+        );
+
+        let messages = sqlx::query_as_with::<_, DbMessage, _>(&query, args)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(decode_rows("message", messages, |message| message.id))
+    }
+
+    async fn export_messages(
+        &self,
+        filter: &MessageFilter,
+        from: u32,
+        limit: u32,
+    ) -> sqlx::Result<Vec<ExportRow>> {
+        let mut args = SqliteArguments::default();
+        let (where_statement, input_n) = filter.where_clause(&mut args);
+        let keyword = if where_statement.is_empty() {
+            "where"
+        } else {
+            " and"
+        };
+        args.add(from);
+        args.add(limit);
+        let query = format!(
+            "
+            select messages.id, messages.player_id, players.alias, players.uuid, messages.time, messages.content as message, messages.ty
+            from messages
+            join players on players.id = messages.player_id
+            {where_statement}{keyword} messages.id > ${input_n}
+            order by messages.id asc
+            limit ${};
+        ",
+            input_n + 1
+        );
+
+        sqlx::query_as_with::<_, ExportRow, _>(&query, args)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    async fn count_messages(&self, filter: &MessageFilter) -> sqlx::Result<u64> {
+        let mut args = SqliteArguments::default();
+        let (where_statement, _) = filter.where_clause(&mut args);
+        let query = format!("select count(*) from messages {where_statement}");
+
+        sqlx::query_scalar_with::<_, i64, _>(&query, args)
+            .fetch_one(&self.pool)
+            .await
+            .map(|count| count as u64)
+    }
+
+    async fn latest_ids(&self) -> sqlx::Result<(u32, u32)> {
+        sqlx::query_as::<_, (u32, u32)>(
+            "
+            select
+                (select coalesce(max(id), 0) from messages),
+                (select coalesce(max(id), 0) from activity);
+        ",
+        )
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    async fn ping(&self) -> sqlx::Result<()> {
+        sqlx::query("select 1;").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn activity_after(&self, id: u32, limit: u32) -> sqlx::Result<Vec<Activity>> {
+        sqlx::query_as::<_, Activity>(
+            "
+            select id, player_id, online
+            from activity
+            where id > $1
+            order by id asc
+            limit $2;
+        ",
+        )
+        .bind(id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn activity(&self, player_id: u32) -> sqlx::Result<Vec<(DateTime<Utc>, bool)>> {
+        let activity = sqlx::query_as::<_, DbActivity>(
+            "
+            select time, online
+            from activity
+            where player_id = ?
+            order by id asc;
+        ",
+        )
+        .bind(player_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(activity.into_iter().map(|a| (a.time, a.online)).collect())
+    }
+
+    async fn report_messages(&self, report_id: u32) -> sqlx::Result<Vec<Message>> {
+        reports::linked_messages(&mut *self.pool.acquire().await?, report_id).await
+    }
+
+    async fn ticket_players(&self, ticket_id: u32) -> sqlx::Result<Vec<Player>> {
+        tickets::linked_players(&mut *self.pool.acquire().await?, ticket_id).await
+    }
+
+    async fn ticket_messages(&self, ticket_id: u32) -> sqlx::Result<Vec<Message>> {
+        tickets::linked_messages(&mut *self.pool.acquire().await?, ticket_id).await
+    }
+
+    async fn legal_hold(&self, id: u32, hold: Option<bool>) -> sqlx::Result<Option<bool>> {
+        retention::set_legal_hold(&mut *self.pool.acquire().await?, id, hold).await
+    }
+
+    async fn prune(&self, config: &RetentionConfig) -> sqlx::Result<PruneRun> {
+        let mut tx = self.pool.begin().await?;
+        let run = retention::prune(&mut tx, config).await?;
+        tx.commit().await?;
+        Ok(run)
+    }
+
+    async fn player_data(&self, id: u32) -> sqlx::Result<Option<PlayerData>> {
+        player_data::load(&mut *self.pool.acquire().await?, id).await
+    }
+
+    async fn erase_player(
+        &self,
+        id: u32,
+        mode: ErasureMode,
+        operator: &str,
+        reason: &str,
+    ) -> sqlx::Result<Option<Erasure>> {
+        let mut tx = self.pool.begin().await?;
+        let erasure = player_data::erase(&mut tx, id, mode, operator, reason).await?;
+        tx.commit().await?;
+        Ok(erasure)
+    }
+}
@@ -0,0 +1,340 @@
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use veloren_common::uuid::Uuid;
+
+use super::{ChatStore, ChatWriter, Player, Store};
+use crate::{
+    export::ExportRow,
+    messages::Direction,
+    metrics::Metrics,
+    player_data::{Erasure, ErasureMode, PlayerData},
+    reports::Report,
+    retention::{PruneRun, RetentionConfig},
+    rules::{Action, RuleFiring},
+    tickets::Ticket,
+    Activity, Message, MessageFilter, MessageType,
+};
+
+/// Wraps another store and records the latency of every query.
+pub struct TimedStore {
+    inner: Store,
+    metrics: Metrics,
+}
+
+impl TimedStore {
+    pub fn new(inner: Store, metrics: Metrics) -> Self {
+        Self { inner, metrics }
+    }
+}
+
+pub struct TimedWriter {
+    inner: Box<dyn ChatWriter>,
+    metrics: Metrics,
+}
+
+#[rocket::async_trait]
+impl ChatWriter for TimedWriter {
+    async fn upsert_player(&mut self, uuid: &str, alias: &str) -> sqlx::Result<u32> {
+        let start = Instant::now();
+        let result = self.inner.upsert_player(uuid, alias).await;
+        self.metrics.query_done("upsert_player", start);
+        result
+    }
+
+    async fn insert_message(
+        &mut self,
+        player_id: u32,
+        time: DateTime<Utc>,
+        content: &str,
+        ty: &MessageType,
+        spam_score: f32,
+        recipient_id: Option<u32>,
+    ) -> sqlx::Result<u32> {
+        let start = Instant::now();
+        let result = self
+            .inner
+            .insert_message(player_id, time, content, ty, spam_score, recipient_id)
+            .await;
+        self.metrics.query_done("insert_message", start);
+        result
+    }
+
+    async fn insert_activity(
+        &mut self,
+        player_id: u32,
+        time: DateTime<Utc>,
+        online: bool,
+    ) -> sqlx::Result<u32> {
+        let start = Instant::now();
+        let result = self.inner.insert_activity(player_id, time, online).await;
+        self.metrics.query_done("insert_activity", start);
+        result
+    }
+
+    async fn record_alias(
+        &mut self,
+        player_id: u32,
+        alias: &str,
+        time: DateTime<Utc>,
+    ) -> sqlx::Result<()> {
+        let start = Instant::now();
+        let result = self.inner.record_alias(player_id, alias, time).await;
+        self.metrics.query_done("record_alias", start);
+        result
+    }
+
+    async fn player_context(
+        &mut self,
+        player_id: u32,
+    ) -> sqlx::Result<(Vec<String>, Option<DateTime<Utc>>)> {
+        let start = Instant::now();
+        let result = self.inner.player_context(player_id).await;
+        self.metrics.query_done("player_context", start);
+        result
+    }
+
+    async fn log_firing(
+        &mut self,
+        rule_id: u32,
+        message_id: u32,
+        player_id: u32,
+        time: DateTime<Utc>,
+        action: &Action,
+        dry_run: bool,
+    ) -> sqlx::Result<RuleFiring> {
+        let start = Instant::now();
+        let result = self
+            .inner
+            .log_firing(rule_id, message_id, player_id, time, action, dry_run)
+            .await;
+        self.metrics.query_done("log_firing", start);
+        result
+    }
+
+    async fn find_reported(
+        &mut self,
+        alias: &str,
+        uuid: Option<Uuid>,
+    ) -> sqlx::Result<Option<u32>> {
+        let start = Instant::now();
+        let result = self.inner.find_reported(alias, uuid).await;
+        self.metrics.query_done("find_reported", start);
+        result
+    }
+
+    async fn file_report(
+        &mut self,
+        reporter_id: u32,
+        reported_id: u32,
+        reason: &str,
+        time: DateTime<Utc>,
+    ) -> sqlx::Result<Report> {
+        let start = Instant::now();
+        let result = self
+            .inner
+            .file_report(reporter_id, reported_id, reason, time)
+            .await;
+        self.metrics.query_done("file_report", start);
+        result
+    }
+
+    async fn ticket_for_report(&mut self, report: &Report) -> sqlx::Result<Ticket> {
+        let start = Instant::now();
+        let result = self.inner.ticket_for_report(report).await;
+        self.metrics.query_done("ticket_for_report", start);
+        result
+    }
+
+    async fn commit(self: Box<Self>) -> sqlx::Result<()> {
+        let TimedWriter { inner, metrics } = *self;
+        let start = Instant::now();
+        let result = inner.commit().await;
+        metrics.query_done("commit", start);
+        result
+    }
+}
+
+#[rocket::async_trait]
+impl ChatStore for TimedStore {
+    async fn begin(&self) -> sqlx::Result<Box<dyn ChatWriter>> {
+        let start = Instant::now();
+        let inner = self.inner.begin().await;
+        self.metrics.query_done("begin", start);
+        Ok(Box::new(TimedWriter {
+            inner: inner?,
+            metrics: self.metrics.clone(),
+        }))
+    }
+
+    async fn player_alias(&self, id: u32) -> sqlx::Result<Option<String>> {
+        let start = Instant::now();
+        let result = self.inner.player_alias(id).await;
+        self.metrics.query_done("player_alias", start);
+        result
+    }
+
+    async fn find_players(&self, alias: &str) -> sqlx::Result<Vec<u32>> {
+        let start = Instant::now();
+        let result = self.inner.find_players(alias).await;
+        self.metrics.query_done("find_players", start);
+        result
+    }
+
+    async fn player(&self, id: u32) -> sqlx::Result<Option<Player>> {
+        let start = Instant::now();
+        let result = self.inner.player(id).await;
+        self.metrics.query_done("player", start);
+        result
+    }
+
+    async fn list_players(
+        &self,
+        alias: Option<&str>,
+        from: Option<u32>,
+        direction: Direction,
+        limit: u32,
+    ) -> sqlx::Result<Vec<Player>> {
+        let start = Instant::now();
+        let result = self.inner.list_players(alias, from, direction, limit).await;
+        self.metrics.query_done("list_players", start);
+        result
+    }
+
+    async fn message(&self, id: u32) -> sqlx::Result<Option<Message>> {
+        let start = Instant::now();
+        let result = self.inner.message(id).await;
+        self.metrics.query_done("message", start);
+        result
+    }
+
+    async fn list_messages(
+        &self,
+        filter: &MessageFilter,
+        from: Option<u32>,
+        direction: Direction,
+        limit: u32,
+    ) -> sqlx::Result<Vec<Message>> {
+        let start = Instant::now();
+        let result = self
+            .inner
+            .list_messages(filter, from, direction, limit)
+            .await;
+        self.metrics.query_done("list_messages", start);
+        result
+    }
+
+    async fn query_messages(
+        &self,
+        filter: &MessageFilter,
+        per_page: u32,
+        page: u32,
+    ) -> sqlx::Result<Vec<Message>> {
+        let start = Instant::now();
+        let result = self.inner.query_messages(filter, per_page, page).await;
+        self.metrics.query_done("query_messages", start);
+        result
+    }
+
+    async fn export_messages(
+        &self,
+        filter: &MessageFilter,
+        from: u32,
+        limit: u32,
+    ) -> sqlx::Result<Vec<ExportRow>> {
+        let start = Instant::now();
+        let result = self.inner.export_messages(filter, from, limit).await;
+        self.metrics.query_done("export_messages", start);
+        result
+    }
+
+    async fn count_messages(&self, filter: &MessageFilter) -> sqlx::Result<u64> {
+        let start = Instant::now();
+        let result = self.inner.count_messages(filter).await;
+        self.metrics.query_done("count_messages", start);
+        result
+    }
+
+    async fn latest_ids(&self) -> sqlx::Result<(u32, u32)> {
+        let start = Instant::now();
+        let result = self.inner.latest_ids().await;
+        self.metrics.query_done("latest_ids", start);
+        result
+    }
+
+    async fn ping(&self) -> sqlx::Result<()> {
+        let start = Instant::now();
+        let result = self.inner.ping().await;
+        self.metrics.query_done("ping", start);
+        result
+    }
+
+    async fn activity_after(&self, id: u32, limit: u32) -> sqlx::Result<Vec<Activity>> {
+        let start = Instant::now();
+        let result = self.inner.activity_after(id, limit).await;
+        self.metrics.query_done("activity_after", start);
+        result
+    }
+
+    async fn activity(&self, player_id: u32) -> sqlx::Result<Vec<(DateTime<Utc>, bool)>> {
+        let start = Instant::now();
+        let result = self.inner.activity(player_id).await;
+        self.metrics.query_done("activity", start);
+        result
+    }
+
+    async fn report_messages(&self, report_id: u32) -> sqlx::Result<Vec<Message>> {
+        let start = Instant::now();
+        let result = self.inner.report_messages(report_id).await;
+        self.metrics.query_done("report_messages", start);
+        result
+    }
+
+    async fn ticket_players(&self, ticket_id: u32) -> sqlx::Result<Vec<Player>> {
+        let start = Instant::now();
+        let result = self.inner.ticket_players(ticket_id).await;
+        self.metrics.query_done("ticket_players", start);
+        result
+    }
+
+    async fn ticket_messages(&self, ticket_id: u32) -> sqlx::Result<Vec<Message>> {
+        let start = Instant::now();
+        let result = self.inner.ticket_messages(ticket_id).await;
+        self.metrics.query_done("ticket_messages", start);
+        result
+    }
+
+    async fn legal_hold(&self, id: u32, hold: Option<bool>) -> sqlx::Result<Option<bool>> {
+        let start = Instant::now();
+        let result = self.inner.legal_hold(id, hold).await;
+        self.metrics.query_done("legal_hold", start);
+        result
+    }
+
+    async fn prune(&self, config: &RetentionConfig) -> sqlx::Result<PruneRun> {
+        let start = Instant::now();
+        let result = self.inner.prune(config).await;
+        self.metrics.query_done("prune", start);
+        result
+    }
+
+    async fn player_data(&self, id: u32) -> sqlx::Result<Option<PlayerData>> {
+        let start = Instant::now();
+        let result = self.inner.player_data(id).await;
+        self.metrics.query_done("player_data", start);
+        result
+    }
+
+    async fn erase_player(
+        &self,
+        id: u32,
+        mode: ErasureMode,
+        operator: &str,
+        reason: &str,
+    ) -> sqlx::Result<Option<Erasure>> {
+        let start = Instant::now();
+        let result = self.inner.erase_player(id, mode, operator, reason).await;
+        self.metrics.query_done("erase_player", start);
+        result
+    }
+}
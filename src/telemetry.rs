@@ -0,0 +1,100 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+use rocket::{
+    fairing::{Fairing, Info, Kind},
+    Data, Request, Response,
+};
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+/// Log settings, read from the `tracing` table in `Rocket.toml`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TracingConfig {
+    /// Filter directives like `info,sqlx=warn`, overridden by `RUST_LOG`.
+    pub level: String,
+    /// One JSON object per line instead of human readable output.
+    pub json: bool,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            json: false,
+        }
+    }
+}
+
+/// Installs the global subscriber. Rocket, sqlx and the Veloren client log
+/// through the `log` crate, their records are forwarded to it as well.
+pub fn init(config: &TracingConfig) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.level));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    let result = if config.json {
+        builder.json().try_init()
+    } else {
+        builder.try_init()
+    };
+    if let Err(e) = result {
+        eprintln!("Failed to set up logging: {e}");
+    }
+}
+
+/// When a request came in, cached on the request.
+struct RequestStart {
+    id: u64,
+    time: Instant,
+}
+
+/// Logs every request with its status and duration in an `http_request`
+/// span, and returns its id in the `X-Request-Id` header.
+#[derive(Default)]
+pub struct RequestTrace {
+    next_id: AtomicU64,
+}
+
+impl RequestTrace {
+    fn start(&self) -> RequestStart {
+        RequestStart {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            time: Instant::now(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RequestTrace {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request tracing",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, req: &mut Request<'_>, _: &mut Data<'_>) {
+        req.local_cache(|| self.start());
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        let start = req.local_cache(|| self.start());
+        let span = tracing::info_span!(
+            "http_request",
+            request_id = start.id,
+            method = %req.method(),
+            uri = %req.uri(),
+        );
+        span.in_scope(|| {
+            tracing::info!(
+                status = res.status().code,
+                elapsed_ms = start.time.elapsed().as_millis() as u64,
+                "Request finished"
+            );
+        });
+        res.set_raw_header("X-Request-Id", start.id.to_string());
+    }
+}
@@ -0,0 +1,539 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use rocket::{serde::json::Json, Route, State};
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::Template;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, SqliteConnection};
+use tokio::sync::broadcast::Sender;
+
+use crate::{
+    error::{decode_rows, ApiError, ApiResult, UnknownVariant},
+    reports::Report,
+    store::{Player, Store},
+    Db, DbMessage, Message, NetworkEvent,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, FromFormField)]
+pub enum TicketStatus {
+    Open,
+    Claimed,
+    Resolved,
+    Dismissed,
+}
+
+impl FromStr for TicketStatus {
+    type Err = UnknownVariant;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "Open" => Ok(TicketStatus::Open),
+            "Claimed" => Ok(TicketStatus::Claimed),
+            "Resolved" => Ok(TicketStatus::Resolved),
+            "Dismissed" => Ok(TicketStatus::Dismissed),
+            _ => Err(UnknownVariant::new("ticket status", value)),
+        }
+    }
+}
+
+impl Display for TicketStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TicketStatus::Open => "Open",
+            TicketStatus::Claimed => "Claimed",
+            TicketStatus::Resolved => "Resolved",
+            TicketStatus::Dismissed => "Dismissed",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ticket {
+    pub id: u32,
+    pub title: String,
+    pub status: TicketStatus,
+    pub assignee: Option<String>,
+    pub report_id: Option<u32>,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+#[derive(FromRow)]
+struct DbTicket {
+    id: u32,
+    title: String,
+    status: String,
+    assignee: Option<String>,
+    report_id: Option<u32>,
+    created: DateTime<Utc>,
+    updated: DateTime<Utc>,
+}
+
+impl TryFrom<DbTicket> for Ticket {
+    type Error = sqlx::Error;
+
+    fn try_from(ticket: DbTicket) -> Result<Self, Self::Error> {
+        Ok(Ticket {
+            id: ticket.id,
+            title: ticket.title,
+            status: ticket.status.parse()?,
+            assignee: ticket.assignee,
+            report_id: ticket.report_id,
+            created: ticket.created,
+            updated: ticket.updated,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+pub struct Comment {
+    pub id: u32,
+    pub ticket_id: u32,
+    pub author: String,
+    pub content: String,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TicketEventKind {
+    Created,
+    Claimed,
+    StatusChanged,
+    Commented(Comment),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TicketEvent {
+    pub kind: TicketEventKind,
+    pub ticket: Ticket,
+}
+
+async fn fetch_ticket(conn: &mut SqliteConnection, id: u32) -> ApiResult<Ticket> {
+    let ticket = sqlx::query_as::<_, DbTicket>("select * from tickets where id = ?;")
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("ticket {id}")))?;
+    Ok(Ticket::try_from(ticket)?)
+}
+
+async fn link(
+    conn: &mut SqliteConnection,
+    id: u32,
+    players: &[u32],
+    messages: &[u32],
+) -> sqlx::Result<()> {
+    for player_id in players {
+        sqlx::query("insert or ignore into ticket_players (ticket_id, player_id) values ($1, $2);")
+            .bind(id)
+            .bind(player_id)
+            .execute(&mut *conn)
+            .await?;
+    }
+    for message_id in messages {
+        sqlx::query(
+            "insert or ignore into ticket_messages (ticket_id, message_id) values ($1, $2);",
+        )
+        .bind(id)
+        .bind(message_id)
+        .execute(&mut *conn)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Refuses links to players or messages that don't exist, before anything is
+/// written.
+async fn check_links(
+    conn: &mut SqliteConnection,
+    players: &[u32],
+    messages: &[u32],
+) -> ApiResult<()> {
+    for player_id in players {
+        sqlx::query_scalar::<_, u32>("select id from players where id = ?;")
+            .bind(player_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or_else(|| ApiError::BadRequest(format!("player {player_id} does not exist")))?;
+    }
+    for message_id in messages {
+        sqlx::query_scalar::<_, u32>("select id from messages where id = ?;")
+            .bind(message_id)
+            .fetch_optional(&mut *conn)
+            .await?
+            .ok_or_else(|| ApiError::BadRequest(format!("message {message_id} does not exist")))?;
+    }
+    Ok(())
+}
+
+pub async fn create_ticket(
+    conn: &mut SqliteConnection,
+    title: &str,
+    report_id: Option<u32>,
+    players: &[u32],
+    messages: &[u32],
+) -> sqlx::Result<Ticket> {
+    let now = Utc::now();
+    let id = sqlx::query_scalar::<_, u32>(
+        "
+        insert into tickets (title, status, report_id, created, updated) values ($1, $2, $3, $4, $4);
+        select last_insert_rowid() as id;
+        ",
+    )
+    .bind(title)
+    .bind(TicketStatus::Open.to_string())
+    .bind(report_id)
+    .bind(now)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    link(conn, id, players, messages).await?;
+
+    Ok(Ticket {
+        id,
+        title: title.to_string(),
+        status: TicketStatus::Open,
+        assignee: None,
+        report_id,
+        created: now,
+        updated: now,
+    })
+}
+
+/// Opens a ticket for a freshly filed report, linked to both players and the
+/// report's chat context.
+pub async fn ticket_for_report(
+    conn: &mut SqliteConnection,
+    report: &Report,
+) -> sqlx::Result<Ticket> {
+    let messages = sqlx::query_scalar::<_, u32>(
+        "
+        select message_id
+        from report_messages
+        where report_id = ?;
+    ",
+    )
+    .bind(report.id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    create_ticket(
+        conn,
+        &format!("Report #{}: {}", report.id, report.reason),
+        Some(report.id),
+        &[report.reporter_id, report.reported_id],
+        &messages,
+    )
+    .await
+}
+
+#[derive(Serialize)]
+struct TicketPlayer {
+    id: u32,
+    alias: String,
+}
+
+#[get("/tickets")]
+async fn tickets_page() -> Template {
+    Template::render("tickets", ())
+}
+
+/// Players linked to the ticket `id`.
+pub async fn linked_players(conn: &mut SqliteConnection, id: u32) -> sqlx::Result<Vec<Player>> {
+    sqlx::query_as::<_, Player>(
+        "
+        select players.id, players.uuid, players.alias
+        from ticket_players
+        join players on players.id = ticket_players.player_id
+        where ticket_players.ticket_id = ?;
+    ",
+    )
+    .bind(id)
+    .fetch_all(&mut *conn)
+    .await
+}
+
+/// Messages linked to the ticket `id`, oldest first.
+pub async fn linked_messages(conn: &mut SqliteConnection, id: u32) -> sqlx::Result<Vec<Message>> {
+    let messages = sqlx::query_as::<_, DbMessage>(
+        "
+        select messages.*
+        from ticket_messages
+        join messages on messages.id = ticket_messages.message_id
+        where ticket_messages.ticket_id = ?
+        order by messages.id asc;
+    ",
+    )
+    .bind(id)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    Ok(decode_rows("message", messages, |message| message.id))
+}
+
+#[get("/ticket/<id>")]
+async fn ticket_page(mut db: Connection<Db>, store: &State<Store>, id: u32) -> ApiResult<Template> {
+    #[derive(Serialize)]
+    struct Context {
+        ticket: Ticket,
+        players: Vec<TicketPlayer>,
+        messages: Vec<Message>,
+        comments: Vec<Comment>,
+    }
+
+    let ticket = match fetch_ticket(&mut db, id).await {
+        Ok(ticket) => ticket,
+        Err(ApiError::NotFound(_)) => return Ok(Template::render("ticket_not_found", ())),
+        Err(e) => return Err(e),
+    };
+    let players = store
+        .ticket_players(id)
+        .await?
+        .into_iter()
+        .map(|player| TicketPlayer {
+            id: player.id,
+            alias: player.alias,
+        })
+        .collect();
+    let messages = store.ticket_messages(id).await?;
+    let comments = sqlx::query_as::<_, Comment>(
+        "
+        select *
+        from ticket_comments
+        where ticket_id = ?
+        order by id asc;
+    ",
+    )
+    .bind(id)
+    .fetch_all(&mut *db)
+    .await?;
+
+    Ok(Template::render(
+        "ticket",
+        Context {
+            ticket,
+            players,
+            messages,
+            comments,
+        },
+    ))
+}
+
+#[post("/tickets?<status>")]
+async fn list_tickets(
+    mut db: Connection<Db>,
+    status: Option<TicketStatus>,
+) -> ApiResult<Json<Vec<Ticket>>> {
+    let tickets = if let Some(status) = status {
+        sqlx::query_as::<_, DbTicket>(
+            "
+            select *
+            from tickets
+            where status = ?
+            order by id desc;
+        ",
+        )
+        .bind(status.to_string())
+        .fetch_all(&mut *db)
+    } else {
+        sqlx::query_as::<_, DbTicket>(
+            "
+            select *
+            from tickets
+            where status in ('Open', 'Claimed')
+            order by id desc;
+        ",
+        )
+        .fetch_all(&mut *db)
+    }
+    .await?;
+
+    Ok(Json(decode_rows("ticket", tickets, |ticket| ticket.id)))
+}
+
+#[derive(Deserialize)]
+struct NewTicket {
+    title: String,
+    #[serde(default)]
+    players: Vec<u32>,
+    #[serde(default)]
+    messages: Vec<u32>,
+}
+
+#[post("/tickets/create", data = "<ticket>")]
+async fn new_ticket(
+    mut db: Connection<Db>,
+    sx: &State<Sender<NetworkEvent>>,
+    ticket: Json<NewTicket>,
+) -> ApiResult<Json<Ticket>> {
+    let mut tx = sqlx::Connection::begin(&mut **db).await?;
+    check_links(&mut tx, &ticket.players, &ticket.messages).await?;
+    let ticket = create_ticket(
+        &mut tx,
+        &ticket.title,
+        None,
+        &ticket.players,
+        &ticket.messages,
+    )
+    .await?;
+    tx.commit().await?;
+    let _ = sx.send(NetworkEvent::Ticket(TicketEvent {
+        kind: TicketEventKind::Created,
+        ticket: ticket.clone(),
+    }));
+
+    Ok(Json(ticket))
+}
+
+#[derive(Deserialize)]
+struct TicketLinks {
+    #[serde(default)]
+    players: Vec<u32>,
+    #[serde(default)]
+    messages: Vec<u32>,
+}
+
+#[post("/tickets/<id>/link", data = "<links>")]
+async fn link_ticket(mut db: Connection<Db>, id: u32, links: Json<TicketLinks>) -> ApiResult<()> {
+    let mut tx = sqlx::Connection::begin(&mut **db).await?;
+    fetch_ticket(&mut tx, id).await?;
+    check_links(&mut tx, &links.players, &links.messages).await?;
+    link(&mut tx, id, &links.players, &links.messages).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+#[post("/tickets/<id>/claim?<assignee>")]
+async fn claim_ticket(
+    mut db: Connection<Db>,
+    sx: &State<Sender<NetworkEvent>>,
+    id: u32,
+    assignee: &str,
+) -> ApiResult<Json<Ticket>> {
+    // Claiming again is fine, taking over from someone else or reopening a
+    // closed ticket has to go through its status first.
+    let result = sqlx::query(
+        "
+        update tickets
+        set status = $1, assignee = $2, updated = $3
+        where id = $4
+            and ((status = 'Open' and assignee is null) or (status = 'Claimed' and assignee = $2));
+    ",
+    )
+    .bind(TicketStatus::Claimed.to_string())
+    .bind(assignee)
+    .bind(Utc::now())
+    .bind(id)
+    .execute(&mut *db)
+    .await?;
+
+    let ticket = fetch_ticket(&mut db, id).await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::Conflict(match &ticket.assignee {
+            Some(current) if ticket.status == TicketStatus::Claimed => {
+                format!("ticket {id} is already claimed by {current}")
+            }
+            _ => format!("ticket {id} is {}", ticket.status),
+        }));
+    }
+    let _ = sx.send(NetworkEvent::Ticket(TicketEvent {
+        kind: TicketEventKind::Claimed,
+        ticket: ticket.clone(),
+    }));
+
+    Ok(Json(ticket))
+}
+
+#[post("/tickets/<id>/status?<status>")]
+async fn set_ticket_status(
+    mut db: Connection<Db>,
+    sx: &State<Sender<NetworkEvent>>,
+    id: u32,
+    status: TicketStatus,
+) -> ApiResult<Json<Ticket>> {
+    // A claimed ticket always has an assignee, set by claiming it.
+    let result = sqlx::query(
+        "
+        update tickets
+        set status = $1, assignee = case when $1 = 'Open' then null else assignee end, updated = $2
+        where id = $3 and ($1 != 'Claimed' or assignee is not null);
+    ",
+    )
+    .bind(status.to_string())
+    .bind(Utc::now())
+    .bind(id)
+    .execute(&mut *db)
+    .await?;
+
+    let ticket = fetch_ticket(&mut db, id).await?;
+    if result.rows_affected() == 0 {
+        return Err(ApiError::Conflict(format!(
+            "ticket {id} has no assignee, claim it instead"
+        )));
+    }
+    let _ = sx.send(NetworkEvent::Ticket(TicketEvent {
+        kind: TicketEventKind::StatusChanged,
+        ticket: ticket.clone(),
+    }));
+
+    Ok(Json(ticket))
+}
+
+#[derive(Deserialize)]
+struct NewComment {
+    author: String,
+    content: String,
+}
+
+#[post("/tickets/<id>/comment", data = "<comment>")]
+async fn comment_ticket(
+    mut db: Connection<Db>,
+    sx: &State<Sender<NetworkEvent>>,
+    id: u32,
+    comment: Json<NewComment>,
+) -> ApiResult<Json<Comment>> {
+    let ticket = fetch_ticket(&mut db, id).await?;
+    let time = Utc::now();
+    let comment_id = sqlx::query_scalar::<_, u32>(
+        "
+        insert into ticket_comments (ticket_id, author, content, time) values ($1, $2, $3, $4);
+        select last_insert_rowid() as id;
+        ",
+    )
+    .bind(id)
+    .bind(&comment.author)
+    .bind(&comment.content)
+    .bind(time)
+    .fetch_one(&mut *db)
+    .await?;
+
+    let comment = Comment {
+        id: comment_id,
+        ticket_id: id,
+        author: comment.author.clone(),
+        content: comment.content.clone(),
+        time,
+    };
+    let _ = sx.send(NetworkEvent::Ticket(TicketEvent {
+        kind: TicketEventKind::Commented(comment.clone()),
+        ticket,
+    }));
+
+    Ok(Json(comment))
+}
+
+pub fn page_routes() -> Vec<Route> {
+    routes![tickets_page, ticket_page]
+}
+
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        list_tickets,
+        new_ticket,
+        link_ticket,
+        claim_ticket,
+        set_ticket_status,
+        comment_ticket
+    ]
+}
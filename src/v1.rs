@@ -0,0 +1,308 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use rocket::{serde::json::Json, Route, State};
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+
+use crate::{
+    error::{ApiError, ApiResult, ErrorBody},
+    messages::{paginate, start, Cursor, Direction},
+    sessions,
+    store::{self, Store},
+    Message, MessageFilter, MessageType, PlayerList,
+};
+
+/// A single resource.
+#[derive(Serialize, ToSchema)]
+#[aliases(PlayerData = Data<Player>, MessageData = Data<Message>)]
+pub struct Data<T> {
+    data: T,
+}
+
+/// A page of a listing, walked with the `cursor` parameter.
+#[derive(Serialize, ToSchema)]
+#[aliases(
+    PlayerPage = Page<Player>,
+    MessagePage = Page<Message>,
+    SessionPage = Page<Session>
+)]
+pub struct Page<T> {
+    data: Vec<T>,
+    /// Continues in the same direction. Always set when walking `newer`, to
+    /// poll for new entries, and only while there is more when walking
+    /// `older`.
+    #[schema(value_type = Option<String>)]
+    next: Option<Cursor>,
+    /// Walks back from the first entry of this page.
+    #[schema(value_type = Option<String>)]
+    prev: Option<Cursor>,
+}
+
+impl<T> Page<T> {
+    /// A complete listing, there are no other pages.
+    fn all(data: Vec<T>) -> Self {
+        Page {
+            data,
+            next: None,
+            prev: None,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Player {
+    id: u32,
+    uuid: String,
+    /// Latest alias the player was seen with.
+    alias: String,
+    online: bool,
+}
+
+impl Player {
+    fn new(player: store::Player, online: &HashSet<u32>) -> Self {
+        Player {
+            online: online.contains(&player.id),
+            id: player.id,
+            uuid: player.uuid,
+            alias: player.alias,
+        }
+    }
+}
+
+/// Time a player spent online, from login to logout.
+#[derive(Serialize, ToSchema)]
+pub struct Session {
+    player_id: u32,
+    start: DateTime<Utc>,
+    /// Unset while the player is still online.
+    end: Option<DateTime<Utc>>,
+    /// Length of the session so far in seconds.
+    duration: i64,
+}
+
+/// Lists players by id, optionally those whose alias contains `alias`.
+#[utoipa::path(
+    get,
+    path = "/api/v1/players",
+    params(
+        ("alias" = Option<String>, Query, description = "Part of the alias, ignoring case"),
+        ("cursor" = Option<String>, Query, description = "Cursor from a previous page"),
+        ("direction" = Option<Direction>, Query, description = "Defaults to `older`"),
+        ("limit" = Option<u32>, Query, description = "Page size, at most 500")
+    ),
+    responses(
+        (status = 200, body = PlayerPage),
+        (status = 400, body = ErrorBody)
+    )
+)]
+#[get("/players?<alias>&<cursor>&<direction>&<limit>")]
+async fn players(
+    store: &State<Store>,
+    player_list: &State<PlayerList>,
+    alias: Option<&str>,
+    cursor: Option<&str>,
+    direction: Option<Direction>,
+    limit: Option<u32>,
+) -> ApiResult<Json<PlayerPage>> {
+    let start = start(cursor, None, direction, alias.map(str::to_string), limit)?;
+
+    let mut players = store
+        .list_players(
+            start.listing.filter.as_deref(),
+            start.from,
+            start.direction,
+            start.listing.limit + 1,
+        )
+        .await?;
+    let (next, prev) = paginate(&mut players, |player| player.id, &start);
+
+    let online = player_list.read().await;
+    Ok(Json(Page {
+        data: players
+            .into_iter()
+            .map(|player| Player::new(player, &online))
+            .collect(),
+        next,
+        prev,
+    }))
+}
+
+/// Lists the players currently online.
+#[utoipa::path(
+    get,
+    path = "/api/v1/players/online",
+    responses((status = 200, body = PlayerPage))
+)]
+#[get("/players/online")]
+async fn online_players(
+    store: &State<Store>,
+    player_list: &State<PlayerList>,
+) -> ApiResult<Json<PlayerPage>> {
+    let online = player_list.read().await.clone();
+    let mut players = Vec::with_capacity(online.len());
+    for id in &online {
+        if let Some(player) = store.player(*id).await? {
+            players.push(Player::new(player, &online));
+        }
+    }
+    players.sort_by_key(|player| player.id);
+
+    Ok(Json(Page::all(players)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/players/{id}",
+    params(("id" = u32, Path)),
+    responses(
+        (status = 200, body = PlayerData),
+        (status = 404, body = ErrorBody)
+    )
+)]
+#[get("/players/<id>")]
+async fn player(
+    store: &State<Store>,
+    player_list: &State<PlayerList>,
+    id: u32,
+) -> ApiResult<Json<PlayerData>> {
+    let player = store
+        .player(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("player {id}")))?;
+
+    Ok(Json(Data {
+        data: Player::new(player, &*player_list.read().await),
+    }))
+}
+
+/// Lists all sessions of a player, oldest first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/players/{id}/sessions",
+    params(("id" = u32, Path)),
+    responses(
+        (status = 200, body = SessionPage),
+        (status = 404, body = ErrorBody)
+    )
+)]
+#[get("/players/<id>/sessions")]
+async fn player_sessions(store: &State<Store>, id: u32) -> ApiResult<Json<SessionPage>> {
+    if store.player(id).await?.is_none() {
+        return Err(ApiError::not_found(format!("player {id}")));
+    }
+    let now = Utc::now();
+    let sessions = sessions(store.activity(id).await?)
+        .into_iter()
+        .map(|(start, end)| Session {
+            player_id: id,
+            start,
+            end,
+            duration: (end.unwrap_or(now) - start).num_seconds(),
+        })
+        .collect();
+
+    Ok(Json(Page::all(sessions)))
+}
+
+/// Lists messages matching the filter. Without a cursor, `older` starts at
+/// the latest message and `newer` at the first one.
+#[utoipa::path(
+    get,
+    path = "/api/v1/messages",
+    params(
+        ("cursor" = Option<String>, Query, description = "Cursor from a previous page"),
+        ("from" = Option<u32>, Query, description = "Start next to this message"),
+        ("direction" = Option<Direction>, Query, description = "Defaults to `older`"),
+        ("limit" = Option<u32>, Query, description = "Page size, at most 500"),
+        MessageFilter
+    ),
+    responses(
+        (status = 200, body = MessagePage),
+        (status = 400, body = ErrorBody)
+    )
+)]
+#[get("/messages?<cursor>&<from>&<direction>&<limit>&<filter..>")]
+async fn messages(
+    store: &State<Store>,
+    cursor: Option<&str>,
+    from: Option<u32>,
+    direction: Option<Direction>,
+    limit: Option<u32>,
+    filter: MessageFilter,
+) -> ApiResult<Json<MessagePage>> {
+    let start = start(cursor, from, direction, filter, limit)?;
+
+    let mut messages = store
+        .list_messages(
+            &start.listing.filter,
+            start.from,
+            start.direction,
+            start.listing.limit + 1,
+        )
+        .await?;
+    let (next, prev) = paginate(&mut messages, |message| message.id, &start);
+
+    Ok(Json(Page {
+        data: messages,
+        next,
+        prev,
+    }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/messages/{id}",
+    params(("id" = u32, Path)),
+    responses(
+        (status = 200, body = MessageData),
+        (status = 404, body = ErrorBody)
+    )
+)]
+#[get("/messages/<id>")]
+async fn message(store: &State<Store>, id: u32) -> ApiResult<Json<MessageData>> {
+    let message = store
+        .message(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(format!("message {id}")))?;
+
+    Ok(Json(Data { data: message }))
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Veloren mod panel", version = "1"),
+    paths(players, online_players, player, player_sessions, messages, message),
+    components(schemas(
+        Player,
+        Session,
+        Message,
+        MessageType,
+        Direction,
+        ErrorBody,
+        PlayerData,
+        MessageData,
+        PlayerPage,
+        MessagePage,
+        SessionPage
+    ))
+)]
+struct ApiDoc;
+
+/// The OpenAPI description of this API.
+#[get("/openapi.json")]
+fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        players,
+        online_players,
+        player,
+        player_sessions,
+        messages,
+        message,
+        openapi
+    ]
+}
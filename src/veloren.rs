@@ -1,4 +1,4 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use rocket::futures::FutureExt;
 use std::{
     ops::{Deref, DerefMut},
@@ -8,19 +8,41 @@ use std::{
 };
 use tokio::{
     runtime::Runtime,
-    sync::mpsc::{error::SendError, Sender},
+    sync::{
+        broadcast,
+        mpsc::{error::SendError, Receiver, Sender},
+        RwLock,
+    },
+    task::JoinHandle,
 };
 use veloren_client::{addr::ConnectionArgs, Client as VelorenClient, Event as VelorenEvent};
 use veloren_common::{
     clock::Clock,
     comp,
+    uid::Uid,
     util::{GIT_DATE, GIT_HASH},
 };
 
-use crate::MessageType;
+use crate::{metrics::Metrics, MessageType};
 
 const TPS: f64 = 10.0;
 
+/// Where and as whom the bot logs in.
+pub struct BotConfig {
+    pub addr: ConnectionArgs,
+    pub username: String,
+    pub password: String,
+    pub trusted_auth_server: String,
+}
+
+/// Something the panel wants the bot to do in game.
+#[derive(Debug)]
+pub enum BotCommand {
+    World { message: String },
+    Tell { alias: String, message: String },
+    Mute { alias: String, secs: u64, reason: String },
+}
+
 async fn connect_to_veloren(
     addr: ConnectionArgs,
     veloren_username: &str,
@@ -30,7 +52,7 @@ async fn connect_to_veloren(
 ) -> veloren_client::Client {
     let mut retry_cnt = 0u32;
     'connect: loop {
-        rocket::debug!("Connecting...");
+        tracing::debug!("Connecting...");
         let mut mismatched_server_info = None;
         let veloren_client = match VelorenClient::new(
             addr.clone(),
@@ -44,13 +66,13 @@ async fn connect_to_veloren(
         {
             Ok(client) => client,
             Err(e) => {
-                rocket::error!(
+                tracing::error!(
                     "Failed to connect to Veloren server: {:?}, retry: {}",
                     e,
                     retry_cnt
                 );
                 if let Some(server_info) = mismatched_server_info {
-                    rocket::error!(
+                    tracing::error!(
                         "This is likely due to a version mismatch: Client Version {}-{}, Server Version {}-{}",
                         *GIT_HASH, *GIT_DATE,
                         server_info.git_hash, server_info.git_date
@@ -62,7 +84,7 @@ async fn connect_to_veloren(
             }
         };
 
-        rocket::debug!("Logged in.");
+        tracing::debug!("Logged in.");
 
         return veloren_client;
     }
@@ -92,6 +114,23 @@ impl Client {
     fn send(&self, value: crate::VelorenEvent) -> Result<(), SendError<crate::VelorenEvent>> {
         self.runtime.block_on(self.sx.send(value))
     }
+
+    fn execute(&mut self, command: BotCommand) {
+        tracing::debug!(?command, "Executing bot command");
+        match command {
+            BotCommand::World { message } => {
+                self.send_command("world".to_string(), vec![message])
+            }
+            BotCommand::Tell { alias, message } => {
+                self.send_command("tell".to_string(), vec![alias, message])
+            }
+            BotCommand::Mute {
+                alias,
+                secs,
+                reason,
+            } => self.send_command("mute".to_string(), vec![alias, format!("{secs}s"), reason]),
+        }
+    }
 }
 
 impl Drop for Client {
@@ -107,16 +146,49 @@ impl Drop for Client {
     }
 }
 
+/// What the bot loop last did, read by the health checks.
+#[derive(Clone, Debug, Default)]
+pub struct BotStatus {
+    pub connected: bool,
+    /// Last time the client ticked, the loop has stalled if this gets old.
+    pub last_tick: Option<DateTime<Utc>>,
+    /// Last time the game server sent the bot anything.
+    pub last_event: Option<DateTime<Utc>>,
+}
+
+pub type BotState = Arc<RwLock<BotStatus>>;
+
+fn send_connection(
+    status: &broadcast::Sender<crate::NetworkEvent>,
+    state: &BotState,
+    connected: bool,
+) {
+    state.blocking_write().connected = connected;
+    let _ = status.send(crate::NetworkEvent::Connection(crate::BotConnection {
+        connected,
+        time: Utc::now(),
+    }));
+}
+
+/// Runs the bot on a blocking thread until `shutdown`, the handle completes
+/// once it has disconnected.
 pub fn run(
-    addr: ConnectionArgs,
-    veloren_username: String,
-    veloren_password: String,
-    trusted_auth_server: String,
+    config: BotConfig,
     sx: Sender<crate::VelorenEvent>,
+    mut commands: Receiver<BotCommand>,
+    status: broadcast::Sender<crate::NetworkEvent>,
+    state: BotState,
+    metrics: Metrics,
     runtime: Arc<Runtime>,
     mut shutdown: rocket::Shutdown,
-) {
+) -> JoinHandle<()> {
     tokio::task::spawn_blocking(move || {
+        let BotConfig {
+            addr,
+            username: veloren_username,
+            password: veloren_password,
+            trusted_auth_server,
+        } = config;
         let mut retry_cnt = 0u32;
 
         let mut client = Client {
@@ -130,19 +202,30 @@ pub fn run(
             )),
             runtime,
         };
+        send_connection(&status, &state, true);
 
         let mut sent_players = false;
 
         let mut clock = Clock::new(Duration::from_secs_f64(1.0 / TPS));
+        let mut tick_cnt = 0u64;
 
         loop {
             if (&mut shutdown).now_or_never().is_some() {
                 break;
             }
-            let events = match client.tick(comp::ControllerInputs::default(), clock.dt(), |_| {}) {
+            tick_cnt += 1;
+            let _span = tracing::info_span!("bot_tick", tick = tick_cnt).entered();
+            let tick = metrics.bot_tick_seconds.start_timer();
+            let events = client.tick(comp::ControllerInputs::default(), clock.dt(), |_| {});
+            tick.observe_duration();
+            let events = match events {
                 Ok(events) => events,
                 Err(e) => {
-                    rocket::error!("Failed to tick client: {:?}, retry: {}", e, retry_cnt);
+                    tracing::error!(error = ?e, retry = retry_cnt, "Failed to tick client");
+                    metrics.bot_tick_errors.inc();
+                    if retry_cnt == 0 {
+                        send_connection(&status, &state, false);
+                    }
                     retry_cnt += 1;
                     thread::sleep(Duration::from_secs(10) * retry_cnt);
                     client.client = client.runtime.block_on(connect_to_veloren(
@@ -152,25 +235,42 @@ pub fn run(
                         &trusted_auth_server,
                         Arc::clone(&client.runtime),
                     ));
+                    metrics.bot_reconnects.inc();
+                    send_connection(&status, &state, true);
                     continue;
                 }
             };
+            {
+                let mut state = state.blocking_write();
+                let now = Utc::now();
+                state.last_tick = Some(now);
+                if !events.is_empty() {
+                    state.last_event = Some(now);
+                }
+            }
 
             if !sent_players && !client.player_list().is_empty() {
                 for (_, info) in client.player_list() {
-                    let Ok(_) = client.send(crate::VelorenEvent {
+                    let sent = client.send(crate::VelorenEvent {
                         player_alias: info.player_alias.clone(),
                         player_uuid: info.uuid,
                         time: Utc::now(),
                         kind: crate::VelorenEventKind::Activity { online: true },
-                    }) else {
-                        panic!();
-                    };
+                    });
+                    if sent.is_err() {
+                        tracing::error!("Ingestion has stopped, player list not recorded");
+                        break;
+                    }
                 }
                 sent_players = true;
             }
             retry_cnt = 0;
 
+            while let Ok(command) = commands.try_recv() {
+                client.execute(command);
+            }
+
+            let mut replies = Vec::new();
             for event in events {
                 match event {
                     VelorenEvent::Chat(msg) => {
@@ -178,8 +278,11 @@ pub fn run(
 
                         use veloren_common::comp::chat::ChatType;
 
-                        let send_message = |uid, ty: MessageType| {
+                        let send_message = |uid, ty: MessageType, to: Option<Uid>| {
                             if let Some(info) = client.player_list().get(&uid) {
+                                let recipient = to
+                                    .and_then(|to| client.player_list().get(&to))
+                                    .map(|to| (to.player_alias.clone(), to.uuid));
                                 let message = message
                                     .split_once(':')
                                     .map(|(_, message)| message)
@@ -189,7 +292,7 @@ pub fn run(
                                     player_alias: info.player_alias.clone(),
                                     player_uuid: info.uuid,
                                     time: Utc::now(),
-                                    kind: crate::VelorenEventKind::Message { message: message.to_string(), ty }
+                                    kind: crate::VelorenEventKind::Message { message: message.to_string(), ty, recipient }
                                 }) else {
                                     return;
                                 };
@@ -216,27 +319,100 @@ pub fn run(
                             ChatType::Offline(uid) => {
                                 send_activity(uid, false);
                             }
-                            ChatType::World(uid) => send_message(uid, MessageType::World),
-                            ChatType::Tell(uid, _) => send_message(uid, MessageType::Tell),
-                            ChatType::Faction(uid, _) => send_message(uid, MessageType::Faction),
+                            ChatType::World(uid) => send_message(uid, MessageType::World, None),
+                            ChatType::Tell(uid, to) => {
+                                send_message(uid, MessageType::Tell, Some(to));
+                                if Some(to) == client.uid()
+                                    && let Some(reporter) = client.player_list().get(&uid)
+                                {
+                                    let message = message
+                                        .split_once(':')
+                                        .map(|(_, message)| message)
+                                        .unwrap_or(&message);
+                                    match parse_report(message) {
+                                        ReportTell::Report { player, reason } => {
+                                            let reported_uuid = client
+                                                .player_list()
+                                                .values()
+                                                .find(|info| {
+                                                    info.player_alias.eq_ignore_ascii_case(player)
+                                                })
+                                                .map(|info| info.uuid);
+                                            let _ = client.send(crate::VelorenEvent {
+                                                player_alias: reporter.player_alias.clone(),
+                                                player_uuid: reporter.uuid,
+                                                time: Utc::now(),
+                                                kind: crate::VelorenEventKind::Report {
+                                                    reported_alias: player.to_string(),
+                                                    reported_uuid,
+                                                    reason: reason.to_string(),
+                                                },
+                                            });
+                                        }
+                                        ReportTell::Malformed => replies.push(BotCommand::Tell {
+                                            alias: reporter.player_alias.clone(),
+                                            message: REPORT_USAGE.to_string(),
+                                        }),
+                                        ReportTell::NotAReport => {}
+                                    }
+                                }
+                            }
+                            ChatType::Faction(uid, _) => {
+                                send_message(uid, MessageType::Faction, None)
+                            }
                             _ => {}
                         }
                     }
                     VelorenEvent::Disconnect => {}
                     VelorenEvent::DisconnectionNotification(_) => {
-                        rocket::debug!("Will be disconnected soon! :/")
+                        tracing::debug!("Will be disconnected soon! :/")
                     }
                     VelorenEvent::Notification(notification) => {
-                        rocket::debug!("Notification: {:?}", notification);
+                        tracing::debug!("Notification: {:?}", notification);
                     }
                     _ => {}
                 }
             }
+            for reply in replies {
+                client.execute(reply);
+            }
             client.cleanup();
 
             clock.tick();
         }
-    });
+    })
+}
+
+const REPORT_USAGE: &str = "Usage: report <player> <reason>";
+
+/// A tell sent to the bot, as far as reports are concerned.
+#[derive(Debug, PartialEq, Eq)]
+enum ReportTell<'a> {
+    NotAReport,
+    /// Starts with `report` but lacks the player or the reason.
+    Malformed,
+    Report { player: &'a str, reason: &'a str },
+}
+
+fn parse_report(message: &str) -> ReportTell<'_> {
+    /// Splits off the first word, whatever whitespace surrounds it, and keeps
+    /// the rest of the text as it is.
+    fn first_word(text: &str) -> (&str, &str) {
+        let text = text.trim_start();
+        text.split_once(char::is_whitespace).unwrap_or((text, ""))
+    }
+
+    let (command, rest) = first_word(message);
+    if !command.eq_ignore_ascii_case("report") {
+        return ReportTell::NotAReport;
+    }
+    let (player, reason) = first_word(rest);
+    let reason = reason.trim();
+    if player.is_empty() || reason.is_empty() {
+        ReportTell::Malformed
+    } else {
+        ReportTell::Report { player, reason }
+    }
 }
 
 pub fn env_key<T>(key: &str) -> T
@@ -249,3 +425,71 @@ where
         .parse()
         .unwrap_or_else(|_| panic!("'{}' couldn't be parsed.", key))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_command_ignores_case() {
+        assert_eq!(
+            parse_report("REPORT Griefer spamming"),
+            ReportTell::Report {
+                player: "Griefer",
+                reason: "spamming",
+            }
+        );
+        assert_eq!(
+            parse_report("Report griefer spamming"),
+            ReportTell::Report {
+                player: "griefer",
+                reason: "spamming",
+            }
+        );
+    }
+
+    #[test]
+    fn report_keeps_the_whole_reason() {
+        assert_eq!(
+            parse_report("  report Griefer   broke my house twice  "),
+            ReportTell::Report {
+                player: "Griefer",
+                reason: "broke my house twice",
+            }
+        );
+    }
+
+    #[test]
+    fn report_allows_any_whitespace_around_the_player() {
+        assert_eq!(
+            parse_report("report  Griefer spam"),
+            ReportTell::Report {
+                player: "Griefer",
+                reason: "spam",
+            }
+        );
+        assert_eq!(
+            parse_report("report\tGriefer \t spam  in chat"),
+            ReportTell::Report {
+                player: "Griefer",
+                reason: "spam  in chat",
+            }
+        );
+    }
+
+    #[test]
+    fn report_without_player_or_reason_is_malformed() {
+        assert_eq!(parse_report("report"), ReportTell::Malformed);
+        assert_eq!(parse_report("report  "), ReportTell::Malformed);
+        assert_eq!(parse_report("report Griefer"), ReportTell::Malformed);
+        assert_eq!(parse_report("report Griefer   "), ReportTell::Malformed);
+    }
+
+    #[test]
+    fn other_tells_are_not_reports() {
+        assert_eq!(parse_report(""), ReportTell::NotAReport);
+        assert_eq!(parse_report("hello there"), ReportTell::NotAReport);
+        assert_eq!(parse_report("reports Griefer spamming"), ReportTell::NotAReport);
+        assert_eq!(parse_report("please report Griefer"), ReportTell::NotAReport);
+    }
+}
@@ -0,0 +1,478 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use regex::Regex;
+use rocket::{
+    serde::json::{self, Json},
+    Route, State,
+};
+use rocket_db_pools::Connection;
+use rocket_dyn_templates::Template;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::{FromRow, Pool, Sqlite, SqliteExecutor};
+use tokio::sync::{
+    broadcast::{error::RecvError, Receiver},
+    mpsc::{self, error::TrySendError},
+    RwLock,
+};
+
+use crate::{error::ApiResult, tickets::TicketEventKind, Db, MessageType, NetworkEvent};
+
+/// Header carrying the hex encoded HMAC-SHA256 of the body, keyed with the
+/// endpoint's secret.
+const SIGNATURE_HEADER: &str = "X-Mod-Panel-Signature";
+
+/// Delivery settings, read from the `webhooks` table in `Rocket.toml`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after every failed attempt.
+    pub initial_backoff_ms: u64,
+    pub timeout_secs: u64,
+    /// Deliveries waiting per endpoint, further events are dropped while it
+    /// is full.
+    pub queue_size: usize,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 1000,
+            timeout_secs: 10,
+            queue_size: 100,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EventKind {
+    Message,
+    Join,
+    Leave,
+    Alert,
+    Moderation,
+    Report,
+    Ticket,
+    BotConnect,
+    BotDisconnect,
+}
+
+impl EventKind {
+    pub fn of(event: &NetworkEvent) -> Option<Self> {
+        Some(match event {
+            NetworkEvent::Message(_) => EventKind::Message,
+            NetworkEvent::Activity(activity) if activity.online => EventKind::Join,
+            NetworkEvent::Activity(_) => EventKind::Leave,
+            NetworkEvent::Alert(_) => EventKind::Alert,
+            NetworkEvent::RuleFiring(firing) if !firing.dry_run => EventKind::Moderation,
+            NetworkEvent::RuleFiring(_) => return None,
+            NetworkEvent::Report(_) => EventKind::Report,
+            NetworkEvent::Ticket(ticket) => match ticket.kind {
+                TicketEventKind::Created | TicketEventKind::Claimed => EventKind::Ticket,
+                _ => return None,
+            },
+            NetworkEvent::Connection(connection) if connection.connected => EventKind::BotConnect,
+            NetworkEvent::Connection(_) => EventKind::BotDisconnect,
+        })
+    }
+}
+
+/// Restricts which messages are sent to an endpoint, every set field has to
+/// match.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MessageFilter {
+    #[serde(default)]
+    pub types: Vec<MessageType>,
+    #[serde(default)]
+    pub player_ids: Vec<u32>,
+    #[serde(default, with = "serde_regex")]
+    pub pattern: Option<Regex>,
+}
+
+impl MessageFilter {
+    fn matches(&self, event: &NetworkEvent) -> bool {
+        let NetworkEvent::Message(message) = event else {
+            return true;
+        };
+        (self.types.is_empty() || self.types.contains(&message.ty))
+            && (self.player_ids.is_empty() || self.player_ids.contains(&message.player_id))
+            && self
+                .pattern
+                .as_ref()
+                .map_or(true, |pattern| pattern.is_match(&message.message))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Webhook {
+    #[serde(default)]
+    pub id: Option<u32>,
+    pub url: String,
+    /// Never sent to the browser, saving an existing webhook with an empty
+    /// secret keeps the stored one.
+    #[serde(default, skip_serializing)]
+    pub secret: String,
+    pub events: Vec<EventKind>,
+    #[serde(default)]
+    pub filter: MessageFilter,
+    pub enabled: bool,
+}
+
+pub type WebhookSet = Arc<RwLock<Vec<Webhook>>>;
+
+#[derive(FromRow)]
+struct DbWebhook {
+    id: u32,
+    url: String,
+    secret: String,
+    events: String,
+    filter: String,
+    enabled: bool,
+}
+
+impl TryFrom<DbWebhook> for Webhook {
+    type Error = json::serde_json::Error;
+
+    fn try_from(webhook: DbWebhook) -> Result<Self, Self::Error> {
+        Ok(Webhook {
+            id: Some(webhook.id),
+            url: webhook.url,
+            secret: webhook.secret,
+            events: json::from_str(&webhook.events)?,
+            filter: json::from_str(&webhook.filter)?,
+            enabled: webhook.enabled,
+        })
+    }
+}
+
+pub async fn load_webhooks<'e>(executor: impl SqliteExecutor<'e>) -> sqlx::Result<Vec<Webhook>> {
+    Ok(sqlx::query_as::<_, DbWebhook>("select * from webhooks order by id;")
+        .fetch_all(executor)
+        .await?
+        .into_iter()
+        .filter_map(|webhook| {
+            let id = webhook.id;
+            Webhook::try_from(webhook)
+                .map_err(|e| tracing::error!("Failed to load webhook {id}: {e}"))
+                .ok()
+        })
+        .collect())
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    kind: EventKind,
+    time: DateTime<Utc>,
+    event: &'a NetworkEvent,
+}
+
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key length");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// An event waiting to be sent to one endpoint.
+struct Job {
+    webhook: Webhook,
+    kind: EventKind,
+    body: String,
+}
+
+async fn deliver(
+    pool: &Pool<Sqlite>,
+    client: &reqwest::Client,
+    config: &WebhookConfig,
+    webhook_id: u32,
+    Job {
+        webhook,
+        kind,
+        body,
+    }: Job,
+) -> ApiResult<()> {
+    let signature = sign(&webhook.secret, body.as_bytes());
+    let delivery_id = sqlx::query_scalar::<_, u32>(
+        "
+        insert into webhook_deliveries (webhook_id, event, payload, attempts, delivered, time) values ($1, $2, $3, 0, 0, $4);
+        select last_insert_rowid() as id;
+        ",
+    )
+    .bind(webhook_id)
+    .bind(json::to_string(&kind)?)
+    .bind(&body)
+    .bind(Utc::now())
+    .fetch_one(pool)
+    .await?;
+
+    let mut backoff = Duration::from_millis(config.initial_backoff_ms);
+    for attempt in 1..=config.max_attempts.max(1) {
+        let result = client
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, format!("sha256={signature}"))
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .body(body.clone())
+            .send()
+            .await;
+
+        let (status, error, delivered) = match result {
+            Ok(response) => {
+                let status = response.status();
+                let error = (!status.is_success()).then(|| status.to_string());
+                (Some(status.as_u16()), error, status.is_success())
+            }
+            Err(e) => (None, Some(e.to_string()), false),
+        };
+
+        sqlx::query(
+            "
+            update webhook_deliveries
+            set attempts = $1, status = $2, error = $3, delivered = $4
+            where id = $5;
+        ",
+        )
+        .bind(attempt)
+        .bind(status)
+        .bind(error)
+        .bind(delivered)
+        .bind(delivery_id)
+        .execute(pool)
+        .await?;
+
+        if delivered {
+            return Ok(());
+        }
+        if attempt < config.max_attempts {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+    tracing::warn!(
+        "Giving up delivering webhook {webhook_id} to {}",
+        webhook.url
+    );
+    Ok(())
+}
+
+/// Sends the jobs queued for one endpoint in order, so a slow or unreachable
+/// endpoint only holds up its own deliveries.
+fn spawn_worker(
+    pool: Pool<Sqlite>,
+    client: reqwest::Client,
+    config: WebhookConfig,
+    webhook_id: u32,
+) -> mpsc::Sender<Job> {
+    let (tx, mut rx) = mpsc::channel(config.queue_size.max(1));
+    tokio::spawn(async move {
+        while let Some(job) = rx.recv().await {
+            if let Err(e) = deliver(&pool, &client, &config, webhook_id, job).await {
+                tracing::error!("Failed to deliver webhook {webhook_id}: {e}");
+            }
+        }
+    });
+    tx
+}
+
+/// Forwards broadcast events to every endpoint subscribed to their kind.
+pub async fn run(
+    mut rx: Receiver<NetworkEvent>,
+    pool: Pool<Sqlite>,
+    webhooks: WebhookSet,
+    config: WebhookConfig,
+) {
+    let client = reqwest::Client::new();
+    let mut queues = HashMap::<u32, mpsc::Sender<Job>>::new();
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Closed) => break,
+            Err(RecvError::Lagged(n)) => {
+                tracing::warn!("Webhooks lagged behind, skipped {n} events");
+                continue;
+            }
+        };
+        let Some(kind) = EventKind::of(&event) else {
+            continue;
+        };
+
+        let body = match json::to_string(&Payload {
+            kind,
+            time: Utc::now(),
+            event: &event,
+        }) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!("Failed to serialize {kind:?} event for webhooks: {e}");
+                continue;
+            }
+        };
+        let webhooks = webhooks.read().await;
+        // Dropping the sender of a deleted endpoint stops its worker once the
+        // queued deliveries are sent.
+        queues.retain(|id, _| webhooks.iter().any(|webhook| webhook.id == Some(*id)));
+        for webhook in webhooks.iter() {
+            let Some(id) = webhook.id else {
+                continue;
+            };
+            if !webhook.enabled || !webhook.events.contains(&kind) || !webhook.filter.matches(&event)
+            {
+                continue;
+            }
+            let queue = queues
+                .entry(id)
+                .or_insert_with(|| spawn_worker(pool.clone(), client.clone(), config.clone(), id));
+            let job = Job {
+                webhook: webhook.clone(),
+                kind,
+                body: body.clone(),
+            };
+            match queue.try_send(job) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    tracing::warn!(
+                        "Delivery queue of webhook {id} is full, dropped a {kind:?} event"
+                    );
+                }
+                Err(TrySendError::Closed(_)) => {
+                    tracing::error!("Delivery worker of webhook {id} stopped");
+                    queues.remove(&id);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, FromRow)]
+struct Delivery {
+    id: u32,
+    webhook_id: u32,
+    event: String,
+    payload: String,
+    attempts: u32,
+    status: Option<u16>,
+    error: Option<String>,
+    delivered: bool,
+    time: DateTime<Utc>,
+}
+
+#[get("/webhooks")]
+async fn webhooks_page() -> Template {
+    Template::render("webhooks", ())
+}
+
+#[post("/webhooks")]
+async fn list_webhooks(webhooks: &State<WebhookSet>) -> Json<Vec<Webhook>> {
+    Json(webhooks.read().await.clone())
+}
+
+#[post("/webhooks/save", data = "<webhook>")]
+async fn save_webhook(
+    mut db: Connection<Db>,
+    webhooks: &State<WebhookSet>,
+    webhook: Json<Webhook>,
+) -> ApiResult<Json<u32>> {
+    let events = json::to_string(&webhook.events)?;
+    let filter = json::to_string(&webhook.filter)?;
+    let id = match webhook.id {
+        Some(id) => {
+            let result = sqlx::query(
+                "
+                update webhooks
+                set url = $1, secret = case when $2 = '' then secret else $2 end,
+                    events = $3, filter = $4, enabled = $5
+                where id = $6;
+            ",
+            )
+            .bind(&webhook.url)
+            .bind(&webhook.secret)
+            .bind(events)
+            .bind(filter)
+            .bind(webhook.enabled)
+            .bind(id)
+            .execute(&mut *db)
+            .await?;
+            if result.rows_affected() == 0 {
+                return Err(ApiError::not_found(format!("webhook {id}")));
+            }
+            id
+        }
+        None => {
+            // Deliveries are signed with the secret, an empty one would sign
+            // them with a key anyone can guess.
+            if webhook.secret.trim().is_empty() {
+                return Err(ApiError::BadRequest(
+                    "a new webhook needs a secret".to_string(),
+                ));
+            }
+            sqlx::query_scalar::<_, u32>(
+                "
+                insert into webhooks (url, secret, events, filter, enabled) values ($1, $2, $3, $4, $5);
+                select last_insert_rowid() as id;
+                ",
+            )
+            .bind(&webhook.url)
+            .bind(&webhook.secret)
+            .bind(events)
+            .bind(filter)
+            .bind(webhook.enabled)
+            .fetch_one(&mut *db)
+            .await?
+        }
+    };
+
+    *webhooks.write().await = load_webhooks(&mut *db).await?;
+    Ok(Json(id))
+}
+
+#[post("/webhooks/delete?<id>")]
+async fn delete_webhook(
+    mut db: Connection<Db>,
+    webhooks: &State<WebhookSet>,
+    id: u32,
+) -> ApiResult<()> {
+    sqlx::query("delete from webhooks where id = ?;")
+        .bind(id)
+        .execute(&mut *db)
+        .await?;
+
+    *webhooks.write().await = load_webhooks(&mut *db).await?;
+    Ok(())
+}
+
+#[post("/webhook_deliveries?<webhook_id>")]
+async fn webhook_deliveries(
+    mut db: Connection<Db>,
+    webhook_id: u32,
+) -> ApiResult<Json<Vec<Delivery>>> {
+    let deliveries = sqlx::query_as::<_, Delivery>(
+        "
+        select *
+        from webhook_deliveries
+        where webhook_id = ?
+        order by id desc
+        limit 100;
+    ",
+    )
+    .bind(webhook_id)
+    .fetch_all(&mut *db)
+    .await?;
+
+    Ok(Json(deliveries))
+}
+
+pub fn page_routes() -> Vec<Route> {
+    routes![webhooks_page]
+}
+
+pub fn api_routes() -> Vec<Route> {
+    routes![
+        list_webhooks,
+        save_webhook,
+        delete_webhook,
+        webhook_deliveries
+    ]
+}
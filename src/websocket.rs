@@ -0,0 +1,514 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use chrono::{DateTime, Utc};
+use futures::{SinkExt, StreamExt};
+use rocket::serde::json;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{
+        broadcast::{self, error::RecvError, Receiver},
+        mpsc::Sender,
+        RwLock,
+    },
+};
+use tokio_tungstenite::{
+    tungstenite::{
+        self,
+        handshake::server::{ErrorResponse, Request as HandshakeRequest, Response},
+        http::StatusCode,
+        Message as WsMessage,
+    },
+    WebSocketStream,
+};
+
+use crate::{
+    events::{missed, EventFilter, EventId},
+    rules::Watchlist,
+    store::Store,
+    veloren::BotCommand,
+    NetworkEvent,
+};
+
+/// Longest chat message accepted from a panel client.
+const MAX_CHAT_LEN: usize = 256;
+
+/// WebSocket server settings, read from the `websocket` table in
+/// `Rocket.toml`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WebSocketConfig {
+    pub enabled: bool,
+    pub address: String,
+    pub port: u16,
+    /// Clients have to `authenticate` with this token before sending chat or
+    /// acknowledging alerts. Those commands are refused while it is empty.
+    pub token: String,
+    /// Origins browsers may connect from. Clients sending no `Origin`, which
+    /// is anything but a browser, are always accepted.
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: "127.0.0.1".to_string(),
+            port: 8001,
+            token: String::new(),
+            allowed_origins: vec![
+                "http://127.0.0.1:8000".to_string(),
+                "http://localhost:8000".to_string(),
+            ],
+        }
+    }
+}
+
+/// A command sent by a client, `id` is echoed back in the reply.
+#[derive(Deserialize)]
+struct Request {
+    id: Option<u32>,
+    #[serde(flatten)]
+    command: Command,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Command {
+    /// Starts receiving events matching `filter`, replacing any previous
+    /// subscription.
+    Subscribe {
+        #[serde(default)]
+        filter: EventFilter,
+        last_event_id: Option<String>,
+    },
+    Unsubscribe,
+    /// Allows the commands below that act on the game or on other clients.
+    Authenticate {
+        token: String,
+    },
+    /// Sends a world message through the bot.
+    SendChat {
+        message: String,
+    },
+    /// Marks an alert as handled for every connected client.
+    AckAlert {
+        player_id: u32,
+        message_id: Option<u32>,
+        time: DateTime<Utc>,
+    },
+    /// Tells other clients who is connected and what they are looking at.
+    Presence {
+        name: String,
+        page: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Presence {
+    name: String,
+    page: Option<String>,
+    since: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Event {
+        id: String,
+        event: NetworkEvent,
+    },
+    Presence {
+        clients: Vec<Presence>,
+    },
+    AlertAck {
+        player_id: u32,
+        message_id: Option<u32>,
+        time: DateTime<Utc>,
+        /// Presence name of the client that acknowledged it, if it set one.
+        by: Option<String>,
+    },
+    Done {
+        id: Option<u32>,
+    },
+    Error {
+        id: Option<u32>,
+        message: String,
+    },
+}
+
+/// State shared by all connections.
+struct Hub {
+    events: Receiver<NetworkEvent>,
+    store: Store,
+    watchlist: Watchlist,
+    bot: Sender<BotCommand>,
+    /// Presence updates and alert acknowledgements, relayed to every client.
+    panel: broadcast::Sender<Reply>,
+    presence: RwLock<HashMap<u64, Presence>>,
+    next_client: AtomicU64,
+    token: String,
+    allowed_origins: Vec<String>,
+}
+
+impl Hub {
+    async fn broadcast_presence(&self) {
+        let mut clients = self
+            .presence
+            .read()
+            .await
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        clients.sort_by_key(|presence| presence.since);
+        let _ = self.panel.send(Reply::Presence { clients });
+    }
+}
+
+struct Subscription {
+    rx: Receiver<NetworkEvent>,
+    filter: EventFilter,
+    position: EventId,
+    /// Set while there are stored events left to catch up on.
+    replay: bool,
+}
+
+async fn next_event(subscription: &mut Option<Subscription>) -> Result<NetworkEvent, RecvError> {
+    match subscription {
+        Some(subscription) => subscription.rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+type Socket = WebSocketStream<TcpStream>;
+
+async fn send(socket: &mut Socket, reply: &Reply) -> tungstenite::Result<()> {
+    let text = json::to_string(reply).expect("replies serialize");
+    socket.send(WsMessage::Text(text)).await
+}
+
+struct Connection {
+    hub: Arc<Hub>,
+    client: u64,
+    subscription: Option<Subscription>,
+    /// Set once the client sent the configured token.
+    authenticated: bool,
+}
+
+/// Compares tokens in time independent of where they differ.
+fn same_token(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+impl Connection {
+    /// Runs `command` and returns the reply to send back.
+    async fn handle(&mut self, id: Option<u32>, command: Command) -> Reply {
+        let error = |message: String| Reply::Error { id, message };
+        match command {
+            Command::Subscribe {
+                filter,
+                last_event_id,
+            } => {
+                let position = match last_event_id.as_deref().map(str::parse::<EventId>) {
+                    Some(Ok(position)) => position,
+                    Some(Err(_)) => return error("invalid last_event_id".to_string()),
+                    None => match self.hub.store.latest_ids().await {
+                        Ok((message, activity)) => EventId { message, activity },
+                        Err(e) => return error(e.to_string()),
+                    },
+                };
+                self.subscription = Some(Subscription {
+                    rx: self.hub.events.resubscribe(),
+                    filter,
+                    position,
+                    replay: true,
+                });
+            }
+            Command::Unsubscribe => self.subscription = None,
+            Command::Authenticate { token } => {
+                if self.hub.token.is_empty() || !same_token(&token, &self.hub.token) {
+                    return error("invalid token".to_string());
+                }
+                self.authenticated = true;
+            }
+            Command::SendChat { .. } | Command::AckAlert { .. } if !self.authenticated => {
+                return error(
+                    "authenticate before sending chat or acknowledging alerts".to_string(),
+                );
+            }
+            Command::SendChat { message } => {
+                let message = message.replace(['\r', '\n'], " ").trim().to_string();
+                if message.is_empty() || message.len() > MAX_CHAT_LEN {
+                    return error(format!(
+                        "message must be between 1 and {MAX_CHAT_LEN} bytes"
+                    ));
+                }
+                if self
+                    .hub
+                    .bot
+                    .send(BotCommand::World { message })
+                    .await
+                    .is_err()
+                {
+                    return error("the bot is not running".to_string());
+                }
+            }
+            Command::AckAlert {
+                player_id,
+                message_id,
+                time,
+            } => {
+                let by = self
+                    .hub
+                    .presence
+                    .read()
+                    .await
+                    .get(&self.client)
+                    .map(|presence| presence.name.clone());
+                let _ = self.hub.panel.send(Reply::AlertAck {
+                    player_id,
+                    message_id,
+                    time,
+                    by,
+                });
+            }
+            Command::Presence { name, page } => {
+                self.hub
+                    .presence
+                    .write()
+                    .await
+                    .entry(self.client)
+                    .and_modify(|presence| {
+                        presence.name = name.clone();
+                        presence.page = page.clone();
+                    })
+                    .or_insert_with(|| Presence {
+                        name,
+                        page,
+                        since: Utc::now(),
+                    });
+                self.hub.broadcast_presence().await;
+            }
+        }
+        Reply::Done { id }
+    }
+
+    /// Sends the stored events the subscription missed, page by page.
+    async fn replay(&mut self, socket: &mut Socket) -> tungstenite::Result<()> {
+        let Some(subscription) = &mut self.subscription else {
+            return Ok(());
+        };
+        while subscription.replay {
+            let events = match missed(&self.hub.store, subscription.position).await {
+                Ok(events) => events,
+                Err(e) => {
+                    tracing::error!("Failed to replay events: {e}");
+                    subscription.replay = false;
+                    break;
+                }
+            };
+            subscription.replay = !events.is_empty();
+            for event in events {
+                if subscription.position.advance(&event)
+                    && subscription
+                        .filter
+                        .matches(&event, &self.hub.watchlist)
+                        .await
+                {
+                    let id = subscription.position.to_string();
+                    send(socket, &Reply::Event { id, event }).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn run(&mut self, socket: &mut Socket) -> tungstenite::Result<()> {
+        let mut panel = self.hub.panel.subscribe();
+        self.hub.broadcast_presence().await;
+
+        loop {
+            self.replay(socket).await?;
+
+            tokio::select! {
+                message = socket.next() => match message.transpose()? {
+                    Some(WsMessage::Text(text)) => {
+                        let reply = match json::from_str::<Request>(&text) {
+                            Ok(request) => self.handle(request.id, request.command).await,
+                            Err(e) => Reply::Error {
+                                id: None,
+                                message: e.to_string(),
+                            },
+                        };
+                        send(socket, &reply).await?;
+                    }
+                    Some(WsMessage::Close(_)) | None => return Ok(()),
+                    Some(_) => {}
+                },
+                event = next_event(&mut self.subscription) => {
+                    let Some(subscription) = &mut self.subscription else {
+                        continue;
+                    };
+                    match event {
+                        Ok(event) => {
+                            if subscription.position.advance(&event)
+                                && subscription
+                                    .filter
+                                    .matches(&event, &self.hub.watchlist)
+                                    .await
+                            {
+                                let id = subscription.position.to_string();
+                                send(socket, &Reply::Event { id, event }).await?;
+                            }
+                        }
+                        Err(RecvError::Lagged(_)) => subscription.replay = true,
+                        Err(RecvError::Closed) => return Ok(()),
+                    }
+                },
+                reply = panel.recv() => match reply {
+                    Ok(reply) => send(socket, &reply).await?,
+                    Err(RecvError::Lagged(n)) => {
+                        tracing::warn!("WebSocket client {} skipped {n} panel updates", self.client);
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+            }
+        }
+    }
+}
+
+/// Turns away browsers connecting from a page outside `allowed_origins`.
+fn check_origin(
+    allowed_origins: &[String],
+    request: &HandshakeRequest,
+    response: Response,
+) -> Result<Response, ErrorResponse> {
+    match request.headers().get("origin") {
+        Some(origin)
+            if !allowed_origins
+                .iter()
+                .any(|allowed| allowed.as_bytes() == origin.as_bytes()) =>
+        {
+            let mut response = ErrorResponse::new(Some("origin not allowed".to_string()));
+            *response.status_mut() = StatusCode::FORBIDDEN;
+            Err(response)
+        }
+        _ => Ok(response),
+    }
+}
+
+async fn connection(hub: Arc<Hub>, stream: TcpStream, addr: SocketAddr) {
+    let handshake =
+        tokio_tungstenite::accept_hdr_async(stream, |request: &HandshakeRequest, response| {
+            check_origin(&hub.allowed_origins, request, response)
+        });
+    let mut socket = match handshake.await {
+        Ok(socket) => socket,
+        Err(e) => {
+            tracing::warn!("WebSocket handshake with {addr} failed: {e}");
+            return;
+        }
+    };
+    let client = hub.next_client.fetch_add(1, Ordering::Relaxed);
+    let mut connection = Connection {
+        hub: hub.clone(),
+        client,
+        subscription: None,
+        authenticated: false,
+    };
+    if let Err(e) = connection.run(&mut socket).await {
+        tracing::debug!("WebSocket client {addr} disconnected: {e}");
+    }
+
+    if hub.presence.write().await.remove(&client).is_some() {
+        hub.broadcast_presence().await;
+    }
+}
+
+/// Accepts WebSocket clients of the panel, which receive live events and send
+/// commands over the same connection.
+pub async fn run(
+    config: WebSocketConfig,
+    events: Receiver<NetworkEvent>,
+    store: Store,
+    watchlist: Watchlist,
+    bot: Sender<BotCommand>,
+) {
+    let listener = match TcpListener::bind((config.address.as_str(), config.port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!(
+                "Failed to bind the WebSocket server to {}:{}: {e}",
+                config.address,
+                config.port
+            );
+            return;
+        }
+    };
+    tracing::info!(
+        "WebSocket server listening on {}:{}",
+        config.address,
+        config.port
+    );
+
+    let hub = Arc::new(Hub {
+        events,
+        store,
+        watchlist,
+        bot,
+        panel: broadcast::channel(64).0,
+        presence: RwLock::default(),
+        next_client: AtomicU64::new(0),
+        token: config.token,
+        allowed_origins: config.allowed_origins,
+    });
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                tokio::task::spawn(connection(hub.clone(), stream, addr));
+            }
+            Err(e) => tracing::warn!("Failed to accept a WebSocket connection: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(origin: Option<&str>) -> Result<Response, ErrorResponse> {
+        let mut request = HandshakeRequest::builder();
+        if let Some(origin) = origin {
+            request = request.header("origin", origin);
+        }
+        let allowed = WebSocketConfig::default().allowed_origins;
+        check_origin(&allowed, &request.body(()).unwrap(), Response::new(()))
+    }
+
+    #[test]
+    fn only_allowed_origins_connect() {
+        assert!(handshake(None).is_ok());
+        assert!(handshake(Some("http://localhost:8000")).is_ok());
+        let refused = handshake(Some("https://example.com")).unwrap_err();
+        assert_eq!(refused.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn tokens_have_to_match() {
+        assert!(same_token("secret", "secret"));
+        assert!(!same_token("secreT", "secret"));
+        assert!(!same_token("secret2", "secret"));
+    }
+}
@@ -3,54 +3,42 @@ let message_template = document.getElementById("message");
 let messages_div = document.getElementById("messages");
 
 var newest_message = null;
+// Cursors continuing the loaded part of the log towards older and newer messages.
+var older_cursor = null;
+var newer_cursor = null;
+
+// Fetches a page of messages, `query` holds a cursor or a starting point and
+// any filters.
+function list_messages(query) {
+  return fetch("/api/messages", {
+    method: "POST",
+    body: JSON.stringify(query),
+  }).then(res => res.json());
+}
 
 messages_div.onscroll = function () {
   if (messages_div.scrollTop == 0) {
-    var element = messages_div.children[1];
-    if (element == null) {
-      fetch("/api/messages_before", {
-        method: "POST",
-      }).then(res => {
-        res.json().then(res => {
-          res.forEach(add_message_back);
-        });
-      });
-    } else {
-      let id = element.id.substring(4);
-      fetch("/api/messages_before?id=" + id, {
-        method: "POST",
-      }).then(res => {
-        res.json().then(res => {
-          res.forEach(add_message_back);
-        });
+    if (older_cursor != null) {
+      let cursor = older_cursor;
+      older_cursor = null;
+      list_messages({ cursor: cursor }).then(res => {
+        older_cursor = res.next;
+        res.messages.forEach(add_message_back);
       });
     }
   } else if (messages_div.scrollTop == messages_div.scrollHeight - messages_div.offsetHeight) {
     var element = messages_div.children[messages_div.children.length - 1];
-    if (element == null) {
-      fetch("/api/messages_after", {
-        method: "POST",
-      }).then(res => {
-        res.json().then(res => {
-          res.forEach(add_message_front);
-        });
+    if (newer_cursor != null && (element == null || element.id.substring(4) != newest_message)) {
+      let cursor = newer_cursor;
+      newer_cursor = null;
+      list_messages({ cursor: cursor }).then(res => {
+        newer_cursor = res.next;
+        res.messages.forEach(add_message_front);
       });
-    } else {
-      let id = element.id.substring(4);
-      if (id != newest_message) {
-        fetch("/api/messages_after?id=" + id, {
-          method: "POST",
-        }).then(res => {
-          res.json().then(res => {
-            res.forEach(add_message_front);
-          });
-        });
-      }
     }
   } 
 };
 
-
 messages_div.style.width = window.localStorage.getItem("messages-size-width");
 messages_div.style.height = window.localStorage.getItem("messages-size-height");
 messages_div.scrollTop = (messages_div.scrollHeight - messages_div.clientHeight);
@@ -65,15 +53,13 @@ observer.observe(messages_div);
 dragElement(document.getElementById("message-box"));
 
 function load_recent() {
-  fetch("/api/messages_before", {
-    method: "POST",
-  }).then(res => {
-    res.json().then(res => {
-      if (res.length > 0) {
-        newest_message = res[0].id;
-        res.forEach(add_message_back);
-      }
-    });
+  list_messages({ direction: "older" }).then(res => {
+    older_cursor = res.next;
+    newer_cursor = res.prev;
+    if (res.messages.length > 0) {
+      newest_message = res.messages[0].id;
+      res.messages.forEach(add_message_back);
+    }
   });
 }
 
@@ -161,6 +147,10 @@ function get_player_alias(id) {
         method: "POST",
         body: id,
       }).then(res => {
+        if (!res.ok) {
+          res.json().then(error => reject(error.message)).catch(reject);
+          return;
+        }
         res.text().then(res => {
           window.sessionStorage.setItem(storage_id, res);
           resolve(res);
@@ -214,41 +204,46 @@ document.addEventListener("click", function (ev) {
   if (target.classList.contains("name")) {
     window.location.href = '/user/' + target.id.substring("player-".length);
   } else if (target.classList.contains("goto")) {
-    if (selected != null) {
-      messages_div.querySelector('#' + selected).classList.remove('selected');
-    }
-
     let elem_id = target.parentElement.id;
     let element = messages_div.querySelector('#' + elem_id);
     if (element == null) {
-      clear_chat();
-      let id = elem_id.substring(4);
-      fetch("/api/messages_before?id=" + (+id + 25), {
-        method: "POST",
-      }).then(res => {
-        res.json().then(res => {
-          res.forEach(add_message_back);
-          let element = messages_div.querySelector('#' + elem_id);
-          element.classList.add('selected');
-          element.scrollIntoView({
-              behavior: 'auto',
-              block: 'center',
-              inline: 'center'
-          });
-        });
-      });
+      load_context(elem_id.substring(4));
     } else {
-      element.classList.add('selected');
-      element.scrollIntoView({
-          behavior: 'auto',
-          block: 'center',
-          inline: 'center'
-      });
+      select_message(element);
     }
-    selected = elem_id;
   }
 });
 
+function select_message(element) {
+  let previous = selected == null ? null : messages_div.querySelector('#' + selected);
+  if (previous != null) {
+    previous.classList.remove('selected');
+  }
+  element.classList.add('selected');
+  element.scrollIntoView({
+      behavior: 'auto',
+      block: 'center',
+      inline: 'center'
+  });
+  selected = element.id;
+}
+
+// Replaces the chat with the messages around message `id` and highlights it.
+function load_context(id) {
+  fetch("/api/messages/" + id + "/context?before=25&after=25", {
+    method: "POST",
+  }).then(res => res.json()).then(res => {
+    if (res.messages == null) {
+      return;
+    }
+    clear_chat();
+    older_cursor = res.older;
+    newer_cursor = res.newer;
+    res.messages.forEach(add_message_front);
+    select_message(messages_div.querySelector('#msg-' + id));
+  });
+}
+
 document.getElementById('goto-bottom').onclick = function (ev) {
   let element = messages_div.children[messages_div.children.length - 1];
   if (element == null || element.id.substring(4) != newest_message) {
@@ -275,6 +270,8 @@ document.addEventListener('messagerecv', function (ev) {
   var element = messages_div.children[messages_div.children.length - 1];
   if (element == null || element.id.substring(4) == ev.detail.id - 1) {
     var is_at_bottom = (messages_div.scrollHeight - messages_div.clientHeight) - messages_div.scrollTop < 10;
+    // Caught up with the live stream, a page cursor would fetch this again.
+    newer_cursor = null;
     add_message_front(ev.detail);
     if (is_at_bottom) {
       messages_div.scrollTop = messages_div.scrollHeight - messages_div.clientHeight;
@@ -282,14 +279,32 @@ document.addEventListener('messagerecv', function (ev) {
   }
 });
 
+document.addEventListener('alertrecv', function (ev) {
+  let alert = ev.detail;
+  if (alert.message_id != null) {
+    let element = messages_div.querySelector('#msg-' + alert.message_id);
+    if (element != null) {
+      element.classList.add('alert');
+      if (alert.kind.Spam != null) {
+        element.title = 'Spam: ' + alert.kind.Spam.reasons.join(', ');
+      }
+    }
+  }
+});
+
 // Subscribe to the event source at `uri` with exponential backoff reconnect.
+// Reconnects resume after the last event seen, so nothing is missed.
 function subscribe(uri) {
   var retryTime = 1;
+  var last_event_id = null;
 
   function connect(uri) {
-    const events = new EventSource(uri);
+    const events = new EventSource(last_event_id == null ? uri : uri + "?last_event_id=" + last_event_id);
 
     events.addEventListener("message", (ev) => {
+      if (ev.lastEventId) {
+        last_event_id = ev.lastEventId;
+      }
       const msg = JSON.parse(ev.data);
       if (msg.Activity != null) {
         var evt = new CustomEvent('activityrecv', {
@@ -301,7 +316,29 @@ function subscribe(uri) {
           detail: msg.Message,
         });
       }
-      document.dispatchEvent(evt);
+      if (msg.Alert != null) {
+        var evt = new CustomEvent('alertrecv', {
+          detail: msg.Alert,
+        });
+      }
+      if (msg.RuleFiring != null) {
+        var evt = new CustomEvent('rulefiringrecv', {
+          detail: msg.RuleFiring,
+        });
+      }
+      if (msg.Ticket != null) {
+        var evt = new CustomEvent('ticketrecv', {
+          detail: msg.Ticket,
+        });
+      }
+      if (msg.Connection != null) {
+        var evt = new CustomEvent('connectionrecv', {
+          detail: msg.Connection,
+        });
+      }
+      if (evt != null) {
+        document.dispatchEvent(evt);
+      }
     });
 
     events.addEventListener("open", () => {
@@ -338,6 +375,12 @@ document.getElementById('toggle-time').onclick = function (ev) {
   }
 };
 
-load_recent();
+// Permalink pages open the chat at their message.
+const permalink = document.getElementById("permalink");
+if (permalink != null) {
+  load_context(permalink.dataset.id);
+} else {
+  load_recent();
+}
 
 subscribe("/api/events");
\ No newline at end of file
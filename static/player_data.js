@@ -0,0 +1,89 @@
+let notes_div = document.getElementById("player-notes");
+let note_template = document.getElementById("note-template");
+const player_id = notes_div.dataset.id;
+
+function moderator_name() {
+  let name = window.localStorage.getItem("moderator-name");
+  if (name == null || name.length == 0) {
+    name = window.prompt("Moderator name");
+    if (name != null) {
+      window.localStorage.setItem("moderator-name", name);
+    }
+  }
+  return name;
+}
+
+function add_note(note) {
+  var node = note_template.content.cloneNode(true);
+  node.querySelector(".time-log").textContent = new Date(note.time).toLocaleString();
+  node.querySelector(".author").textContent = note.author;
+  node.querySelector(".text").textContent = note.content;
+  notes_div.appendChild(node);
+}
+
+fetch("/api/player_notes?id=" + player_id, {
+  method: "POST",
+}).then(res => res.json()).then(notes => {
+  notes.forEach(add_note);
+});
+
+document.getElementById("note-send").onclick = function () {
+  let content = document.getElementById("note-content");
+  let author = moderator_name();
+  if (author == null || content.value.length == 0) {
+    return;
+  }
+  fetch("/api/player_notes/add", {
+    method: "POST",
+    body: JSON.stringify({
+      player_id: parseInt(player_id),
+      author: author,
+      content: content.value,
+    }),
+  }).then(res => res.json()).then(note => {
+    content.value = "";
+    add_note(note);
+  });
+};
+
+function erase_player(mode, confirmation) {
+  let operator = moderator_name();
+  if (operator == null || !window.confirm(confirmation)) {
+    return;
+  }
+  let reason = window.prompt("Reason");
+  if (reason == null) {
+    return;
+  }
+  fetch("/api/players/" + player_id + "/erase?mode=" + mode
+    + "&operator=" + encodeURIComponent(operator)
+    + "&reason=" + encodeURIComponent(reason), {
+    method: "POST",
+  }).then(res => {
+    if (res.ok) {
+      window.location.href = mode == "Delete" ? "/" : window.location.href;
+    }
+  });
+}
+
+document.getElementById("erase-pseudonymize").onclick = function () {
+  erase_player("Pseudonymize", "Replace this player's identity with an anonymous id?");
+};
+
+document.getElementById("erase-delete").onclick = function () {
+  erase_player("Delete", "Permanently delete everything stored about this player?");
+};
+
+let legal_hold = document.getElementById("legal-hold");
+
+fetch("/api/players/" + player_id + "/legal_hold", {
+  method: "POST",
+}).then(res => res.json()).then(hold => {
+  legal_hold.checked = hold;
+});
+
+legal_hold.onchange = function () {
+  fetch("/api/players/" + player_id + "/legal_hold?hold=" + legal_hold.checked, {
+    method: "POST",
+  });
+};
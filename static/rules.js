@@ -0,0 +1,109 @@
+let rule_list = document.getElementById("rule-list");
+let rule_template = document.getElementById("rule-template");
+let firing_list = document.getElementById("rule-firings");
+let firing_template = document.getElementById("rule-firing-template");
+
+var rule_names = {};
+
+function add_rule(rule) {
+  var node = rule_template.content.cloneNode(true);
+  let element = node.querySelector(".rule");
+  element.querySelector(".rule-name").value = rule.name;
+  element.querySelector(".rule-enabled").checked = rule.enabled;
+  element.querySelector(".rule-dry-run").checked = rule.dry_run;
+  element.querySelector(".rule-conditions").value = JSON.stringify(rule.conditions, null, 2);
+  element.querySelector(".rule-actions").value = JSON.stringify(rule.actions, null, 2);
+
+  let status = element.querySelector(".rule-status");
+  element.querySelector(".rule-save").onclick = function () {
+    var body;
+    try {
+      body = JSON.stringify({
+        id: rule.id,
+        name: element.querySelector(".rule-name").value,
+        enabled: element.querySelector(".rule-enabled").checked,
+        dry_run: element.querySelector(".rule-dry-run").checked,
+        conditions: JSON.parse(element.querySelector(".rule-conditions").value),
+        actions: JSON.parse(element.querySelector(".rule-actions").value),
+      });
+    } catch (e) {
+      status.textContent = "Invalid JSON: " + e.message;
+      return;
+    }
+    fetch("/api/rules/save", {
+      method: "POST",
+      body: body,
+    }).then(res => {
+      if (res.ok) {
+        res.json().then(id => {
+          rule.id = id;
+          status.textContent = "Saved";
+        });
+      } else {
+        status.textContent = "Failed to save rule";
+      }
+    });
+  };
+  element.querySelector(".rule-delete").onclick = function () {
+    if (rule.id == null) {
+      element.remove();
+      return;
+    }
+    fetch("/api/rules/delete?id=" + rule.id, {
+      method: "POST",
+    }).then(res => {
+      if (res.ok) {
+        element.remove();
+      }
+    });
+  };
+
+  rule_list.appendChild(node);
+}
+
+function add_firing(firing) {
+  var node = firing_template.content.cloneNode(true);
+  let element = node.querySelector(".message");
+  element.querySelector(".time-log").textContent = new Date(firing.time).toLocaleString();
+  element.querySelector(".name").id = "player-" + firing.player_id;
+  get_player_alias(firing.player_id).then(res => {
+    element.querySelector(".name").textContent = res;
+  });
+  let name = rule_names[firing.rule_id] || ("#" + firing.rule_id);
+  element.querySelector(".text").textContent =
+    (firing.dry_run ? "[dry run] " : "") + name + ": " + firing.action.kind;
+  firing_list.insertBefore(node, firing_list.children[1]);
+}
+
+fetch("/api/rules", {
+  method: "POST",
+}).then(res => {
+  res.json().then(res => {
+    res.forEach(rule => {
+      rule_names[rule.id] = rule.name;
+      add_rule(rule);
+    });
+    fetch("/api/rule_firings", {
+      method: "POST",
+    }).then(res => {
+      res.json().then(res => {
+        res.reverse().forEach(add_firing);
+      });
+    });
+  });
+});
+
+document.getElementById("new-rule").onclick = function () {
+  add_rule({
+    id: null,
+    name: "",
+    enabled: true,
+    dry_run: true,
+    conditions: [{ kind: "contains", text: "" }],
+    actions: [{ kind: "alert" }],
+  });
+};
+
+document.addEventListener("rulefiringrecv", function (ev) {
+  add_firing(ev.detail);
+});
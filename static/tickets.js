@@ -0,0 +1,152 @@
+function moderator_name() {
+  let name = window.localStorage.getItem("moderator-name");
+  if (name == null || name.length == 0) {
+    name = window.prompt("Moderator name");
+    if (name != null) {
+      window.localStorage.setItem("moderator-name", name);
+    }
+  }
+  return name;
+}
+
+function claim_ticket(id) {
+  let name = moderator_name();
+  if (name == null) {
+    return Promise.reject();
+  }
+  return fetch("/api/tickets/" + id + "/claim?assignee=" + encodeURIComponent(name), {
+    method: "POST",
+  });
+}
+
+document.querySelectorAll(".time-log[data-time]").forEach(element => {
+  element.textContent = new Date(element.dataset.time).toLocaleString();
+});
+
+let ticket_list = document.getElementById("ticket-list");
+
+if (ticket_list != null) {
+  let ticket_template = document.getElementById("ticket-template");
+  let ticket_status = document.getElementById("ticket-status");
+  let moderator_input = document.getElementById("moderator-name");
+
+  moderator_input.value = window.localStorage.getItem("moderator-name");
+  moderator_input.onchange = function () {
+    window.localStorage.setItem("moderator-name", moderator_input.value);
+  };
+
+  function add_ticket(ticket) {
+    var node = ticket_template.content.cloneNode(true);
+    let element = node.querySelector(".ticket");
+    element.id = "ticket-" + ticket.id;
+    element.querySelector(".ticket-status").textContent = ticket.status;
+    element.querySelector(".ticket-title").textContent = "#" + ticket.id + " " + ticket.title;
+    element.querySelector(".ticket-title").href = "/ticket/" + ticket.id;
+    element.querySelector(".ticket-assignee").textContent = ticket.assignee || "";
+    element.querySelector(".time-log").textContent = new Date(ticket.updated).toLocaleString();
+    let claim = element.querySelector(".ticket-claim");
+    claim.style.display = ticket.status == "Open" ? "inline" : "none";
+    claim.onclick = function () {
+      claim_ticket(ticket.id);
+    };
+
+    let existing = document.getElementById(element.id);
+    if (existing != null) {
+      existing.replaceWith(node);
+    } else {
+      ticket_list.insertBefore(node, ticket_list.children[1]);
+    }
+  }
+
+  function load_tickets() {
+    ticket_list.querySelectorAll(".ticket").forEach(element => element.remove());
+    let status = ticket_status.value;
+    fetch("/api/tickets" + (status.length > 0 ? "?status=" + status : ""), {
+      method: "POST",
+    }).then(res => {
+      res.json().then(res => {
+        res.reverse().forEach(add_ticket);
+      });
+    });
+  }
+
+  ticket_status.onchange = load_tickets;
+  load_tickets();
+
+  document.addEventListener("ticketrecv", function (ev) {
+    let ticket = ev.detail.ticket;
+    let status = ticket_status.value;
+    if (status.length == 0 ? (ticket.status == "Open" || ticket.status == "Claimed") : ticket.status == status) {
+      add_ticket(ticket);
+    } else {
+      let existing = document.getElementById("ticket-" + ticket.id);
+      if (existing != null) {
+        existing.remove();
+      }
+    }
+  });
+}
+
+let ticket_header = document.getElementById("ticket");
+
+if (ticket_header != null) {
+  const ticket_id = ticket_header.dataset.id;
+  let comment_list = document.getElementById("ticket-comments");
+  let comment_template = document.getElementById("comment-template");
+
+  document.querySelectorAll("#ticket-messages .name").forEach(element => {
+    get_player_alias(element.id.substring("player-".length)).then(res => {
+      element.textContent = res;
+    });
+  });
+
+  document.querySelectorAll(".ticket-set-status").forEach(button => {
+    button.onclick = function () {
+      fetch("/api/tickets/" + ticket_id + "/status?status=" + button.dataset.status, {
+        method: "POST",
+      }).then(() => window.location.reload());
+    };
+  });
+
+  document.getElementById("ticket-claim").onclick = function () {
+    claim_ticket(ticket_id).then(() => window.location.reload());
+  };
+
+  function add_comment(comment) {
+    var node = comment_template.content.cloneNode(true);
+    node.querySelector(".time-log").textContent = new Date(comment.time).toLocaleString();
+    node.querySelector(".author").textContent = comment.author;
+    node.querySelector(".text").textContent = comment.content;
+    comment_list.appendChild(node);
+  }
+
+  let comment_content = document.getElementById("comment-content");
+  document.getElementById("comment-send").onclick = function () {
+    let author = moderator_name();
+    if (author == null || comment_content.value.length == 0) {
+      return;
+    }
+    fetch("/api/tickets/" + ticket_id + "/comment", {
+      method: "POST",
+      body: JSON.stringify({
+        author: author,
+        content: comment_content.value,
+      }),
+    }).then(res => {
+      if (res.ok) {
+        comment_content.value = "";
+      }
+    });
+  };
+
+  document.addEventListener("ticketrecv", function (ev) {
+    if (ev.detail.ticket.id != ticket_id) {
+      return;
+    }
+    if (ev.detail.kind.Commented != null) {
+      add_comment(ev.detail.kind.Commented);
+    } else {
+      document.getElementById("ticket-status").textContent = ev.detail.ticket.status;
+    }
+  });
+}
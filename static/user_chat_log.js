@@ -6,21 +6,27 @@ let chat_log_div = document.getElementById("chat-log");
 let online_dot = document.getElementById("online-dot");
 
 const self_id = parseInt(window.location.href.substring(window.location.href.lastIndexOf('/') + 1));
-fetch("/api/query_messages", {
-    method: "POST",
-    body: JSON.stringify({
-        per_page: 100000,
-        page: 0,
-        player_id: self_id,
-    })
-}).then(res => {
-    res.json().then(res => {
-        res.forEach(msg => {
-            add_chat_log_back(msg);
-        });
-    });
+
+// Cursor to the next page of older messages, null once the start is reached.
+var chat_log_cursor = null;
+
+list_messages({ player_id: self_id, direction: "older" }).then(res => {
+  chat_log_cursor = res.next;
+  res.messages.forEach(add_chat_log_back);
+  chat_log_div.scrollTop = chat_log_div.scrollHeight - chat_log_div.clientHeight;
 });
 
+chat_log_div.onscroll = function () {
+  if (chat_log_div.scrollTop == 0 && chat_log_cursor != null) {
+    let cursor = chat_log_cursor;
+    chat_log_cursor = null;
+    list_messages({ cursor: cursor, player_id: self_id }).then(res => {
+      chat_log_cursor = res.next;
+      res.messages.forEach(add_chat_log_back);
+    });
+  }
+};
+
 function add_chat_log(msg, add_func) {
   var is_at_bottom = (chat_log_div.scrollHeight - chat_log_div.clientHeight) - chat_log_div.scrollTop < 10;
   
@@ -0,0 +1,126 @@
+const event_kinds = [
+  "Message",
+  "Join",
+  "Leave",
+  "Alert",
+  "Moderation",
+  "Report",
+  "Ticket",
+  "BotConnect",
+  "BotDisconnect",
+];
+
+let webhook_list = document.getElementById("webhook-list");
+let webhook_template = document.getElementById("webhook-template");
+
+function add_webhook(webhook) {
+  var node = webhook_template.content.cloneNode(true);
+  let element = node.querySelector(".webhook");
+  element.querySelector(".webhook-url").value = webhook.url;
+  if (webhook.id != null) {
+    element.querySelector(".webhook-secret").placeholder = "Secret (leave empty to keep)";
+  }
+  element.querySelector(".webhook-enabled").checked = webhook.enabled;
+  element.querySelector(".webhook-filter").value = JSON.stringify(webhook.filter, null, 2);
+
+  let events = element.querySelector(".webhook-events");
+  event_kinds.forEach(kind => {
+    let label = document.createElement("label");
+    let input = document.createElement("input");
+    input.type = "checkbox";
+    input.value = kind;
+    input.checked = webhook.events.includes(kind);
+    label.appendChild(input);
+    label.appendChild(document.createTextNode(" " + kind + " "));
+    events.appendChild(label);
+  });
+
+  let status = element.querySelector(".webhook-status");
+  element.querySelector(".webhook-save").onclick = function () {
+    var body;
+    try {
+      body = JSON.stringify({
+        id: webhook.id,
+        url: element.querySelector(".webhook-url").value,
+        secret: element.querySelector(".webhook-secret").value,
+        enabled: element.querySelector(".webhook-enabled").checked,
+        events: Array.from(events.querySelectorAll("input:checked")).map(input => input.value),
+        filter: JSON.parse(element.querySelector(".webhook-filter").value),
+      });
+    } catch (e) {
+      status.textContent = "Invalid JSON: " + e.message;
+      return;
+    }
+    fetch("/api/webhooks/save", {
+      method: "POST",
+      body: body,
+    }).then(res => {
+      if (res.ok) {
+        res.json().then(id => {
+          webhook.id = id;
+          status.textContent = "Saved";
+        });
+      } else {
+        status.textContent = "Failed to save webhook";
+      }
+    });
+  };
+  element.querySelector(".webhook-delete").onclick = function () {
+    if (webhook.id == null) {
+      element.remove();
+      return;
+    }
+    fetch("/api/webhooks/delete?id=" + webhook.id, {
+      method: "POST",
+    }).then(res => {
+      if (res.ok) {
+        element.remove();
+      }
+    });
+  };
+
+  let deliveries = element.querySelector(".webhook-deliveries");
+  element.querySelector(".webhook-show-deliveries").onclick = function () {
+    if (webhook.id == null) {
+      return;
+    }
+    fetch("/api/webhook_deliveries?webhook_id=" + webhook.id, {
+      method: "POST",
+    }).then(res => {
+      res.json().then(res => {
+        deliveries.innerHTML = "";
+        res.forEach(delivery => {
+          let line = document.createElement("div");
+          line.className = "message";
+          line.textContent = new Date(delivery.time).toLocaleString() + " " + delivery.event
+            + " attempts: " + delivery.attempts
+            + (delivery.delivered ? " delivered" : " failed")
+            + (delivery.status != null ? " (" + delivery.status + ")" : "")
+            + (delivery.error != null ? " " + delivery.error : "");
+          deliveries.appendChild(line);
+        });
+      });
+    });
+  };
+
+  webhook_list.appendChild(node);
+}
+
+fetch("/api/webhooks", {
+  method: "POST",
+}).then(res => {
+  res.json().then(res => {
+    res.forEach(add_webhook);
+  });
+});
+
+document.getElementById("new-webhook").onclick = function () {
+  add_webhook({
+    id: null,
+    url: "",
+    secret: "",
+    enabled: true,
+    events: ["Alert"],
+    filter: { types: [], player_ids: [], pattern: null },
+  });
+};